@@ -0,0 +1,84 @@
+use crate::interval::{Interval, EMPTY_INTERVAL};
+use crate::ray::Ray;
+use crate::vec3::Point3;
+
+// Axis-aligned bounding box.
+#[derive(Debug, Copy, Clone)]
+pub struct Aabb {
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
+}
+
+impl Aabb {
+    pub fn new(x: Interval, y: Interval, z: Interval) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn empty() -> Self {
+        Self::new(EMPTY_INTERVAL, EMPTY_INTERVAL, EMPTY_INTERVAL)
+    }
+
+    pub fn from_points(a: Point3, b: Point3) -> Self {
+        // The box with the two points as opposite corners.
+        Self::new(
+            Interval::new(a.x().min(b.x()), a.x().max(b.x())),
+            Interval::new(a.y().min(b.y()), a.y().max(b.y())),
+            Interval::new(a.z().min(b.z()), a.z().max(b.z())),
+        )
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self::new(
+            self.x.union(&other.x),
+            self.y.union(&other.y),
+            self.z.union(&other.z),
+        )
+    }
+
+    pub fn axis(&self, n: usize) -> Interval {
+        match n {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        if self.x.size() > self.y.size() && self.x.size() > self.z.size() {
+            0
+        } else if self.y.size() > self.z.size() {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        Point3::new(
+            (self.x.min + self.x.max) / 2.0,
+            (self.y.min + self.y.max) / 2.0,
+            (self.z.min + self.z.max) / 2.0,
+        )
+    }
+
+    pub fn hit(&self, r: &Ray, ray_t: Interval) -> bool {
+        // Narrows `ray_t` down to the slab of each axis in turn.
+        let origin = r.origin();
+        let direction = r.direction();
+        let (mut t_min, mut t_max) = (ray_t.min, ray_t.max);
+        for n in 0..3 {
+            let slab = self.axis(n);
+            let inverse = 1.0 / direction[n];
+            let t0 = (slab.min - origin[n]) * inverse;
+            let t1 = (slab.max - origin[n]) * inverse;
+            let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
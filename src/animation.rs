@@ -0,0 +1,577 @@
+// Keyframe animation of the camera and of objects, over time in seconds.
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::ray::Ray;
+use crate::utils::degrees_to_radians;
+use crate::vec3::{Point3, Vec3};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    CatmullRom, // Smooth curve through the keys
+    Bezier,     // Cubic Bezier curve shaped by the handles of the keys
+    Monotone,   // Smooth curve that does not overshoot the keys (Fritsch-Carlson)
+}
+
+// Values that are interpolated one component at a time.
+pub trait Channels: Copy {
+    fn channel(&self, n: usize) -> f64;
+    fn from_channels(channel: impl Fn(usize) -> f64) -> Self;
+}
+
+impl Channels for f64 {
+    fn channel(&self, _n: usize) -> f64 {
+        *self
+    }
+
+    fn from_channels(channel: impl Fn(usize) -> f64) -> Self {
+        channel(0)
+    }
+}
+
+impl Channels for Vec3 {
+    fn channel(&self, n: usize) -> f64 {
+        self[n]
+    }
+
+    fn from_channels(channel: impl Fn(usize) -> f64) -> Self {
+        Vec3::new(channel(0), channel(1), channel(2))
+    }
+}
+
+// Bezier handles before and after a key, as offsets from its value.
+pub type Handles<T> = [Option<T>; 2];
+
+#[derive(Debug, Clone)]
+pub struct Key<T> {
+    pub time: f64,
+    pub value: T,
+    pub interpolation: Interpolation, // Towards the next key
+    // A third of the way to the keys on either side in time, and Catmull-Rom's where they
+    // are left out.
+    pub handles: Handles<T>,
+}
+
+// Values of one parameter at keyed times. Before the first and after the last key it keeps
+// their values.
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    pub keys: Vec<Key<T>>, // Sorted by time
+}
+
+impl<T: Channels> Track<T> {
+    pub fn new() -> Self {
+        Self { keys: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn add(&mut self, key: Key<T>) {
+        // A key at the time of an existing one replaces it.
+        let index = self.keys.partition_point(|k| k.time < key.time);
+        if self.keys.get(index).is_some_and(|k| k.time == key.time) {
+            self.keys[index] = key;
+        } else {
+            self.keys.insert(index, key);
+        }
+    }
+
+    pub fn value(&self, time: f64) -> Option<T> {
+        // The value at `time`, None without keys.
+        if self.keys.is_empty() {
+            return None;
+        }
+        Some(T::from_channels(|n| self.channel_value(n, time)))
+    }
+
+    fn channel_value(&self, n: usize, time: f64) -> f64 {
+        let keys = &self.keys;
+        let last = keys.len() - 1;
+        if time <= keys[0].time {
+            return keys[0].value.channel(n);
+        }
+        if time >= keys[last].time {
+            return keys[last].value.channel(n);
+        }
+        let k = keys.partition_point(|key| key.time <= time) - 1;
+        let h = keys[k + 1].time - keys[k].time;
+        let s = (time - keys[k].time) / h;
+        let p0 = keys[k].value.channel(n);
+        let p1 = keys[k + 1].value.channel(n);
+        let interpolation = keys[k].interpolation;
+        if interpolation == Interpolation::Linear {
+            return p0 + (p1 - p0) * s;
+        }
+
+        // Cubic Hermite curve, the same as a Bezier curve with handles at a third of the
+        // tangents.
+        let m0 = self.tangent(n, k, interpolation) * h;
+        let m1 = self.tangent(n, k + 1, interpolation) * h;
+        let (m0, m1) = match interpolation {
+            // Handles a third of the way along give tangents of three times their offsets.
+            Interpolation::Bezier => (
+                keys[k].handles[1].map_or(m0, |after| 3.0 * after.channel(n)),
+                keys[k + 1].handles[0].map_or(m1, |before| -3.0 * before.channel(n)),
+            ),
+            _ => (m0, m1),
+        };
+        let (s2, s3) = (s * s, s * s * s);
+        (2.0 * s3 - 3.0 * s2 + 1.0) * p0
+            + (s3 - 2.0 * s2 + s) * m0
+            + (-2.0 * s3 + 3.0 * s2) * p1
+            + (s3 - s2) * m1
+    }
+
+    fn tangent(&self, n: usize, k: usize, interpolation: Interpolation) -> f64 {
+        // Rate of change of channel n at key k.
+        let keys = &self.keys;
+        let last = keys.len() - 1;
+        let slope = |a: usize, b: usize| {
+            (keys[b].value.channel(n) - keys[a].value.channel(n)) / (keys[b].time - keys[a].time)
+        };
+        match interpolation {
+            Interpolation::Linear => slope(k.min(last - 1), k.min(last - 1) + 1),
+            Interpolation::CatmullRom | Interpolation::Bezier if k == 0 => slope(0, 1),
+            Interpolation::CatmullRom | Interpolation::Bezier if k == last => slope(last - 1, last),
+            Interpolation::CatmullRom | Interpolation::Bezier => slope(k - 1, k + 1),
+            // Flat at the ends and at extremes, elsewhere limited to three times the
+            // slopes on either side, which keeps the curve between the keys.
+            Interpolation::Monotone if k == 0 || k == last => 0.0,
+            Interpolation::Monotone => {
+                let (before, after) = (slope(k - 1, k), slope(k, k + 1));
+                if before * after <= 0.0 {
+                    return 0.0;
+                }
+                let limit = 3.0 * before.abs().min(after.abs());
+                slope(k - 1, k + 1).clamp(-limit, limit)
+            }
+        }
+    }
+}
+
+// Frame rate of an animation and the part of each frame the shutter is open.
+#[derive(Debug, Copy, Clone)]
+pub struct Timing {
+    pub fps: f64,
+    pub shutter: f64, // Fraction of the frame time, 0.5 is a 180 degree shutter
+}
+
+impl Timing {
+    pub fn default() -> Self {
+        Self {
+            fps: 24.0,
+            shutter: 0.5,
+        }
+    }
+
+    pub fn frame_time(&self, frame: i32) -> f64 {
+        // Time at which frame number `frame`, counted from 1, starts.
+        (frame - 1) as f64 / self.fps
+    }
+}
+
+// Camera settings at one time.
+#[derive(Debug, Copy, Clone)]
+pub struct CameraPose {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vfov: f64,
+    pub focus_dist: f64,
+    pub defocus_angle: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct CameraAnimation {
+    pub lookfrom: Track<Point3>,
+    pub lookat: Track<Point3>,
+    pub vfov: Track<f64>,
+    pub focus_dist: Track<f64>,
+    pub defocus_angle: Track<f64>,
+}
+
+impl CameraAnimation {
+    pub fn new() -> Self {
+        Self {
+            lookfrom: Track::new(),
+            lookat: Track::new(),
+            vfov: Track::new(),
+            focus_dist: Track::new(),
+            defocus_angle: Track::new(),
+        }
+    }
+
+    pub fn pose(&self, still: CameraPose, time: f64) -> CameraPose {
+        // The pose at `time`, with the settings that are not animated taken from `still`.
+        CameraPose {
+            lookfrom: self.lookfrom.value(time).unwrap_or(still.lookfrom),
+            lookat: self.lookat.value(time).unwrap_or(still.lookat),
+            vfov: self.vfov.value(time).unwrap_or(still.vfov),
+            focus_dist: self.focus_dist.value(time).unwrap_or(still.focus_dist),
+            defocus_angle: self
+                .defocus_angle
+                .value(time)
+                .unwrap_or(still.defocus_angle),
+        }
+    }
+}
+
+// Transform of an object over time: scaled and rotated about its pivot, then moved.
+#[derive(Debug, Clone)]
+pub struct Motion {
+    pub translate: Track<Vec3>,
+    pub rotate: Track<Vec3>, // Angles in degrees about the x, then the y, then the z axis
+    pub scale: Track<f64>,
+}
+
+impl Motion {
+    pub fn new() -> Self {
+        Self {
+            translate: Track::new(),
+            rotate: Track::new(),
+            scale: Track::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.translate.is_empty() && self.rotate.is_empty() && self.scale.is_empty()
+    }
+}
+
+type Rotation = [Vec3; 3]; // Rows of a rotation matrix
+
+fn rotation(angles: Vec3) -> Rotation {
+    // Rz Ry Rx for angles in degrees.
+    let (sx, cx) = degrees_to_radians(angles.x()).sin_cos();
+    let (sy, cy) = degrees_to_radians(angles.y()).sin_cos();
+    let (sz, cz) = degrees_to_radians(angles.z()).sin_cos();
+    [
+        Vec3::new(cz * cy, cz * sy * sx - sz * cx, cz * sy * cx + sz * sx),
+        Vec3::new(sz * cy, sz * sy * sx + cz * cx, sz * sy * cx - cz * sx),
+        Vec3::new(-sy, cy * sx, cy * cx),
+    ]
+}
+
+fn rotate(m: &Rotation, v: Vec3) -> Vec3 {
+    Vec3::new(m[0].dot(&v), m[1].dot(&v), m[2].dot(&v))
+}
+
+fn rotate_back(m: &Rotation, v: Vec3) -> Vec3 {
+    // The inverse rotation, by the transpose.
+    m[0] * v.x() + m[1] * v.y() + m[2] * v.z()
+}
+
+// An object moving with its motion at the time of each ray, which blurs the motion over
+// the time the shutter is open.
+#[derive(Debug)]
+pub struct Animated {
+    pub object: Box<dyn Hittable>,
+    pub motion: Motion,
+    pub pivot: Point3, // Point the object is scaled and rotated about
+}
+
+impl Animated {
+    pub fn new(object: Box<dyn Hittable>, motion: Motion) -> Self {
+        // Pivots about the center of the object's box, or the origin without one.
+        let pivot = object
+            .bounding_box()
+            .map_or(Point3::zero(), |bbox| bbox.centroid());
+        Self {
+            object,
+            motion,
+            pivot,
+        }
+    }
+
+    fn placement(&self, time: f64) -> (Vec3, Rotation, f64) {
+        // Translation, rotation and scale at `time`.
+        let translate = self.motion.translate.value(time).unwrap_or(Vec3::zero());
+        let m = rotation(self.motion.rotate.value(time).unwrap_or(Vec3::zero()));
+        let scale = self.motion.scale.value(time).unwrap_or(1.0);
+        (translate, m, scale)
+    }
+
+    fn corners(&self, bbox: &Aabb, time: f64) -> [Point3; 8] {
+        // Corners of the object's box placed at `time`.
+        let (translate, m, scale) = self.placement(time);
+        std::array::from_fn(|n| {
+            let corner = Point3::new(
+                if n & 1 == 0 { bbox.x.min } else { bbox.x.max },
+                if n & 2 == 0 { bbox.y.min } else { bbox.y.max },
+                if n & 4 == 0 { bbox.z.min } else { bbox.z.max },
+            );
+            self.pivot + translate + rotate(&m, corner - self.pivot) * scale
+        })
+    }
+}
+
+// Steps the box of an animated object is sampled at between two keys.
+const BOX_STEPS: usize = 16;
+
+impl Hittable for Animated {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        // Intersects the object with the ray brought back to the object's own placement,
+        // which keeps the ray parameter t, then moves the hit along.
+        let time = r.time();
+        let (translate, m, scale) = self.placement(time);
+        let origin = self.pivot + rotate_back(&m, r.origin() - self.pivot - translate) / scale;
+        let direction = rotate_back(&m, r.direction()) / scale;
+        let mut moved = Ray::with_time(origin, direction, time);
+        moved.set_wavelengths(r.wavelengths().copied());
+
+        let mut rec = self.object.hit(&moved, ray_t)?;
+        rec.p = self.pivot + translate + rotate(&m, rec.p - self.pivot) * scale;
+        rec.normal = rotate(&m, rec.normal);
+        rec.dpdu = rotate(&m, rec.dpdu) * scale;
+        rec.dpdv = rotate(&m, rec.dpdv) * scale;
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // The object's box placed at every key and at steps between them. Between two steps
+        // a corner stays within the distance it moves of one of them, so padding by the
+        // longest move covers the curves and rotations that bulge out of the samples.
+        let bbox = self.object.bounding_box()?;
+        let mut keys: Vec<f64> = self
+            .motion
+            .translate
+            .keys
+            .iter()
+            .map(|k| k.time)
+            .chain(self.motion.rotate.keys.iter().map(|k| k.time))
+            .chain(self.motion.scale.keys.iter().map(|k| k.time))
+            .collect();
+        keys.sort_by(f64::total_cmp);
+        keys.dedup();
+        let mut times = vec![keys.first().copied().unwrap_or(0.0)];
+        for pair in keys.windows(2) {
+            let step = (pair[1] - pair[0]) / BOX_STEPS as f64;
+            times.extend((1..=BOX_STEPS).map(|i| pair[0] + step * i as f64));
+        }
+
+        let mut placed = Aabb::empty();
+        let mut pad: f64 = 0.0;
+        let mut previous: Option<[Point3; 8]> = None;
+        for time in times {
+            let corners = self.corners(&bbox, time);
+            for (n, corner) in corners.iter().enumerate() {
+                placed = placed.union(&Aabb::from_points(*corner, *corner));
+                if let Some(previous) = previous {
+                    pad = pad.max((*corner - previous[n]).length());
+                }
+            }
+            previous = Some(corners);
+        }
+        let grow = |i: Interval| Interval::new(i.min - pad, i.max + pad);
+        Some(Aabb::new(grow(placed.x), grow(placed.y), grow(placed.z)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::hittable_list::HittableList;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+
+    fn key<T>(time: f64, value: T, interpolation: Interpolation) -> Key<T> {
+        Key {
+            time,
+            value,
+            interpolation,
+            handles: [None, None],
+        }
+    }
+
+    fn track(keys: &[(f64, f64)], interpolation: Interpolation) -> Track<f64> {
+        let mut track = Track::new();
+        for &(time, value) in keys {
+            track.add(key(time, value, interpolation));
+        }
+        track
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    fn contains(bbox: &Aabb, p: Point3) -> bool {
+        (0..3).all(|n| bbox.axis(n).contains(p[n]))
+    }
+
+    #[test]
+    fn keys_replace_keys_at_the_same_time() {
+        let mut track = track(&[(1.0, 5.0), (0.0, 2.0)], Interpolation::Linear);
+        track.add(key(1.0, 3.0, Interpolation::CatmullRom));
+        assert_eq!(track.keys.len(), 2);
+        assert_eq!(track.keys[0].time, 0.0);
+        assert_eq!(track.keys[1].value, 3.0);
+        assert_eq!(track.keys[1].interpolation, Interpolation::CatmullRom);
+        assert!(close(track.value(0.5).unwrap(), 2.5));
+        assert!(Track::<f64>::new().value(0.0).is_none());
+    }
+
+    #[test]
+    fn values_are_held_outside_the_keys() {
+        for interpolation in [
+            Interpolation::Linear,
+            Interpolation::CatmullRom,
+            Interpolation::Bezier,
+            Interpolation::Monotone,
+        ] {
+            let track = track(&[(1.0, 2.0), (2.0, 4.0), (3.0, 3.0)], interpolation);
+            assert_eq!(track.channel_value(0, -10.0), 2.0);
+            assert_eq!(track.channel_value(0, 1.0), 2.0);
+            assert_eq!(track.channel_value(0, 3.0), 3.0);
+            assert_eq!(track.channel_value(0, 10.0), 3.0);
+            assert_eq!(track.channel_value(0, 2.0), 4.0);
+        }
+        let single = track(&[(1.0, 7.0)], Interpolation::CatmullRom);
+        assert_eq!(single.channel_value(0, 0.0), 7.0);
+        assert_eq!(single.channel_value(0, 2.0), 7.0);
+    }
+
+    #[test]
+    fn catmull_rom_tangents() {
+        // One-sided slopes at the ends, the slope across the neighbours in between.
+        let track = track(
+            &[(0.0, 0.0), (1.0, 1.0), (2.0, 4.0)],
+            Interpolation::CatmullRom,
+        );
+        assert!(close(track.tangent(0, 0, Interpolation::CatmullRom), 1.0));
+        assert!(close(track.tangent(0, 1, Interpolation::CatmullRom), 2.0));
+        assert!(close(track.tangent(0, 2, Interpolation::CatmullRom), 3.0));
+        // Hermite curve from 0 to 1 with tangents 1 and 2 at its middle.
+        assert!(close(track.channel_value(0, 0.5), 0.375));
+    }
+
+    #[test]
+    fn bezier_handles() {
+        let bezier = |keys: &[(f64, f64, [Option<f64>; 2])]| {
+            let mut track = Track::new();
+            for &(time, value, handles) in keys {
+                track.add(Key {
+                    handles,
+                    ..key(time, value, Interpolation::Bezier)
+                });
+            }
+            track
+        };
+
+        // Handles on the line between the keys keep the curve on it.
+        let straight = bezier(&[
+            (0.0, 0.0, [None, Some(1.0)]),
+            (3.0, 3.0, [Some(-1.0), None]),
+        ]);
+        for time in [0.5, 1.0, 1.5, 2.9] {
+            assert!(close(straight.channel_value(0, time), time));
+        }
+
+        // Flat handles ease in and out, and a handle past the keys overshoots them.
+        let eased = bezier(&[(0.0, 0.0, [None, Some(0.0)]), (1.0, 1.0, [Some(0.0), None])]);
+        assert!(close(eased.channel_value(0, 0.25), 0.15625));
+        assert!(close(eased.channel_value(0, 0.5), 0.5));
+        let raised = bezier(&[(0.0, 0.0, [None, Some(2.0)]), (1.0, 0.0, [Some(0.0), None])]);
+        assert!(close(raised.channel_value(0, 1.0 / 3.0), 8.0 / 9.0));
+
+        // Without handles the curve is Catmull-Rom's, ends included.
+        let keys = [(0.0, 0.0), (1.0, 1.0), (2.0, 4.0), (4.0, 2.0)];
+        let plain = track(&keys, Interpolation::Bezier);
+        let catmull_rom = track(&keys, Interpolation::CatmullRom);
+        for i in 0..=40 {
+            let time = i as f64 / 10.0;
+            assert!(close(
+                plain.channel_value(0, time),
+                catmull_rom.channel_value(0, time)
+            ));
+        }
+    }
+
+    #[test]
+    fn monotone_tangents() {
+        let track = track(
+            &[(0.0, 0.0), (1.0, 1.0), (2.0, 4.0), (3.0, 4.0), (4.0, 0.0)],
+            Interpolation::Monotone,
+        );
+        let tangent = |k| track.tangent(0, k, Interpolation::Monotone);
+        assert_eq!(tangent(0), 0.0); // Flat at the ends
+        assert_eq!(tangent(4), 0.0);
+        assert!(close(tangent(1), 2.0)); // Within three times the smaller slope
+        assert_eq!(tangent(2), 0.0); // Flat where the slope changes sign
+        assert_eq!(tangent(3), 0.0);
+
+        let steep = self::track(
+            &[(0.0, 0.0), (1.0, 0.1), (2.0, 10.0)],
+            Interpolation::Monotone,
+        );
+        assert!(close(steep.tangent(0, 1, Interpolation::Monotone), 0.3));
+
+        // Never outside the keys on either side.
+        for i in 0..=400 {
+            let time = i as f64 / 100.0;
+            let k = (time as usize).min(3);
+            let (a, b) = (track.keys[k].value, track.keys[k + 1].value);
+            let value = track.channel_value(0, time);
+            assert!(value >= a.min(b) - 1e-12 && value <= a.max(b) + 1e-12);
+        }
+    }
+
+    #[test]
+    fn frame_times() {
+        let timing = Timing::default();
+        assert_eq!(timing.frame_time(1), 0.0);
+        assert!(close(timing.frame_time(25), 1.0));
+        let timing = Timing {
+            fps: 30.0,
+            shutter: 1.0,
+        };
+        assert!(close(timing.frame_time(16), 0.5));
+    }
+
+    #[test]
+    fn box_covers_the_motion() {
+        // Two spheres on either side of the pivot, spun a quarter turn about z while
+        // moving along a Catmull-Rom curve that dips below its first keys.
+        let mut list = HittableList::new();
+        for x in [-2.0, 2.0] {
+            list.add(Sphere::new(
+                Point3::new(x, 0.0, 0.0),
+                0.5,
+                Lambertian::new(Color::same(0.5)),
+            ));
+        }
+        let mut motion = Motion::new();
+        for (time, x) in [(0.0, 0.0), (1.0, 0.0), (2.0, 1.0), (3.0, 1.0)] {
+            motion
+                .translate
+                .add(key(time, Vec3::new(x, 0.0, 0.0), Interpolation::CatmullRom));
+        }
+        motion
+            .rotate
+            .add(key(0.0, Vec3::zero(), Interpolation::Linear));
+        motion
+            .rotate
+            .add(key(3.0, Vec3::new(0.0, 0.0, 90.0), Interpolation::Linear));
+        let animated = Animated::new(Box::new(list), motion);
+        let bbox = animated.bounding_box().unwrap();
+
+        for i in 0..=300 {
+            let time = i as f64 / 100.0;
+            let x = animated.motion.translate.value(time).unwrap().x();
+            let angle = degrees_to_radians(30.0 * time);
+            for side in [-2.0, 2.0] {
+                let center = Point3::new(x + side * angle.cos(), side * angle.sin(), 0.0);
+                for offset in [Vec3::new(0.5, 0.0, 0.0), Vec3::new(0.0, 0.5, 0.0)] {
+                    assert!(contains(&bbox, center + offset));
+                    assert!(contains(&bbox, center - offset));
+                }
+            }
+        }
+        assert!(animated.motion.translate.value(2.0 / 3.0).unwrap().x() < -0.05);
+        assert!(bbox.x.max < 4.0 && bbox.y.max < 3.0 && bbox.z.max < 1.0);
+    }
+}
@@ -0,0 +1,199 @@
+// Arbitrary output variables: per-pixel auxiliary buffers written next to the beauty image.
+//
+// AOVs are averaged over the samples of each pixel (a box filter) instead of being
+// splatted with the reconstruction filter, so that geometric buffers stay sharp. Object
+// and material IDs cannot be averaged and are taken from the first sample that hits
+// something.
+
+use crate::checkpoint::hash_str;
+use crate::exr::Channel;
+use crate::hittable::HitRecord;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::Color;
+use crate::Point3;
+use crate::Vec3;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Aov {
+    Albedo,     // Attenuation at the first hit, the background color for misses
+    Normal,     // Shading normal at the first hit
+    Depth,      // Distance from the camera to the first hit, infinite for misses
+    Position,   // World position of the first hit
+    ObjectId,   // 1 + index of the hit object in the scene, 0 for misses
+    MaterialId, // Hash of the hit material, equal for equal materials, 0 for misses
+    Direct,     // Light that reached the camera after at most one bounce
+    Indirect,   // Light that bounced two or more times
+}
+
+impl Aov {
+    pub const ALL: [Aov; 8] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Direct,
+        Aov::Indirect,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::ALL.into_iter().find(|aov| aov.name() == name)
+    }
+
+    pub fn channel_names(&self) -> &'static [&'static str] {
+        // Names of the channels inside the AOV's EXR layer.
+        match self {
+            Aov::Albedo | Aov::Direct | Aov::Indirect => &["R", "G", "B"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+        }
+    }
+
+    pub fn is_scalar(&self) -> bool {
+        self.channel_names().len() == 1
+    }
+}
+
+// What a single camera sample saw, filled in while tracing it.
+#[derive(Debug, Clone)]
+pub struct AovSample<'a> {
+    pub albedo: Color,
+    pub direct: Color,
+    pub indirect: Color,
+
+    // Only meaningful when `hit` is set
+    pub hit: bool,
+    pub normal: Vec3,
+    pub depth: f64,
+    pub position: Point3,
+    pub object_id: i32,
+    pub material: Option<&'a dyn Material>,
+}
+
+impl<'a> AovSample<'a> {
+    pub fn new() -> Self {
+        Self {
+            albedo: Color::zero(),
+            direct: Color::zero(),
+            indirect: Color::zero(),
+            hit: false,
+            normal: Vec3::zero(),
+            depth: 0.0,
+            position: Point3::zero(),
+            object_id: 0,
+            material: None,
+        }
+    }
+
+    pub fn record_hit(&mut self, r: &Ray, rec: &HitRecord<'a>) {
+        self.hit = true;
+        self.normal = rec.normal;
+        self.depth = rec.t * r.direction().length();
+        self.position = rec.p;
+        self.object_id = rec.object_id;
+        self.material = Some(rec.mat);
+    }
+}
+
+fn material_id(mat: &dyn Material) -> i32 {
+    // Kept to 24 bits so that the ID is exact in a 32-bit float channel.
+    ((hash_str(&format!("{:?}", mat)) & 0xffffff) as i32).max(1)
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct AovPixel {
+    pub sample_count: i32,
+    pub hit_count: i32, // Samples that hit something
+    pub albedo_sum: Color,
+    pub direct_sum: Color,
+    pub indirect_sum: Color,
+    pub normal_sum: Vec3,
+    pub depth_sum: f64,
+    pub position_sum: Point3,
+    pub object_id: i32,
+    pub material_id: i32,
+}
+
+impl AovPixel {
+    pub fn new() -> Self {
+        Self {
+            sample_count: 0,
+            hit_count: 0,
+            albedo_sum: Color::zero(),
+            direct_sum: Color::zero(),
+            indirect_sum: Color::zero(),
+            normal_sum: Vec3::zero(),
+            depth_sum: 0.0,
+            position_sum: Point3::zero(),
+            object_id: 0,
+            material_id: 0,
+        }
+    }
+
+    pub fn add(&mut self, sample: &AovSample) {
+        self.sample_count += 1;
+        self.albedo_sum += sample.albedo;
+        self.direct_sum += sample.direct;
+        self.indirect_sum += sample.indirect;
+        if !sample.hit {
+            return;
+        }
+        if self.hit_count == 0 {
+            self.object_id = sample.object_id;
+            self.material_id = sample.material.map_or(0, material_id);
+        }
+        self.hit_count += 1;
+        self.normal_sum += sample.normal;
+        self.depth_sum += sample.depth;
+        self.position_sum += sample.position;
+    }
+
+    pub fn value(&self, aov: Aov) -> Color {
+        // Scalar AOVs are returned in all three components.
+        let samples = self.sample_count.max(1) as f64;
+        let hits = self.hit_count.max(1) as f64;
+        match aov {
+            Aov::Albedo => self.albedo_sum / samples,
+            Aov::Direct => self.direct_sum / samples,
+            Aov::Indirect => self.indirect_sum / samples,
+            Aov::Normal => self.normal_sum / hits,
+            Aov::Position => self.position_sum / hits,
+            Aov::Depth if self.hit_count == 0 => Color::same(f64::INFINITY),
+            Aov::Depth => Color::same(self.depth_sum / hits),
+            Aov::ObjectId => Color::same(self.object_id as f64),
+            Aov::MaterialId => Color::same(self.material_id as f64),
+        }
+    }
+}
+
+pub fn aov_channels(aov: Aov, values: &[Color]) -> Vec<Channel> {
+    // One EXR channel per component, in the layer named after the AOV.
+    aov.channel_names()
+        .iter()
+        .enumerate()
+        .map(|(k, channel)| Channel {
+            name: format!("{}.{}", aov.name(), channel),
+            values: values
+                .iter()
+                .map(|c| [c.x(), c.y(), c.z()][k] as f32)
+                .collect(),
+            exact: matches!(aov, Aov::ObjectId | Aov::MaterialId),
+        })
+        .collect()
+}
@@ -0,0 +1,130 @@
+// Shapes of the lens aperture, which out of focus highlights (bokeh) take on.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::color::luminance;
+use crate::texture::Texture;
+use crate::utils::{degrees_to_radians, PI};
+use crate::vec3::{Point3, Vec3};
+
+#[derive(Debug, Clone)]
+pub enum Aperture {
+    Circle,
+    Polygon { blades: i32, rotation: f64 }, // Regular polygon, rotated in degrees
+    Annulus { inner: f64 },                 // Ring, with the inner radius as a fraction
+    Image(Arc<ApertureImage>),
+}
+
+impl Aperture {
+    pub fn sample(&self, u: (f64, f64)) -> Point3 {
+        // Maps a point of [0,1)^2 to a point of the aperture within the unit disk (the square
+        // around it for images), uniformly over its open area or in proportion to the
+        // transmission of an image.
+        match self {
+            Aperture::Circle => Vec3::disk_from_2d(u),
+            Aperture::Polygon { blades, rotation } => {
+                // One of the triangles between the center and an edge, then a uniform
+                // point in it.
+                let blades = (*blades).max(3);
+                let scaled = u.0 * blades as f64;
+                let k = (scaled as i32).min(blades - 1);
+                let u0 = scaled - k as f64;
+                let angle =
+                    |k: i32| 2.0 * PI * k as f64 / blades as f64 + degrees_to_radians(*rotation);
+                let a = Vec3::new(angle(k).cos(), angle(k).sin(), 0.0);
+                let b = Vec3::new(angle(k + 1).cos(), angle(k + 1).sin(), 0.0);
+                let r = u0.sqrt();
+                a * (r * (1.0 - u.1)) + b * (r * u.1)
+            }
+            Aperture::Annulus { inner } => {
+                let inner2 = inner * inner;
+                let r = (inner2 + u.0 * (1.0 - inner2)).sqrt();
+                let phi = 2.0 * PI * u.1;
+                Vec3::new(r * phi.cos(), r * phi.sin(), 0.0)
+            }
+            Aperture::Image(image) => image.sample(u),
+        }
+    }
+}
+
+// Resolution of the grid an aperture image is sampled on.
+const IMAGE_GRID: usize = 128;
+
+// Aperture transmission from the luminance of a texture over [0,1]^2, with v up, mapped
+// onto the square around the unit disk.
+pub struct ApertureImage {
+    texture: Arc<dyn Texture>,
+    row_cdf: Vec<f64>,   // Cumulative transmission of the rows, top to bottom
+    cell_cdfs: Vec<f64>, // Cumulative transmission of the cells within each row
+}
+
+impl Debug for ApertureImage {
+    // The sampling tables follow from the texture.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApertureImage")
+            .field("texture", &self.texture)
+            .finish()
+    }
+}
+
+fn cumulative(values: &[f64]) -> Vec<f64> {
+    // Normalized running sums starting at 0, uniform if all values are 0.
+    let total: f64 = values.iter().sum();
+    let mut cdf = vec![0.0];
+    let mut sum = 0.0;
+    for (k, value) in values.iter().enumerate() {
+        sum += value;
+        cdf.push(if total > 0.0 {
+            sum / total
+        } else {
+            (k + 1) as f64 / values.len() as f64
+        });
+    }
+    cdf
+}
+
+fn sample_cdf(cdf: &[f64], u: f64) -> (usize, f64) {
+    // The interval of `cdf` containing u, and the position of u within it.
+    let k = cdf.partition_point(|&c| c <= u).clamp(1, cdf.len() - 1) - 1;
+    let width = cdf[k + 1] - cdf[k];
+    let t = if width > 0.0 {
+        (u - cdf[k]) / width
+    } else {
+        0.5
+    };
+    (k, t.clamp(0.0, 1.0))
+}
+
+impl ApertureImage {
+    pub fn new(texture: Arc<dyn Texture>) -> Self {
+        let n = IMAGE_GRID;
+        let mut row_sums = Vec::with_capacity(n);
+        let mut cell_cdfs = Vec::with_capacity(n * (n + 1));
+        for row in 0..n {
+            let v = 1.0 - (row as f64 + 0.5) / n as f64;
+            let cells: Vec<f64> = (0..n)
+                .map(|column| {
+                    let u = (column as f64 + 0.5) / n as f64;
+                    luminance(texture.value(u, v, &Point3::zero())).max(0.0)
+                })
+                .collect();
+            row_sums.push(cells.iter().sum());
+            cell_cdfs.extend(cumulative(&cells));
+        }
+        Self {
+            texture,
+            row_cdf: cumulative(&row_sums),
+            cell_cdfs,
+        }
+    }
+
+    fn sample(&self, u: (f64, f64)) -> Point3 {
+        let n = IMAGE_GRID;
+        let (row, tv) = sample_cdf(&self.row_cdf, u.1);
+        let (column, tu) = sample_cdf(&self.cell_cdfs[row * (n + 1)..(row + 1) * (n + 1)], u.0);
+        let x = (column as f64 + tu) / n as f64;
+        let y = (row as f64 + tv) / n as f64;
+        Vec3::new(2.0 * x - 1.0, 1.0 - 2.0 * y, 0.0)
+    }
+}
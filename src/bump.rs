@@ -0,0 +1,137 @@
+// Shading normals perturbed by normal maps and bump maps, for surface detail that the
+// geometry does not have.
+
+use std::sync::Arc;
+
+use crate::color::luminance;
+use crate::material::Material;
+use crate::medium::Medium;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::texture::Texture;
+use crate::vec3::Vec3;
+use crate::Color;
+use crate::HitRecord;
+
+#[derive(Debug, Clone)]
+pub enum ShadingNormal {
+    // Tangent space normals encoded as colors in [0,1], with x along u, y along v and z
+    // out of the surface. The texture should be loaded as data.
+    NormalMap(Arc<dyn Texture>),
+    // Heights along the normal in world units per unit of texture luminance, scaled.
+    Bump {
+        height: Arc<dyn Texture>,
+        scale: f64,
+    },
+}
+
+// Offset in texture coordinates for the finite differences of a bump map.
+const BUMP_DELTA: f64 = 1.0 / 2048.0;
+
+impl ShadingNormal {
+    fn perturb(&self, rec: &HitRecord) -> Vec3 {
+        // The perturbed normal, on the same side of the surface as `rec.normal`.
+        let n = rec.normal;
+        let perturbed = match self {
+            ShadingNormal::NormalMap(map) => {
+                let c = map.value(rec.u, rec.v, &rec.p);
+                let local = Vec3::new(2.0 * c.x() - 1.0, 2.0 * c.y() - 1.0, 2.0 * c.z() - 1.0);
+                // The map's z is towards the front of the surface.
+                let outward = if rec.front_face { n } else { -n };
+                let frame = Onb::with_tangent(&outward, &rec.dpdu);
+                // Follow the direction of increasing v, whichever way the parameterization
+                // is oriented.
+                let flip = if frame.v.dot(&rec.dpdv) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                let local = Vec3::new(local.x(), local.y() * flip, local.z());
+                frame.local(&local)
+            }
+            ShadingNormal::Bump { height, scale } => {
+                // Moves the surface along its normal and takes the normal of the displaced
+                // surface from the displaced derivatives.
+                let h = |u: f64, v: f64| {
+                    let p = rec.p + rec.dpdu * (u - rec.u) + rec.dpdv * (v - rec.v);
+                    luminance(height.value(u, v, &p)) * scale
+                };
+                let h0 = h(rec.u, rec.v);
+                let dhdu = (h(rec.u + BUMP_DELTA, rec.v) - h0) / BUMP_DELTA;
+                let dhdv = (h(rec.u, rec.v + BUMP_DELTA) - h0) / BUMP_DELTA;
+                let outward = if rec.front_face { n } else { -n };
+                let dpdu = rec.dpdu + outward * dhdu;
+                let dpdv = rec.dpdv + outward * dhdv;
+                dpdu.cross(&dpdv)
+            }
+        };
+        if perturbed.near_zero() || !perturbed.length().is_finite() {
+            return n;
+        }
+        let perturbed = perturbed.unit();
+        if perturbed.dot(&n) < 0.0 {
+            -perturbed
+        } else {
+            perturbed
+        }
+    }
+}
+
+// A material shaded with a perturbed normal. The geometric normal still decides which
+// side of the surface light is on, so scattering that the shading normal sends through
+// the surface is absorbed rather than leaking light.
+#[derive(Debug, Clone)]
+pub struct Perturbed {
+    pub base: Arc<dyn Material>,
+    pub normal: ShadingNormal,
+}
+
+impl Perturbed {
+    pub fn new(base: Arc<dyn Material>, normal: ShadingNormal) -> Self {
+        Self { base, normal }
+    }
+}
+
+impl Material for Perturbed {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        uc: f64,
+        u: (f64, f64),
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let d = r_in.direction();
+        let mut shading = rec.clone();
+        shading.normal = self.normal.perturb(rec);
+        if d.dot(&shading.normal) >= 0.0 {
+            // Seen from behind the shading normal: shade with the geometric normal.
+            shading.normal = rec.normal;
+        }
+        if !self
+            .base
+            .scatter(r_in, &shading, uc, u, attenuation, scattered)
+        {
+            return false;
+        }
+        let s = scattered.direction();
+        (s.dot(&shading.normal) > 0.0) == (s.dot(&rec.normal) > 0.0)
+    }
+
+    fn interior(&self, rec: &HitRecord) -> Option<Medium> {
+        self.base.interior(rec)
+    }
+
+    fn dispersive(&self) -> bool {
+        self.base.dispersive()
+    }
+
+    fn cut_out(&self, r: &Ray, rec: &HitRecord) -> bool {
+        self.base.cut_out(r, rec)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.base.emitted(r_in, rec)
+    }
+}
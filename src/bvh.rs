@@ -0,0 +1,114 @@
+// Bounding volume hierarchy, so that a ray is only tested against the objects whose boxes
+// it passes through.
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::interval::Interval;
+use crate::ray::Ray;
+
+#[derive(Debug)]
+enum Node {
+    Leaf(Aabb, i32, Box<dyn Hittable>), // Box, object id and object
+    Branch(Aabb, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn build(mut objects: Vec<(Aabb, i32, Box<dyn Hittable>)>) -> Self {
+        // Splits the objects in half along the longest axis of their box, by the centers
+        // of their own boxes.
+        if objects.len() == 1 {
+            let (bbox, id, object) = objects.pop().unwrap();
+            return Node::Leaf(bbox, id, object);
+        }
+        let bbox = objects
+            .iter()
+            .fold(Aabb::empty(), |bbox, (object_box, _, _)| {
+                bbox.union(object_box)
+            });
+        let axis = bbox.longest_axis();
+        objects.sort_by(|a, b| a.0.centroid()[axis].total_cmp(&b.0.centroid()[axis]));
+        let right = objects.split_off(objects.len() / 2);
+        Node::Branch(
+            bbox,
+            Box::new(Node::build(objects)),
+            Box::new(Node::build(right)),
+        )
+    }
+
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        match self {
+            Node::Leaf(bbox, id, object) => {
+                if !bbox.hit(r, ray_t) {
+                    return None;
+                }
+                let mut hit = object.hit(r, ray_t)?;
+                hit.object_id = *id;
+                Some(hit)
+            }
+            Node::Branch(bbox, left, right) => {
+                if !bbox.hit(r, ray_t) {
+                    return None;
+                }
+                let left_hit = left.hit(r, ray_t);
+                let max = left_hit.as_ref().map_or(ray_t.max, |hit| hit.t);
+                right.hit(r, Interval::new(ray_t.min, max)).or(left_hit)
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match self {
+            Node::Leaf(bbox, _, _) | Node::Branch(bbox, _, _) => *bbox,
+        }
+    }
+}
+
+// The objects of a list, with their ids of 1 + their index in it. Objects without fixed
+// bounds, such as animated ones, are tested on every ray next to the tree.
+#[derive(Debug)]
+pub struct Bvh {
+    root: Option<Node>,
+    unbounded: Vec<(i32, Box<dyn Hittable>)>,
+}
+
+impl Bvh {
+    pub fn new(list: HittableList) -> Self {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for (index, object) in list.objects.into_iter().enumerate() {
+            let id = index as i32 + 1;
+            match object.bounding_box() {
+                Some(bbox) => bounded.push((bbox, id, object)),
+                None => unbounded.push((id, object)),
+            }
+        }
+        let root = if bounded.is_empty() {
+            None
+        } else {
+            Some(Node::build(bounded))
+        };
+        Self { root, unbounded }
+    }
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let mut hit_anything = self.root.as_ref().and_then(|root| root.hit(r, ray_t));
+        for (id, object) in &self.unbounded {
+            let max = hit_anything.as_ref().map_or(ray_t.max, |hit| hit.t);
+            if let Some(mut hit) = object.hit(r, Interval::new(ray_t.min, max)) {
+                hit.object_id = *id;
+                hit_anything = Some(hit);
+            }
+        }
+        hit_anything
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.root.as_ref().map(Node::bounding_box)
+    }
+}
@@ -1,19 +1,122 @@
-use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-use crate::hittable::Hittable;
+use crate::animation::CameraPose;
+use crate::aov::{self, Aov, AovSample};
+use crate::checkpoint::{hash_str, Checkpointer, Checkpointing};
+use crate::color::{self, ColorSpace};
+use crate::denoise::{self, Denoising};
+use crate::exposure::PhysicalCamera;
+use crate::exr::{self, ExrSettings};
+use crate::film::Film;
+use crate::filter::{BoxFilter, Filter};
+use crate::hittable::{HitRecord, Hittable};
+use crate::medium::MediumStack;
+use crate::pfm;
+use crate::png;
+use crate::projection::{Perspective, Projection};
 use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType};
+use crate::spectrum::{self, SampledWavelengths};
+use crate::tonemap::ToneMapping;
 use crate::utils;
-use crate::utils::degrees_to_radians;
-use crate::vec3;
 use crate::Color;
-use crate::HitRecord;
 use crate::HittableList;
 use crate::Interval;
 use crate::Point3;
 use crate::Vec3;
 
+#[derive(Debug, Copy, Clone)]
+pub struct AdaptiveSampling {
+    pub min_samples: i32, // Samples every pixel gets before its noise is estimated
+    pub max_samples: i32, // Upper bound of samples for the noisiest pixels
+    pub threshold: f64,   // Relative standard error under which a pixel is converged
+}
+
+// Pixel rectangle of the image to render, for working on a detail or rendering in tiles.
+// The camera projects as for the full image, so regions composite exactly.
+#[derive(Debug, Copy, Clone)]
+pub struct Region {
+    pub x: i32, // Left column
+    pub y: i32, // Top row
+    pub width: i32,
+    pub height: i32,
+    pub canvas: bool, // Write the full image, transparent around the region, instead of a crop
+}
+
+impl Region {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            canvas: false,
+        }
+    }
+
+    pub fn tile(index: i32, columns: i32, rows: i32, image_width: i32, image_height: i32) -> Self {
+        // Tile `index`, counted row by row from the top left, of a grid over the image. The
+        // tiles cover every pixel once.
+        let (column, row) = (index % columns, index / columns);
+        let x = column * image_width / columns;
+        let y = row * image_height / rows;
+        Self::new(
+            x,
+            y,
+            (column + 1) * image_width / columns - x,
+            (row + 1) * image_height / rows - y,
+        )
+    }
+
+    pub fn fits(&self, image_width: i32, image_height: i32) -> bool {
+        self.x >= 0
+            && self.y >= 0
+            && self.width > 0
+            && self.height > 0
+            && self.x + self.width <= image_width
+            && self.y + self.height <= image_height
+    }
+
+    pub fn contains(&self, i: i32, j: i32) -> bool {
+        i >= self.x && i < self.x + self.width && j >= self.y && j < self.y + self.height
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Progressive {
+    pub samples_per_pass: i32, // Samples added to every pixel by each pass
+    pub snapshot_interval: Option<Duration>, // Time between image writes, None for every pass
+    pub time_budget: Option<Duration>, // Wall-clock time after which rendering stops
+}
+
+// Sky gradient seen by rays that leave the scene, in the working color space.
+#[derive(Debug, Copy, Clone)]
+pub struct Sky {
+    pub horizon: Color,
+    pub zenith: Color,
+}
+
+impl Sky {
+    pub fn default() -> Self {
+        Self {
+            horizon: Color::same(1.0),
+            zenith: Color::new(0.5, 0.7, 1.0),
+        }
+    }
+
+    pub fn value(&self, direction: Vec3) -> Color {
+        let uni_direction = direction.unit();
+        let beta = 0.5 * (uni_direction.y() + 1.0);
+
+        self.horizon * (1.0 - beta) + self.zenith * beta
+    }
+}
+
 pub struct Camera {
-    pub aspect_ratio: f64,      // Ratio of image width over height
+    #[allow(dead_code)]
+    pub aspect_ratio: f64, // Ratio of image width over height
     pub image_width: i32,       // Rendered image width in pixel count
     pub samples_per_pixel: i32, // Count of random samples for each pixel
     pub max_depth: i32,         // Maximum number of ray bounces into scene
@@ -26,22 +129,45 @@ pub struct Camera {
     pub defocus_angle: f64, // Variation angle of rays through each pixel
     pub focus_dist: f64,    // Distance from camera lookfrom point to plane of perfect focus
 
-    image_height: i32,   // Rendered image height
-    center: Point3,      // Camera center
-    pixel00_loc: Point3, // Location of pixel 0, 0
-    pixel_delta_u: Vec3, // Offset to pixel to the right
-    pixel_delta_v: Vec3, // Offset to pixel below
+    pub sampler: SamplerType, // How the random numbers of each pixel sample are generated
+    pub seed: u64,            // Seed of the sampler
+    pub filter: Box<dyn Filter>, // Reconstruction filter the samples are splatted with
+
+    pub adaptive: Option<AdaptiveSampling>, // Adaptive sampling of samples_per_pixel on average
+    pub sample_count_image: Option<String>, // Path of a debug image of the samples per pixel
+
+    pub checkpoint: Option<Checkpointing>, // Periodic saving and resuming of the film
+
+    pub exr_settings: ExrSettings, // Pixel type and compression of .exr output
+    pub tone_mapping: ToneMapping, // Applied to 8-bit output, HDR output stays linear
+
+    pub background: Sky,           // Light from outside the scene
+    pub working_space: ColorSpace, // Color space of the scene, the film and HDR output
+    pub display_space: ColorSpace, // Color space of 8-bit output
+    pub spectral: bool,            // Trace sampled wavelengths instead of RGB
+
+    pub aovs: Vec<Aov>,             // Auxiliary buffers written next to the image
+    pub denoise: Option<Denoising>, // Denoising of the written image, not of the film
+
+    pub projection: Box<dyn Projection>, // Perspective from vfov and the defocus settings by default
+    pub physical: Option<PhysicalCamera>, // Exposure of the film to light in photometric units
+
+    pub region: Option<Region>, // Part of the image to render, all of it if None
+
+    pub time: f64,    // Scene time in seconds at which the shutter opens
+    pub shutter: f64, // Time the shutter stays open, replaced by that of a physical camera
+
+    image_height: i32, // Rendered image height
+    center: Point3,    // Camera center
 
     // Camera frame basis vectors
     u: Vec3,
     v: Vec3,
     w: Vec3,
-
-    defocus_disk_u: Vec3, // Defocus disk horizontal radius
-    defocus_disk_v: Vec3, //  Defocus disk vertical radius
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         aspect_ratio: f64,
         image_width: i32,
@@ -59,27 +185,10 @@ impl Camera {
 
         let center = lookfrom;
 
-        let theta = degrees_to_radians(vfov);
-        let h = (theta / 2.0).tan();
-        let viewport_height = 2.0 * h * focus_dist;
-        let viewport_width = viewport_height * (image_width as f64 / image_height as f64);
-
         let w = (lookfrom - lookat).unit();
         let u = vup.cross(&w).unit();
         let v = w.cross(&u);
 
-        let viewport_u = u * viewport_width;
-        let viewport_v = -v * viewport_height;
-        let pixel_delta_u = viewport_u / image_width as f64;
-        let pixel_delta_v = viewport_v / image_height as f64;
-
-        let viewport_upper_left = center - (w * focus_dist) - viewport_u / 2.0 - viewport_v / 2.0;
-        let pixel00_loc = viewport_upper_left + pixel_delta_u / 2.0 + pixel_delta_v / 2.0;
-
-        let defocus_radius = focus_dist * degrees_to_radians(defocus_angle / 2.0).tan();
-        let defocus_disk_u = u * defocus_radius;
-        let defocus_disk_v = v * defocus_radius;
-
         Self {
             aspect_ratio,
             image_width,
@@ -91,19 +200,34 @@ impl Camera {
             vup,
             defocus_angle,
             focus_dist,
+            sampler: SamplerType::Independent,
+            seed: 0,
+            filter: Box::new(BoxFilter::new(0.5)),
+            adaptive: None,
+            sample_count_image: None,
+            checkpoint: None,
+            exr_settings: ExrSettings::default(),
+            tone_mapping: ToneMapping::default(),
+            background: Sky::default(),
+            working_space: ColorSpace::LinearSrgb,
+            display_space: ColorSpace::LinearSrgb,
+            spectral: false,
+            aovs: Vec::new(),
+            denoise: None,
+            projection: Box::new(Perspective::new(vfov, defocus_angle, focus_dist)),
+            physical: None,
+            region: None,
+            time: 0.0,
+            shutter: 1.0,
             image_height,
             center,
-            pixel00_loc,
-            pixel_delta_u,
-            pixel_delta_v,
             u,
             v,
             w,
-            defocus_disk_u,
-            defocus_disk_v,
         }
     }
 
+    #[allow(dead_code)]
     pub fn default() -> Self {
         Self::new(
             1.0,
@@ -119,57 +243,431 @@ impl Camera {
         )
     }
 
-    fn ray_color(r: &Ray, depth: i32, world: &HittableList) -> Color {
+    pub fn image_height(&self) -> i32 {
+        self.image_height
+    }
+
+    pub fn set_pose(&mut self, pose: CameraPose) {
+        // Moves the camera and changes its lens, for a frame of an animation.
+        self.lookfrom = pose.lookfrom;
+        self.lookat = pose.lookat;
+        self.vfov = pose.vfov;
+        self.defocus_angle = pose.defocus_angle;
+        self.focus_dist = pose.focus_dist;
+        self.center = pose.lookfrom;
+        self.w = (pose.lookfrom - pose.lookat).unit();
+        self.u = self.vup.cross(&self.w).unit();
+        self.v = self.w.cross(&self.u);
+        self.projection
+            .set_lens(pose.vfov, pose.defocus_angle, pose.focus_dist);
+    }
+
+    fn ray_color(
+        &self,
+        r: &Ray,
+        depth: i32,
+        world: &HittableList,
+        sampler: &mut dyn Sampler,
+        media: MediumStack,
+    ) -> Color {
         if depth <= 0 {
             // If we've exceeded the ray bounce limit, no more light is gathered.
             return Color::zero();
         }
-        if let Some(rec) = world.hit(&r, Interval::new(0.0001, utils::INF)) {
-            let mut scattered: Ray = Ray::new(Point3::zero(), Vec3::zero());
-            let mut attenuation: Color = Color::zero();
-            return if rec.mat.scatter(&r, &rec, &mut attenuation, &mut scattered) {
-                attenuation * Self::ray_color(&scattered, depth - 1, world)
-            } else {
-                Color::zero()
-            };
+        match world.hit(r, Interval::new(0.0001, utils::INF)) {
+            Some(rec) => {
+                self.path_color(media.transmittance(rec.t * r.direction().length()), r)
+                    * self.hit_color(r, &rec, depth, world, sampler, media)
+            }
+            None => self.background_color(r),
+        }
+    }
+
+    fn hit_color(
+        &self,
+        r: &Ray,
+        rec: &HitRecord,
+        depth: i32,
+        world: &HittableList,
+        sampler: &mut dyn Sampler,
+        media: MediumStack,
+    ) -> Color {
+        // Light leaving the hit point `rec` of `r` towards the ray origin, with `media`
+        // the media `r` travelled through.
+        self.emitted_color(r, rec) + self.scattered_color(r, rec, depth, world, sampler, media)
+    }
+
+    fn scattered_color(
+        &self,
+        r: &Ray,
+        rec: &HitRecord,
+        depth: i32,
+        world: &HittableList,
+        sampler: &mut dyn Sampler,
+        media: MediumStack,
+    ) -> Color {
+        // The part of `hit_color` the surface scatters rather than emits.
+        let mut media = media;
+        match self.scatter(r, rec, sampler, &mut media) {
+            Some((attenuation, mut scattered)) => {
+                self.path_attenuation(attenuation, rec, &mut scattered)
+                    * self.ray_color(&scattered, depth - 1, world, sampler, media)
+            }
+            None => Color::zero(),
         }
+    }
 
-        let uni_direction = Vec3::unit(&r.direction());
-        let beta = 0.5 * (uni_direction.y() + 1.0);
+    fn scatter(
+        &self,
+        r: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+        media: &mut MediumStack,
+    ) -> Option<(Color, Ray)> {
+        // Draw the bounce dimensions whatever the material, so that later bounces stay
+        // aligned across the samples of a pixel.
+        let uc = sampler.get_1d();
+        let u = sampler.get_2d();
+        let mut scattered: Ray = Ray::new(Point3::zero(), Vec3::zero());
+        let mut attenuation: Color = Color::zero();
+        if !rec
+            .mat
+            .scatter(r, rec, uc, u, &mut attenuation, &mut scattered)
+        {
+            return None;
+        }
+        scattered.set_wavelengths(r.wavelengths().copied());
 
-        Color::same(1.0) * (1.0 - beta) + Color::new(0.5, 0.7, 1.0) * beta
+        // Rays transmitted through the surface enter its interior from the front and
+        // leave it from the back.
+        if let Some(interior) = rec.mat.interior(rec) {
+            if scattered.direction().dot(&rec.normal) < 0.0 {
+                if rec.front_face {
+                    media.enter(interior);
+                } else {
+                    media.exit();
+                }
+            }
+        }
+        Some((attenuation, scattered))
     }
 
-    fn pixel_sample_square(&self) -> Vec3 {
-        // Returns a random point in the square surrounding a pixel at the origin.
-        let px = -0.5 + utils::random_double();
-        let py = -0.5 + utils::random_double();
-        // px, py are in (-0.5, 0.5)
-        (self.pixel_delta_u * px) + (self.pixel_delta_v * py)
+    fn path_attenuation(&self, attenuation: Color, rec: &HitRecord, scattered: &mut Ray) -> Color {
+        // The RGB attenuation of a scattering at the wavelengths of the path. Dispersion
+        // splits the wavelengths apart and leaves only the hero to follow `scattered`.
+        let Some(mut wavelengths) = scattered.wavelengths().copied() else {
+            return attenuation;
+        };
+        let values = spectrum::sample_reflectance(attenuation, self.working_space, &wavelengths);
+        if !rec.mat.dispersive() {
+            return values;
+        }
+        let values = wavelengths.terminate_secondary(values);
+        scattered.set_wavelengths(Some(wavelengths));
+        values
     }
 
-    fn defocus_disk_sample(&self) -> Point3 {
-        // Returns a random point in the camera defocus disk.
-        let p = vec3::Vec3::random_vec2();
-        self.center + (self.defocus_disk_u * p.x()) + (self.defocus_disk_v * p.y())
+    fn path_color(&self, rgb: Color, r: &Ray) -> Color {
+        // An RGB reflectance or transmittance as carried by the path of `r`.
+        match r.wavelengths() {
+            Some(wavelengths) => spectrum::sample_reflectance(rgb, self.working_space, wavelengths),
+            None => rgb,
+        }
     }
 
-    fn get_ray(&self, i: i32, j: i32) -> Ray {
-        // Get a randomly-sampled camera ray for the pixel at location i,j, originating from
-        // the camera defocus disk.
+    fn light_color(&self, rgb: Color, r: &Ray) -> Color {
+        // An RGB radiance as carried by the path of `r`.
+        match r.wavelengths() {
+            Some(wavelengths) => spectrum::sample_illuminant(rgb, self.working_space, wavelengths),
+            None => rgb,
+        }
+    }
 
-        let pixel_center =
-            self.pixel00_loc + self.pixel_delta_u * i as f64 + self.pixel_delta_v * j as f64;
-        let pixel_sample = pixel_center + self.pixel_sample_square();
+    fn background_color(&self, r: &Ray) -> Color {
+        self.light_color(self.background.value(r.direction()), r)
+    }
+
+    fn emitted_color(&self, r: &Ray, rec: &HitRecord) -> Color {
+        self.light_color(rec.mat.emitted(r, rec), r)
+    }
 
-        let ray_origin = if self.defocus_angle <= 0.0 {
-            self.center
+    fn camera_ray_color<'a>(
+        &self,
+        r: &Ray,
+        world: &'a HittableList,
+        sampler: &mut dyn Sampler,
+        aov: &mut AovSample<'a>,
+    ) -> Color {
+        // Same as `ray_color` at full depth from outside of any medium, also recording the
+        // first hit and splitting the light by the number of bounces into `aov`.
+        if self.max_depth <= 0 {
+            return Color::zero();
+        }
+        let Some(rec) = world.hit(r, Interval::new(0.0001, utils::INF)) else {
+            let color = self.background_color(r);
+            aov.albedo = self.background.value(r.direction());
+            aov.direct = color;
+            return color;
+        };
+        aov.record_hit(r, &rec);
+        let emitted = self.emitted_color(r, &rec);
+        aov.direct = emitted;
+        let mut media = MediumStack::new();
+        let Some((albedo, mut scattered)) = self.scatter(r, &rec, sampler, &mut media) else {
+            return emitted;
+        };
+        aov.albedo = albedo;
+        let attenuation = self.path_attenuation(albedo, &rec, &mut scattered);
+        if self.max_depth <= 1 {
+            return emitted;
+        }
+        match world.hit(&scattered, Interval::new(0.0001, utils::INF)) {
+            None => {
+                aov.direct = emitted + attenuation * self.background_color(&scattered);
+                aov.direct
+            }
+            Some(next) => {
+                // Light emitted at the next hit is direct, what it scatters indirect.
+                let transmittance = media.transmittance(next.t * scattered.direction().length());
+                let transmittance = self.path_color(transmittance, &scattered);
+                let next_emitted = transmittance * self.emitted_color(&scattered, &next);
+                let color = transmittance
+                    * self.scattered_color(
+                        &scattered,
+                        &next,
+                        self.max_depth - 1,
+                        world,
+                        sampler,
+                        media,
+                    );
+                aov.direct = emitted + attenuation * next_emitted;
+                aov.indirect = attenuation * color;
+                aov.direct + aov.indirect
+            }
+        }
+    }
+
+    fn get_ray(
+        &self,
+        i: i32,
+        j: i32,
+        offset: (f64, f64),
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)> {
+        // Get a camera ray through `offset` inside the pixel at location i,j from the
+        // projection, or None outside of its image, with the weight of its light. In spectral
+        // mode the ray carries the sampled wavelengths. The sampler must have been started
+        // for this pixel sample.
+        let width = self.image_width as f64;
+        let height = self.image_height as f64;
+        let screen = (
+            2.0 * (i as f64 + offset.0) / width - 1.0,
+            1.0 - 2.0 * (j as f64 + offset.1) / height,
+        );
+
+        // All are drawn whether they are used or not, to keep the dimensions aligned.
+        let lens = sampler.get_2d();
+        let ray_time = sampler.get_1d();
+        let mut wavelengths = if self.spectral {
+            Some(SampledWavelengths::sample_visible(sampler.get_1d()))
         } else {
-            self.defocus_disk_sample()
+            None
+        };
+
+        // A physical camera exposes the film for the shutter time in seconds.
+        let (shutter, exposure) = match self.physical {
+            Some(physical) => (physical.shutter, physical.exposure()),
+            None => (self.shutter, 1.0),
+        };
+        let ray_time = self.time + ray_time * shutter;
+
+        let hero = wavelengths.as_ref().map(|w| w.hero());
+        let aspect = width / height;
+        let (origin, direction, weight) =
+            self.projection.weighted_ray(screen, aspect, lens, hero)?;
+        let weight = match wavelengths.as_mut() {
+            // A dispersive projection already splits the wavelengths apart.
+            Some(wavelengths) if self.projection.dispersive() => {
+                wavelengths.terminate_secondary(Color::same(weight * exposure))
+            }
+            _ => Color::same(weight * exposure),
+        };
+        let to_world = |a: Vec3| self.u * a.x() + self.v * a.y() + self.w * a.z();
+        let mut ray = Ray::with_time(
+            self.center + to_world(origin),
+            to_world(direction),
+            ray_time,
+        );
+        ray.set_wavelengths(wavelengths);
+        Some((ray, weight))
+    }
+
+    fn sample_pixel(
+        &self,
+        i: i32,
+        j: i32,
+        world: &HittableList,
+        sampler: &mut dyn Sampler,
+        film: &mut Film,
+    ) {
+        // Takes the next sample of pixel i,j and adds it to the film.
+        sampler.start_pixel_sample(i, j, film.pixel(i, j).sample_count);
+        let offset = sampler.get_pixel_2d();
+        let mut aov = AovSample::new();
+        let Some((r, weight)) = self.get_ray(i, j, offset, sampler) else {
+            // Outside of the projected image: a black sample without hits.
+            film.add_sample(i, j, offset, Color::zero(), self.filter.as_ref());
+            if film.has_aovs() {
+                film.add_aov_sample(i, j, &aov);
+            }
+            return;
+        };
+        let values = self.camera_ray_color(&r, world, sampler, &mut aov) * weight;
+        aov.direct = aov.direct * weight;
+        aov.indirect = aov.indirect * weight;
+        let sample_color = match r.wavelengths() {
+            Some(wavelengths) => {
+                // Bring the values at the sampled wavelengths back to RGB.
+                aov.direct = wavelengths.to_rgb(aov.direct, self.working_space);
+                aov.indirect = wavelengths.to_rgb(aov.indirect, self.working_space);
+                wavelengths.to_rgb(values, self.working_space)
+            }
+            None => values,
+        };
+        film.add_sample(i, j, offset, sample_color, self.filter.as_ref());
+        if film.has_aovs() {
+            film.add_aov_sample(i, j, &aov);
+        }
+    }
+
+    fn render_pixel(
+        &self,
+        i: i32,
+        j: i32,
+        world: &HittableList,
+        sampler: &mut dyn Sampler,
+        film: &mut Film,
+        limit: i32,
+    ) {
+        // Samples pixel i,j until it has `limit` samples, or until it is done if that is
+        // earlier.
+        let limit = limit.min(self.max_samples_per_pixel());
+        match self.adaptive {
+            None => {
+                while film.pixel(i, j).sample_count < limit {
+                    self.sample_pixel(i, j, world, sampler, film);
+                }
+            }
+            Some(adaptive) => loop {
+                let count = film.pixel(i, j).sample_count;
+                if count >= limit
+                    || (count >= adaptive.min_samples
+                        && film.relative_error(i, j) < adaptive.threshold)
+                {
+                    break;
+                }
+                self.sample_pixel(i, j, world, sampler, film);
+            },
+        }
+    }
+
+    fn render_bounds(&self) -> (Range<i32>, Range<i32>) {
+        // Columns and rows to sample: the region and the pixels around it that the filter
+        // spreads samples into it from, so that it matches the same part of a full render.
+        let Some(region) = self.region else {
+            return (0..self.image_width, 0..self.image_height);
         };
-        let ray_direction = pixel_sample - ray_origin;
+        let (rx, ry) = self.filter.radius();
+        let (mx, my) = ((rx + 0.5).ceil() as i32, (ry + 0.5).ceil() as i32);
+        (
+            (region.x - mx).max(0)..(region.x + region.width + mx).min(self.image_width),
+            (region.y - my).max(0)..(region.y + region.height + my).min(self.image_height),
+        )
+    }
 
-        Ray::new(ray_origin, ray_direction)
+    fn max_samples_per_pixel(&self) -> i32 {
+        match self.adaptive {
+            None => self.samples_per_pixel,
+            Some(adaptive) => adaptive.max_samples,
+        }
+    }
+
+    fn settings(&self) -> String {
+        // Everything besides the scene that the film content depends on. The sample counts
+        // are left out so a finished render can be resumed with more samples.
+        format!(
+            "{}x{} max_depth={} sampler={:?} seed={} filter={:?} projection={:?} physical={:?} time={} shutter={} bounds={:?} lookfrom={:?} lookat={:?} vup={:?} background={:?} working_space={:?} spectral={} aov_buffers={}",
+            self.image_width,
+            self.image_height,
+            self.max_depth,
+            self.sampler,
+            self.seed,
+            self.filter,
+            self.projection,
+            self.physical,
+            self.time,
+            self.shutter,
+            self.render_bounds(),
+            self.lookfrom,
+            self.lookat,
+            self.vup,
+            self.background,
+            self.working_space,
+            self.spectral,
+            self.has_aov_buffers()
+        )
+    }
+
+    fn min_sample_count(&self, film: &Film) -> i32 {
+        // Fewest samples of the pixels that are rendered.
+        let (columns, rows) = self.render_bounds();
+        rows.flat_map(|j| columns.clone().map(move |i| (i, j)))
+            .map(|(i, j)| film.pixel(i, j).sample_count)
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn has_aov_buffers(&self) -> bool {
+        // The denoiser is guided by the AOVs even when none are written.
+        !self.aovs.is_empty() || self.denoise.is_some()
+    }
+
+    fn new_film(&self) -> Film {
+        let mut film = Film::new(self.image_width, self.image_height);
+        if self.has_aov_buffers() {
+            film.enable_aovs();
+        }
+        film
+    }
+
+    fn start_film(
+        &self,
+        world: &HittableList,
+    ) -> Result<(Film, Option<Checkpointer>), std::io::Error> {
+        // Returns the film to render into, resumed from the checkpoint if asked to.
+        let Some(checkpointing) = &self.checkpoint else {
+            return Ok((self.new_film(), None));
+        };
+        let checkpointer = Checkpointer::new(
+            checkpointing,
+            hash_str(&format!("{:?}", world)),
+            self.settings(),
+        );
+        if checkpointing.resume && Path::new(&checkpointing.path).exists() {
+            let film = checkpointer.resume()?;
+            println!(
+                "Resumed from {} ({} to {} spp)",
+                checkpointing.path,
+                self.min_sample_count(&film),
+                film.max_sample_count()
+            );
+            Ok((film, Some(checkpointer)))
+        } else {
+            if checkpointing.resume {
+                println!("No checkpoint at {}, starting over", checkpointing.path);
+            }
+            Ok((self.new_film(), Some(checkpointer)))
+        }
     }
 
     pub fn render(
@@ -177,23 +675,36 @@ impl Camera {
         world: &HittableList,
         image_path: &str,
         log_interval: i32,
-    ) -> Result<(), std::io::Error> {
+    ) -> Result<(Film, Vec<Color>), std::io::Error> {
+        // Renders the image and writes it to `image_path`, returning the film and the pixels
+        // written out.
+        let mut sampler = self.sampler.create(self.max_samples_per_pixel(), self.seed);
+        let (mut film, mut checkpointer) = self.start_film(world)?;
+        if let Some(adaptive) = self.adaptive {
+            self.render_adaptive(
+                world,
+                sampler.as_mut(),
+                &mut film,
+                &mut checkpointer,
+                adaptive,
+            )?;
+            if let Some(checkpointer) = &mut checkpointer {
+                checkpointer.save(&film)?;
+            }
+            let pixels = self.write_images(&film, image_path)?;
+            return Ok((film, pixels));
+        }
+        let (columns, rows) = self.render_bounds();
         let mut percentage = 0;
-        let mut s = String::from(&format!(
-            "P3\n{} {}\n255\n",
-            self.image_width, self.image_height
-        ));
-        for j in 0..self.image_height {
-            for i in 0..self.image_width {
-                let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-                for _ in 0..self.samples_per_pixel {
-                    let r = self.get_ray(i, j);
-                    pixel_color += Self::ray_color(&r, self.max_depth, world);
-                }
-                crate::color::write_color(&mut s, pixel_color, self.samples_per_pixel);
+        for j in rows.clone() {
+            for i in columns.clone() {
+                self.render_pixel(i, j, world, sampler.as_mut(), &mut film, i32::MAX);
+            }
+            if let Some(checkpointer) = &mut checkpointer {
+                checkpointer.tick(&film)?;
             }
             // Log
-            if j as f64 / self.image_height as f64 * 100.0 > percentage as f64 {
+            if (j - rows.start) as f64 / rows.len() as f64 * 100.0 > percentage as f64 {
                 percentage += 1;
                 if percentage % log_interval == 0 {
                     println!("{}% finished", percentage);
@@ -203,12 +714,309 @@ impl Camera {
         // Log
         println!("100% finished");
 
-        // Write
-        fs::write(image_path, s)?;
+        if let Some(checkpointer) = &mut checkpointer {
+            checkpointer.save(&film)?;
+        }
+        let pixels = self.write_images(&film, image_path)?;
+
+        Ok((film, pixels))
+    }
+
+    fn render_adaptive(
+        &self,
+        world: &HittableList,
+        sampler: &mut dyn Sampler,
+        film: &mut Film,
+        checkpointer: &mut Option<Checkpointer>,
+        adaptive: AdaptiveSampling,
+    ) -> Result<(), std::io::Error> {
+        // Spends samples_per_pixel samples per pixel on average. Every pixel first gets
+        // min_samples, then the rest of the budget goes in rounds to the pixels that are
+        // still noisy, the noisiest first, each up to max_samples.
+        let (columns, rows) = self.render_bounds();
+        let pixels: Vec<(i32, i32)> = rows
+            .flat_map(|j| columns.clone().map(move |i| (i, j)))
+            .collect();
+        let spent: i64 = pixels
+            .iter()
+            .map(|&(i, j)| film.pixel(i, j).sample_count as i64)
+            .sum();
+        let mut budget = self.samples_per_pixel as i64 * pixels.len() as i64 - spent;
+        let mut sample_to = |film: &mut Film, i: i32, j: i32, count: i32, budget: &mut i64| {
+            while film.pixel(i, j).sample_count < count {
+                self.sample_pixel(i, j, world, sampler, film);
+                *budget -= 1;
+            }
+        };
+        for &(i, j) in &pixels {
+            sample_to(film, i, j, adaptive.min_samples, &mut budget);
+            if let Some(checkpointer) = checkpointer {
+                checkpointer.tick(film)?;
+            }
+        }
+
+        let mut round = 0;
+        while budget > 0 {
+            let mut noisy: Vec<(f64, i32, i32)> = pixels
+                .iter()
+                .filter(|&&(i, j)| film.pixel(i, j).sample_count < adaptive.max_samples)
+                .map(|&(i, j)| (film.relative_error(i, j), i, j))
+                .filter(|&(error, _, _)| error >= adaptive.threshold)
+                .collect();
+            if noisy.is_empty() {
+                break;
+            }
+            noisy.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+            // An even share of the budget, in small enough steps that the errors are
+            // estimated again before the samples are spent.
+            let step = (budget / noisy.len() as i64).clamp(1, adaptive.min_samples.max(1) as i64);
+            for &(_, i, j) in &noisy {
+                if budget <= 0 {
+                    break;
+                }
+                let count = film.pixel(i, j).sample_count;
+                let target = (count as i64 + step).min(adaptive.max_samples as i64) as i32;
+                sample_to(film, i, j, target, &mut budget);
+                if let Some(checkpointer) = checkpointer {
+                    checkpointer.tick(film)?;
+                }
+            }
+            round += 1;
+            // Log
+            println!(
+                "Round {round} finished: {} noisy pixels, {} samples left",
+                noisy.len(),
+                budget.max(0)
+            );
+        }
+        // Log
+        println!(
+            "100% finished: {} to {} spp",
+            self.min_sample_count(film),
+            film.max_sample_count()
+        );
+        Ok(())
+    }
+
+    pub fn render_progressive(
+        &self,
+        world: &HittableList,
+        image_path: &str,
+        settings: Progressive,
+    ) -> Result<(Film, Vec<Color>), std::io::Error> {
+        // Renders the whole image in passes of `settings.samples_per_pass` samples per pixel,
+        // writing the image as it improves, until every pixel reached its sample count or
+        // the time budget ran out. Returns the film and the pixels last written out.
+        let start = Instant::now();
+        let mut last_snapshot = start;
+        let mut sampler = self.sampler.create(self.max_samples_per_pixel(), self.seed);
+        let (mut film, mut checkpointer) = self.start_film(world)?;
+        let max_samples = self.max_samples_per_pixel();
+        let samples_per_pass = settings.samples_per_pass.max(1);
+        let (columns, rows) = self.render_bounds();
+        let mut pass = self.min_sample_count(&film) / samples_per_pass;
+        let mut out_of_time = false;
+        let mut pixels = Vec::new();
+        while pass * samples_per_pass < max_samples && !out_of_time {
+            pass += 1;
+            let limit = pass * samples_per_pass;
+            for j in rows.clone() {
+                for i in columns.clone() {
+                    self.render_pixel(i, j, world, sampler.as_mut(), &mut film, limit);
+                }
+                if let Some(checkpointer) = &mut checkpointer {
+                    checkpointer.tick(&film)?;
+                }
+                if settings
+                    .time_budget
+                    .is_some_and(|budget| start.elapsed() >= budget)
+                {
+                    out_of_time = true;
+                    break;
+                }
+                if settings
+                    .snapshot_interval
+                    .is_some_and(|interval| last_snapshot.elapsed() >= interval)
+                {
+                    pixels = self.write_images(&film, image_path)?;
+                    last_snapshot = Instant::now();
+                }
+            }
+            // Log
+            println!(
+                "Pass {} finished: {} spp, {:.1}s",
+                pass,
+                limit.min(max_samples),
+                start.elapsed().as_secs_f64()
+            );
+            if settings.snapshot_interval.is_none() {
+                pixels = self.write_images(&film, image_path)?;
+            }
+        }
+        if out_of_time {
+            println!("Time budget reached");
+        }
+        if let Some(checkpointer) = &mut checkpointer {
+            checkpointer.save(&film)?;
+        }
+        if settings.snapshot_interval.is_some() || pixels.is_empty() {
+            pixels = self.write_images(&film, image_path)?;
+        }
+
+        Ok((film, pixels))
+    }
+
+    pub fn output_size(&self) -> (i32, i32) {
+        // Size of the written images, that of the region when it is cropped.
+        match self.region {
+            Some(region) if !region.canvas => (region.width, region.height),
+            _ => (self.image_width, self.image_height),
+        }
+    }
+
+    fn output<T: Copy>(&self, values: &[T], outside: T) -> Vec<T> {
+        // Values of the full image as written: only the region, or `outside` around it on
+        // the full canvas.
+        let Some(region) = self.region else {
+            return values.to_vec();
+        };
+        let (width, height) = self.output_size();
+        let (dx, dy) = if region.canvas {
+            (0, 0)
+        } else {
+            (region.x, region.y)
+        };
+        (0..height)
+            .flat_map(|j| (0..width).map(move |i| (i + dx, j + dy)))
+            .map(|(i, j)| match region.contains(i, j) {
+                true => values[(j * self.image_width + i) as usize],
+                false => outside,
+            })
+            .collect()
+    }
+
+    fn output_pixels(&self, film: &Film) -> Vec<Color> {
+        // The pixels written out, denoised if asked to.
+        match &self.denoise {
+            Some(denoising) => denoise::denoise(film, denoising),
+            None => film.pixel_colors(),
+        }
+    }
+
+    fn display_matrices(&self) -> (color::Matrix3, color::Matrix3) {
+        // The tone mapping operators work on linear sRGB, so pixels are converted to it
+        // from the working space before and to the display space after.
+        (
+            self.working_space.conversion_to(ColorSpace::LinearSrgb),
+            ColorSpace::LinearSrgb.conversion_to(self.display_space),
+        )
+    }
+
+    fn display_color(&self, matrices: &(color::Matrix3, color::Matrix3), c: Color) -> Color {
+        // A pixel tone mapped into the display space, as 8-bit output shows it.
+        let (to_srgb, to_display) = matrices;
+        let c = self.tone_mapping.apply(color::mul_matrix(to_srgb, c));
+        color::mul_matrix(to_display, c)
+    }
+
+    pub fn display_image(&self, pixels: &[Color]) -> Vec<[u8; 3]> {
+        // The 8-bit values of the pixels written out, row by row from the top, as in PNG and
+        // PPM output.
+        let matrices = self.display_matrices();
+        pixels
+            .iter()
+            .map(|&c| color::to_bytes(self.display_color(&matrices, c)))
+            .collect()
+    }
+
+    fn write_images(&self, film: &Film, image_path: &str) -> Result<Vec<Color>, std::io::Error> {
+        // The format follows the file extension: .pfm and .exr keep the linear radiance,
+        // .png and anything else are written as 8-bit PNG and PPM. Returns the pixels
+        // written out, so that video frames need not denoise them again.
+        let (width, height) = self.output_size();
+        let pixels = self.output(&self.output_pixels(film), Color::zero());
+        // A region on the full canvas is transparent around it in PNG and EXR output.
+        let coverage = self
+            .region
+            .filter(|region| region.canvas)
+            .map(|_| self.output(&vec![1.0; pixels.len()], 0.0));
+        let extension = Path::new(image_path).extension().and_then(|e| e.to_str());
+        let is_exr = extension == Some("exr");
+        match extension {
+            Some("pfm") => pfm::write_pfm(image_path, width, height, &pixels)?,
+            Some("exr") => {
+                let alpha = match &coverage {
+                    Some(coverage) => Some(coverage.clone()),
+                    None => self
+                        .exr_settings
+                        .alpha
+                        .then(|| vec![1.0; (width * height) as usize]),
+                };
+                let mut channels = exr::rgba_channels(&pixels, alpha.as_deref());
+                // AOVs go into the same file as extra layers.
+                for &aov in &self.aovs {
+                    let values = self.output(&film.aov_values(aov), Color::zero());
+                    channels.extend(aov::aov_channels(aov, &values));
+                }
+                exr::write_exr(
+                    image_path,
+                    width,
+                    height,
+                    &channels,
+                    &self.exr_settings,
+                    self.working_space,
+                )?
+            }
+            _ => {
+                let matrices = self.display_matrices();
+                let display = |i: i32, j: i32| {
+                    self.display_color(&matrices, pixels[(j * width + i) as usize])
+                };
+                if extension == Some("png") {
+                    png::write_png(image_path, width, height, coverage.as_deref(), display)?
+                } else {
+                    color::write_ppm(image_path, width, height, display)?
+                }
+            }
+        }
 
         // Log
         println!("Image saved");
 
-        Ok(())
+        if !is_exr && !self.aovs.is_empty() {
+            // Other formats get one PFM file per AOV next to the image.
+            for &aov in &self.aovs {
+                let path = aov_path(image_path, aov);
+                let values = self.output(&film.aov_values(aov), Color::zero());
+                if aov.is_scalar() {
+                    let values: Vec<f32> = values.iter().map(|c| c.x() as f32).collect();
+                    pfm::write_pfm_gray(&path, width, height, &values)?;
+                } else {
+                    pfm::write_pfm(&path, width, height, &values)?;
+                }
+            }
+            println!("AOVs saved");
+        }
+
+        if let Some(path) = &self.sample_count_image {
+            // Brighter pixels took more samples.
+            let counts: Vec<i32> = film.pixels().iter().map(|p| p.sample_count).collect();
+            let counts = self.output(&counts, 0);
+            let max_count = counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+            color::write_ppm(path, width, height, |i, j| {
+                Color::same(counts[(j * width + i) as usize] as f64 / max_count)
+            })?;
+            println!("Sample count image saved");
+        }
+
+        Ok(pixels)
     }
 }
+
+fn aov_path(image_path: &str, aov: Aov) -> String {
+    // "image.ppm" gets "image.albedo.pfm" and so on.
+    let path = Path::new(image_path);
+    path.with_extension(format!("{}.pfm", aov.name()))
+        .to_string_lossy()
+        .into_owned()
+}
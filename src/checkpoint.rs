@@ -0,0 +1,241 @@
+// Render checkpoints.
+//
+// A checkpoint holds everything needed to continue an interrupted render: the film (filter
+// weighted sums, per-pixel sample statistics and AOV sums), a hash of the scene and the render
+// settings. Samplers are deterministic functions of their seed, the pixel and the sample
+// index, so the seed in the settings together with the per-pixel sample counts is the
+// complete random number state.
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, Instant};
+
+use crate::aov::AovPixel;
+use crate::film::{Film, FilmPixel};
+use crate::Color;
+use crate::Vec3;
+
+const MAGIC: &[u8; 8] = b"RTCKPT02";
+
+#[derive(Debug, Clone)]
+pub struct Checkpointing {
+    pub path: String,
+    pub interval: Duration, // Time between two checkpoint writes
+    pub resume: bool,       // Continue from the checkpoint at `path` if there is one
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    // FNV-1a, stable across runs and platforms.
+    bytes.iter().fold(0xcbf29ce484222325, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn hash_str(s: &str) -> u64 {
+    hash_bytes(s.as_bytes())
+}
+
+pub struct Checkpoint {
+    pub scene_hash: u64,
+    pub settings: String,
+    pub film: Film,
+}
+
+impl Checkpoint {
+    pub fn save(path: &str, scene_hash: u64, settings: &str, film: &Film) -> Result<(), Error> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&scene_hash.to_le_bytes());
+        bytes.extend_from_slice(&(settings.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(settings.as_bytes());
+        bytes.extend_from_slice(&film.width.to_le_bytes());
+        bytes.extend_from_slice(&film.height.to_le_bytes());
+        for pixel in film.pixels() {
+            for v in [
+                pixel.rgb_sum.x(),
+                pixel.rgb_sum.y(),
+                pixel.rgb_sum.z(),
+                pixel.weight_sum,
+            ] {
+                bytes.extend_from_slice(&v.to_le_bytes());
+            }
+            bytes.extend_from_slice(&pixel.sample_count.to_le_bytes());
+            bytes.extend_from_slice(&pixel.luminance_mean.to_le_bytes());
+            bytes.extend_from_slice(&pixel.luminance_m2.to_le_bytes());
+        }
+        bytes.push(film.has_aovs() as u8);
+        for pixel in film.aov_pixels() {
+            bytes.extend_from_slice(&pixel.sample_count.to_le_bytes());
+            bytes.extend_from_slice(&pixel.hit_count.to_le_bytes());
+            for v in [
+                pixel.albedo_sum,
+                pixel.direct_sum,
+                pixel.indirect_sum,
+                pixel.normal_sum,
+                pixel.position_sum,
+            ] {
+                for c in [v.x(), v.y(), v.z()] {
+                    bytes.extend_from_slice(&c.to_le_bytes());
+                }
+            }
+            bytes.extend_from_slice(&pixel.depth_sum.to_le_bytes());
+            bytes.extend_from_slice(&pixel.object_id.to_le_bytes());
+            bytes.extend_from_slice(&pixel.material_id.to_le_bytes());
+        }
+
+        // Write next to the old checkpoint and swap, so a kill during the write does not
+        // lose the previous one.
+        let tmp_path = format!("{path}.tmp");
+        fs::write(&tmp_path, bytes)?;
+        fs::rename(&tmp_path, path)
+    }
+
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let bytes = fs::read(path)?;
+        let mut reader = Reader {
+            bytes: &bytes,
+            pos: 0,
+        };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid_data(format!("{path} is not a render checkpoint")));
+        }
+        let scene_hash = reader.u64()?;
+        let settings_len = reader.u64()? as usize;
+        let settings = String::from_utf8(reader.take(settings_len)?.to_vec())
+            .map_err(|_| invalid_data(format!("{path} has corrupt settings")))?;
+        let width = reader.i32()?;
+        let height = reader.i32()?;
+        if width <= 0 || height <= 0 {
+            return Err(invalid_data(format!("{path} has an invalid film size")));
+        }
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for _ in 0..width * height {
+            let rgb_sum = Color::new(reader.f64()?, reader.f64()?, reader.f64()?);
+            pixels.push(FilmPixel {
+                rgb_sum,
+                weight_sum: reader.f64()?,
+                sample_count: reader.i32()?,
+                luminance_mean: reader.f64()?,
+                luminance_m2: reader.f64()?,
+            });
+        }
+
+        let mut film = Film::from_pixels(width, height, pixels);
+        if reader.take(1)?[0] != 0 {
+            let mut aov_pixels = Vec::with_capacity((width * height) as usize);
+            for _ in 0..width * height {
+                aov_pixels.push(AovPixel {
+                    sample_count: reader.i32()?,
+                    hit_count: reader.i32()?,
+                    albedo_sum: reader.vec3()?,
+                    direct_sum: reader.vec3()?,
+                    indirect_sum: reader.vec3()?,
+                    normal_sum: reader.vec3()?,
+                    position_sum: reader.vec3()?,
+                    depth_sum: reader.f64()?,
+                    object_id: reader.i32()?,
+                    material_id: reader.i32()?,
+                });
+            }
+            film.set_aov_pixels(aov_pixels);
+        }
+
+        Ok(Self {
+            scene_hash,
+            settings,
+            film,
+        })
+    }
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() - self.pos < n {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "checkpoint is truncated",
+            ));
+        }
+        let s = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(s)
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, Error> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, Error> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3, Error> {
+        Ok(Vec3::new(self.f64()?, self.f64()?, self.f64()?))
+    }
+}
+
+// Writes checkpoints of a running render at most once per interval.
+pub struct Checkpointer {
+    path: String,
+    interval: Duration,
+    scene_hash: u64,
+    settings: String,
+    last_save: Instant,
+}
+
+impl Checkpointer {
+    pub fn new(checkpointing: &Checkpointing, scene_hash: u64, settings: String) -> Self {
+        Self {
+            path: checkpointing.path.clone(),
+            interval: checkpointing.interval,
+            scene_hash,
+            settings,
+            last_save: Instant::now(),
+        }
+    }
+
+    pub fn resume(&self) -> Result<Film, Error> {
+        let checkpoint = Checkpoint::load(&self.path)?;
+        if checkpoint.scene_hash != self.scene_hash {
+            return Err(invalid_data(format!(
+                "the scene changed since {} was written, refusing to resume",
+                self.path
+            )));
+        }
+        if checkpoint.settings != self.settings {
+            return Err(invalid_data(format!(
+                "the render settings changed since {} was written, refusing to resume\n  checkpoint: {}\n  current:    {}",
+                self.path, checkpoint.settings, self.settings
+            )));
+        }
+        Ok(checkpoint.film)
+    }
+
+    pub fn tick(&mut self, film: &Film) -> Result<(), Error> {
+        if self.last_save.elapsed() >= self.interval {
+            self.save(film)?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&mut self, film: &Film) -> Result<(), Error> {
+        Checkpoint::save(&self.path, self.scene_hash, &self.settings, film)?;
+        self.last_save = Instant::now();
+        // Log
+        println!("Checkpoint saved");
+        Ok(())
+    }
+}
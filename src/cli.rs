@@ -0,0 +1,407 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::aov::Aov;
+use crate::camera::{AdaptiveSampling, Progressive, Region};
+use crate::checkpoint::Checkpointing;
+use crate::color::ColorSpace;
+use crate::denoise::Denoising;
+use crate::exr::{Compression, ExrSettings, PixelType};
+use crate::filter::FilterKind;
+use crate::gif::Quantizer;
+use crate::sampler::SamplerType;
+use crate::tonemap::{ToneMapOperator, ToneMapping};
+use crate::video::VideoSettings;
+
+pub const USAGE: &str = "Usage: ray_tracing_1 [options]
+  --scene PATH               Scene file to render instead of the built-in random spheres
+  --output PATH              Image path (default image.ppm); .pfm and .exr are written
+                             as linear HDR, .png as an 8-bit PNG, anything else as an
+                             8-bit PPM
+  --width N                  Image width in pixels
+  --spp N                    Samples per pixel
+  --sampler KIND             Sample sequence: independent, stratified, halton or sobol
+                             (default)
+  --filter NAME[:RADIUS]     Reconstruction filter: box (default), tent, gaussian, mitchell
+                             or lanczos, with its radius in pixels (default 0.5, 1, 1.5,
+                             2 and 3)
+  --adaptive MIN,MAX,ERROR   Sample adaptively: every pixel gets MIN samples, then the
+                             rest of the --spp budget goes to the pixels whose relative
+                             error is above ERROR, up to MAX samples each
+  --sample-count-image PATH  Write the samples each pixel took as a grayscale PPM
+  --progressive              Render in passes over the whole image
+  --pass-spp N               Samples per pixel added by each pass (default 4)
+  --snapshot-interval SECS   Write the image every SECS seconds instead of every pass
+  --time-budget SECS         Stop rendering after SECS seconds
+  --checkpoint PATH          Save the render progress to PATH (default <output>.ckpt)
+  --checkpoint-interval SECS Time between two checkpoint saves (default 60)
+  --resume                   Continue the render saved in the checkpoint
+  --exr-float                Write 32-bit float instead of half EXR channels
+  --exr-compression KIND     EXR compression: zip (default) or none
+  --exr-alpha                Write an alpha channel to EXR files
+  --tonemap OPERATOR         Tone mapping of 8-bit output: linear (default), reinhard,
+                             hable or aces
+  --exposure EV              Exposure adjustment in stops before tone mapping
+  --white-point VALUE        Linear value mapped to white by reinhard and hable (default 4)
+  --working-space SPACE      Color space of the scene and HDR output: srgb (linear,
+                             default), acescg or p3 (linear Display P3)
+  --display-space SPACE      Color space of 8-bit output: srgb (default) or p3
+  --spectral                 Trace sampled wavelengths instead of RGB, for dispersion
+  --aovs LIST                Comma separated AOVs to write, or all: albedo, normal, depth,
+                             position, object_id, material_id, direct, indirect. They are
+                             layers of .exr output, separate .pfm files otherwise
+  --denoise                  Denoise the written image, guided by the AOVs
+  --denoise-iterations N     Passes of the denoising filter (default 5)
+  --frames FIRST-LAST        Render frames FIRST to LAST (or a single one) of the scene's
+                             animation, numbered into the output path as image_0001.png
+  --video PATH               Also encode the rendered frames into PATH: an animated .gif
+                             or an uncompressed .y4m video
+  --gif-palette KIND         Palette of GIF output: median_cut (default) or octree
+  --dither                   Dither GIF output to its palette
+  --region X,Y,WIDTH,HEIGHT  Render only this pixel rectangle of the image, from the top
+                             left, and write it cropped
+  --tile N/COLUMNSxROWS      Render only tile N (from 0, row by row) of a grid over the
+                             image, and write it cropped
+  --canvas                   Write a region or tile on the full image, transparent around
+                             it in .png and .exr output";
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub scene_path: Option<String>,
+    pub image_path: String,
+    pub image_width: Option<i32>,
+    pub samples_per_pixel: Option<i32>,
+    pub sampler: SamplerType,
+    pub filter: Option<(FilterKind, f64)>, // Kind and radius
+    pub adaptive: Option<AdaptiveSampling>,
+    pub sample_count_image: Option<String>,
+    pub progressive: Option<Progressive>,
+    pub checkpoint: Option<Checkpointing>,
+    pub exr_settings: ExrSettings,
+    pub tone_mapping: ToneMapping,
+    pub working_space: ColorSpace,
+    pub display_space: ColorSpace,
+    pub spectral: bool,
+    pub aovs: Vec<Aov>,
+    pub denoise: Option<Denoising>,
+    pub frames: Option<(i32, i32)>, // First and last frame, counted from 1
+    pub video: Option<VideoSettings>,
+    pub region: Option<Region>,
+    pub tile: Option<(i32, i32, i32)>, // Index, columns and rows
+    pub canvas: bool,                  // Place the region on the full image
+}
+
+fn parse_value<T: FromStr>(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> Result<T, String> {
+    let value = args
+        .next()
+        .ok_or_else(|| format!("missing value for {flag}"))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {flag}: {value}"))
+}
+
+fn parse_seconds(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<Duration, String> {
+    let seconds: f64 = parse_value(args, flag)?;
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("invalid value for {flag}: {seconds}"))
+}
+
+fn parse_color_space(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> Result<ColorSpace, String> {
+    let name: String = parse_value(args, flag)?;
+    match name.as_str() {
+        "srgb" => Ok(ColorSpace::LinearSrgb),
+        "acescg" => Ok(ColorSpace::AcesCg),
+        "p3" => Ok(ColorSpace::DisplayP3),
+        _ => Err(format!("invalid value for {flag}: {name}")),
+    }
+}
+
+fn parse_filter(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> Result<(FilterKind, f64), String> {
+    // NAME or NAME:RADIUS, such as mitchell:1.5.
+    let value: String = parse_value(args, flag)?;
+    let invalid = || format!("invalid value for {flag}: {value}");
+    let (name, radius) = match value.split_once(':') {
+        Some((name, radius)) => (name, Some(radius)),
+        None => (value.as_str(), None),
+    };
+    let kind = FilterKind::from_name(name).ok_or_else(invalid)?;
+    let radius = match radius {
+        Some(radius) => radius.parse::<f64>().map_err(|_| invalid())?,
+        None => kind.default_radius(),
+    };
+    if !(radius > 0.0 && radius.is_finite()) {
+        return Err(invalid());
+    }
+    Ok((kind, radius))
+}
+
+fn parse_adaptive(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> Result<AdaptiveSampling, String> {
+    // MIN,MAX,ERROR, such as 16,256,0.02.
+    let value: String = parse_value(args, flag)?;
+    let invalid = || format!("invalid value for {flag}: {value}");
+    let parts: Vec<&str> = value.split(',').collect();
+    let &[min_samples, max_samples, threshold] = parts.as_slice() else {
+        return Err(invalid());
+    };
+    let min_samples: i32 = min_samples.parse().map_err(|_| invalid())?;
+    let max_samples: i32 = max_samples.parse().map_err(|_| invalid())?;
+    let threshold: f64 = threshold.parse().map_err(|_| invalid())?;
+    if min_samples < 2 || max_samples < min_samples || threshold.is_nan() || threshold <= 0.0 {
+        return Err(invalid());
+    }
+    Ok(AdaptiveSampling {
+        min_samples,
+        max_samples,
+        threshold,
+    })
+}
+
+fn parse_aovs(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<Vec<Aov>, String> {
+    let list: String = parse_value(args, flag)?;
+    if list == "all" {
+        return Ok(Aov::ALL.to_vec());
+    }
+    list.split(',')
+        .map(|name| Aov::from_name(name).ok_or_else(|| format!("unknown AOV for {flag}: {name}")))
+        .collect()
+}
+
+fn parse_frames(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<(i32, i32), String> {
+    let range: String = parse_value(args, flag)?;
+    let invalid = || format!("invalid value for {flag}: {range}");
+    let (first, last) = range.split_once('-').unwrap_or((&range, &range));
+    let first: i32 = first.parse().map_err(|_| invalid())?;
+    let last: i32 = last.parse().map_err(|_| invalid())?;
+    if first < 1 || last < first {
+        return Err(invalid());
+    }
+    Ok((first, last))
+}
+
+fn parse_region(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<Region, String> {
+    let value: String = parse_value(args, flag)?;
+    let parts = value
+        .split(',')
+        .map(|part| part.parse::<i32>())
+        .collect::<Result<Vec<_>, _>>();
+    match parts.as_deref() {
+        Ok(&[x, y, width, height]) => Ok(Region::new(x, y, width, height)),
+        _ => Err(format!("invalid value for {flag}: {value}")),
+    }
+}
+
+fn parse_tile(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> Result<(i32, i32, i32), String> {
+    // N/COLUMNSxROWS, such as 5/4x4.
+    let value: String = parse_value(args, flag)?;
+    let invalid = || format!("invalid value for {flag}: {value}");
+    let (index, grid) = value.split_once('/').ok_or_else(invalid)?;
+    let (columns, rows) = grid.split_once('x').ok_or_else(invalid)?;
+    let index: i32 = index.parse().map_err(|_| invalid())?;
+    let columns: i32 = columns.parse().map_err(|_| invalid())?;
+    let rows: i32 = rows.parse().map_err(|_| invalid())?;
+    if columns < 1 || rows < 1 || index < 0 || index >= columns * rows {
+        return Err(invalid());
+    }
+    Ok((index, columns, rows))
+}
+
+pub fn frame_path(path: &str, frame: i32) -> String {
+    // "image.png" gets "image_0001.png" for frame 1.
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}_{frame:04}.{}", extension.to_string_lossy()),
+        None => format!("{stem}_{frame:04}"),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+impl Options {
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self {
+            scene_path: None,
+            image_path: String::from("image.ppm"),
+            image_width: None,
+            samples_per_pixel: None,
+            sampler: SamplerType::Sobol,
+            filter: None,
+            adaptive: None,
+            sample_count_image: None,
+            progressive: None,
+            checkpoint: None,
+            exr_settings: ExrSettings::default(),
+            tone_mapping: ToneMapping::default(),
+            working_space: ColorSpace::LinearSrgb,
+            display_space: ColorSpace::LinearSrgb,
+            spectral: false,
+            aovs: Vec::new(),
+            denoise: None,
+            frames: None,
+            video: None,
+            region: None,
+            tile: None,
+            canvas: false,
+        };
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--scene" => options.scene_path = Some(parse_value(&mut args, &arg)?),
+                "--output" => options.image_path = parse_value(&mut args, &arg)?,
+                "--width" => options.image_width = Some(parse_value(&mut args, &arg)?),
+                "--spp" => options.samples_per_pixel = Some(parse_value(&mut args, &arg)?),
+                "--sampler" => {
+                    let kind: String = parse_value(&mut args, &arg)?;
+                    options.sampler = match kind.as_str() {
+                        "independent" => SamplerType::Independent,
+                        "stratified" => SamplerType::Stratified,
+                        "halton" => SamplerType::Halton,
+                        "sobol" => SamplerType::Sobol,
+                        _ => return Err(format!("invalid value for {arg}: {kind}")),
+                    }
+                }
+                "--filter" => options.filter = Some(parse_filter(&mut args, &arg)?),
+                "--adaptive" => options.adaptive = Some(parse_adaptive(&mut args, &arg)?),
+                "--sample-count-image" => {
+                    options.sample_count_image = Some(parse_value(&mut args, &arg)?)
+                }
+                "--progressive" => {
+                    options.progressive();
+                }
+                "--pass-spp" => {
+                    options.progressive().samples_per_pass = parse_value(&mut args, &arg)?
+                }
+                "--snapshot-interval" => {
+                    options.progressive().snapshot_interval = Some(parse_seconds(&mut args, &arg)?)
+                }
+                "--time-budget" => {
+                    options.progressive().time_budget = Some(parse_seconds(&mut args, &arg)?)
+                }
+                "--checkpoint" => options.checkpoint().path = parse_value(&mut args, &arg)?,
+                "--checkpoint-interval" => {
+                    options.checkpoint().interval = parse_seconds(&mut args, &arg)?
+                }
+                "--resume" => options.checkpoint().resume = true,
+                "--exr-float" => options.exr_settings.pixel_type = PixelType::Float,
+                "--exr-compression" => {
+                    let kind: String = parse_value(&mut args, &arg)?;
+                    options.exr_settings.compression = match kind.as_str() {
+                        "zip" => Compression::Zip,
+                        "none" => Compression::None,
+                        _ => return Err(format!("invalid value for {arg}: {kind}")),
+                    }
+                }
+                "--exr-alpha" => options.exr_settings.alpha = true,
+                "--tonemap" => {
+                    let operator: String = parse_value(&mut args, &arg)?;
+                    options.tone_mapping.operator = match operator.as_str() {
+                        "linear" => ToneMapOperator::Linear,
+                        "reinhard" => ToneMapOperator::Reinhard,
+                        "hable" => ToneMapOperator::Hable,
+                        "aces" => ToneMapOperator::Aces,
+                        _ => return Err(format!("invalid value for {arg}: {operator}")),
+                    }
+                }
+                "--exposure" => options.tone_mapping.exposure = parse_value(&mut args, &arg)?,
+                "--white-point" => options.tone_mapping.white_point = parse_value(&mut args, &arg)?,
+                "--working-space" => options.working_space = parse_color_space(&mut args, &arg)?,
+                "--display-space" => {
+                    options.display_space = parse_color_space(&mut args, &arg)?;
+                    // ACEScg is a working space, not one displays use.
+                    if options.display_space == ColorSpace::AcesCg {
+                        return Err(format!("invalid value for {arg}: acescg"));
+                    }
+                }
+                "--spectral" => options.spectral = true,
+                "--aovs" => options.aovs = parse_aovs(&mut args, &arg)?,
+                "--denoise" => {
+                    options.denoise();
+                }
+                "--denoise-iterations" => {
+                    options.denoise().iterations = parse_value(&mut args, &arg)?
+                }
+                "--frames" => options.frames = Some(parse_frames(&mut args, &arg)?),
+                "--video" => {
+                    let path: String = parse_value(&mut args, &arg)?;
+                    if !VideoSettings::is_supported(&path) {
+                        return Err(format!("invalid value for {arg}: {path}"));
+                    }
+                    options.video().path = path;
+                }
+                "--gif-palette" => {
+                    let kind: String = parse_value(&mut args, &arg)?;
+                    options.video().quantizer = match kind.as_str() {
+                        "median_cut" => Quantizer::MedianCut,
+                        "octree" => Quantizer::Octree,
+                        _ => return Err(format!("invalid value for {arg}: {kind}")),
+                    }
+                }
+                "--dither" => options.video().dither = true,
+                "--region" => options.region = Some(parse_region(&mut args, &arg)?),
+                "--tile" => options.tile = Some(parse_tile(&mut args, &arg)?),
+                "--canvas" => options.canvas = true,
+                _ => return Err(format!("unknown option: {arg}\n{USAGE}")),
+            }
+        }
+        if let Some(checkpoint) = &mut options.checkpoint {
+            if checkpoint.path.is_empty() {
+                checkpoint.path = format!("{}.ckpt", options.image_path);
+            }
+        }
+        if options.region.is_some() && options.tile.is_some() {
+            return Err(String::from("--region and --tile cannot be combined"));
+        }
+        if options.canvas && options.region.is_none() && options.tile.is_none() {
+            return Err(String::from("--canvas needs --region or --tile"));
+        }
+        if options
+            .video
+            .as_ref()
+            .is_some_and(|video| video.path.is_empty())
+        {
+            return Err(String::from("--gif-palette and --dither need --video"));
+        }
+        Ok(options)
+    }
+
+    fn progressive(&mut self) -> &mut Progressive {
+        // Any progressive setting turns progressive rendering on.
+        self.progressive.get_or_insert(Progressive {
+            samples_per_pass: 4,
+            snapshot_interval: None,
+            time_budget: None,
+        })
+    }
+
+    fn checkpoint(&mut self) -> &mut Checkpointing {
+        // Any checkpoint setting turns checkpointing on.
+        self.checkpoint.get_or_insert(Checkpointing {
+            path: String::new(),
+            interval: Duration::from_secs(60),
+            resume: false,
+        })
+    }
+
+    fn video(&mut self) -> &mut VideoSettings {
+        self.video.get_or_insert(VideoSettings::default())
+    }
+
+    fn denoise(&mut self) -> &mut Denoising {
+        // Any denoise setting turns denoising on.
+        self.denoise.get_or_insert(Denoising::default())
+    }
+}
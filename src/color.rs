@@ -1,27 +1,187 @@
+use std::fs;
+use std::sync::OnceLock;
+
 use crate::Interval;
 use crate::Vec3;
 pub type Color = Vec3;
 
-fn linear_to_gamma(x: f64) -> f64 {
-    x.sqrt()
+pub type Matrix3 = [[f64; 3]; 3];
+
+// RGB color spaces. Linear sRGB is the default working space: colors in the scene, the
+// film and HDR output are linear values in the working space, and 8-bit output is
+// converted to a display space and encoded with the sRGB transfer function.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorSpace {
+    LinearSrgb, // Rec. 709 primaries, D65 white
+    AcesCg,     // ACES AP1 primaries, ACES (~D60) white
+    DisplayP3,  // DCI-P3 primaries, D65 white
+}
+
+impl ColorSpace {
+    pub fn rgb_to_xyz(&self) -> Matrix3 {
+        // RGB to CIE XYZ with a D65 white. ACEScg is chromatically adapted from its own
+        // white point with the Bradford transform.
+        match self {
+            ColorSpace::LinearSrgb => [
+                [0.4123908, 0.3575843, 0.1804808],
+                [0.2126390, 0.7151687, 0.0721923],
+                [0.0193308, 0.1191948, 0.9505322],
+            ],
+            ColorSpace::AcesCg => [
+                [0.6522375, 0.1282361, 0.1699822],
+                [0.2676722, 0.6743400, 0.0579878],
+                [-0.0053818, 0.0013691, 1.0930705],
+            ],
+            ColorSpace::DisplayP3 => [
+                [0.4865709, 0.2656677, 0.1982173],
+                [0.2289746, 0.6917385, 0.0792869],
+                [0.0000000, 0.0451134, 1.0439444],
+            ],
+        }
+    }
+
+    pub fn xyz_to_rgb(&self) -> Matrix3 {
+        invert_matrix(&self.rgb_to_xyz())
+    }
+
+    pub fn conversion_to(&self, target: ColorSpace) -> Matrix3 {
+        mul_matrices(&target.xyz_to_rgb(), &self.rgb_to_xyz())
+    }
+
+    pub fn luminance(&self, c: Color) -> f64 {
+        // Relative luminance, the Y row of the conversion to XYZ.
+        let [r, g, b] = self.rgb_to_xyz()[1];
+        r * c.x() + g * c.y() + b * c.z()
+    }
+
+    pub fn convert(&self, c: Color, target: ColorSpace) -> Color {
+        if *self == target {
+            return c;
+        }
+        mul_matrix(&self.conversion_to(target), c)
+    }
+
+    pub fn chromaticities(&self) -> [f64; 8] {
+        // Red, green, blue and white x, y chromaticity coordinates.
+        match self {
+            ColorSpace::LinearSrgb => [0.64, 0.33, 0.30, 0.60, 0.15, 0.06, 0.3127, 0.3290],
+            ColorSpace::AcesCg => [0.713, 0.293, 0.165, 0.830, 0.128, 0.044, 0.32168, 0.33767],
+            ColorSpace::DisplayP3 => [0.680, 0.320, 0.265, 0.690, 0.150, 0.060, 0.3127, 0.3290],
+        }
+    }
 }
 
-pub fn write_color(s: &mut String, pixel_color: Color, samples_per_pixel: i32) {
-    let scale = 1.0 / samples_per_pixel as f64;
+pub fn mul_matrix(m: &Matrix3, c: Color) -> Color {
+    Color::new(
+        m[0][0] * c.x() + m[0][1] * c.y() + m[0][2] * c.z(),
+        m[1][0] * c.x() + m[1][1] * c.y() + m[1][2] * c.z(),
+        m[2][0] * c.x() + m[2][1] * c.y() + m[2][2] * c.z(),
+    )
+}
 
-    let r = scale * pixel_color.x();
-    let g = scale * pixel_color.y();
-    let b = scale * pixel_color.z();
+pub fn mul_matrices(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
 
-    let r = linear_to_gamma(r);
-    let g = linear_to_gamma(g);
-    let b = linear_to_gamma(b);
+pub fn invert_matrix(m: &Matrix3) -> Matrix3 {
+    let [[a, b, c], [d, e, f], [g, h, i]] = *m;
+    let det = a * (e * i - f * h) - b * (d * i - f * g) + c * (d * h - e * g);
+    [
+        [
+            (e * i - f * h) / det,
+            (c * h - b * i) / det,
+            (b * f - c * e) / det,
+        ],
+        [
+            (f * g - d * i) / det,
+            (a * i - c * g) / det,
+            (c * d - a * f) / det,
+        ],
+        [
+            (d * h - e * g) / det,
+            (b * g - a * h) / det,
+            (a * e - b * d) / det,
+        ],
+    ]
+}
+
+pub fn srgb_encode(x: f64) -> f64 {
+    // The piecewise sRGB transfer function (OETF), linear to encoded.
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_decode(x: f64) -> f64 {
+    // Inverse of srgb_encode, encoded to linear.
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn to_bytes(pixel_color: Color) -> [u8; 3] {
+    // pixel_color is the display space value, tone mapped into [0, 1].
+    let r = pixel_color.x();
+    let g = pixel_color.y();
+    let b = pixel_color.z();
+
+    let r = srgb_encode(r);
+    let g = srgb_encode(g);
+    let b = srgb_encode(b);
 
     let intensity = Interval::new(0.0, 0.999);
 
-    let ir = (256.0 * intensity.clamp(r)) as i32;
-    let ig = (256.0 * intensity.clamp(g)) as i32;
-    let ib = (256.0 * intensity.clamp(b)) as i32;
+    let ir = (256.0 * intensity.clamp(r)) as u8;
+    let ig = (256.0 * intensity.clamp(g)) as u8;
+    let ib = (256.0 * intensity.clamp(b)) as u8;
 
+    [ir, ig, ib]
+}
+
+pub fn write_color(s: &mut String, pixel_color: Color) {
+    let [ir, ig, ib] = to_bytes(pixel_color);
     s.push_str(&format!("{ir} {ig} {ib}\n"));
 }
+
+// The working space of the render, which the colors that `luminance` weighs are in.
+static WORKING_SPACE: OnceLock<ColorSpace> = OnceLock::new();
+
+pub fn set_working_space(space: ColorSpace) {
+    // Called once, before the scene is loaded.
+    WORKING_SPACE
+        .set(space)
+        .expect("the working space is set only once");
+}
+
+pub fn luminance(c: Color) -> f64 {
+    // Relative luminance of a color in the working space, linear sRGB unless set.
+    match WORKING_SPACE.get() {
+        Some(space) => space.luminance(c),
+        None => ColorSpace::LinearSrgb.luminance(c),
+    }
+}
+
+pub fn write_ppm(
+    image_path: &str,
+    width: i32,
+    height: i32,
+    pixel_color: impl Fn(i32, i32) -> Color,
+) -> Result<(), std::io::Error> {
+    let mut s = String::from(&format!("P3\n{} {}\n255\n", width, height));
+    for j in 0..height {
+        for i in 0..width {
+            write_color(&mut s, pixel_color(i, j));
+        }
+    }
+    fs::write(image_path, s)
+}
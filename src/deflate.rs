@@ -0,0 +1,321 @@
+// Minimal zlib (RFC 1950) compressor.
+//
+// Finds repeated strings with a hash chain (LZ77) and codes them with the fixed Huffman
+// tables of deflate (RFC 1951). It compresses less than a full encoder but needs no tables
+// in the output and is plenty for image data, which is what the EXR and PNG writers use it
+// for.
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// Packs values starting at the least significant bit, as deflate and GIF's LZW do.
+pub struct BitWriter {
+    pub bytes: Vec<u8>,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    pub fn write_bits(&mut self, value: u32, count: u32) {
+        self.bit_buffer |= value << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.bytes.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn write_code(&mut self, code: u32, length: u32) {
+        // Huffman codes are stored starting at their most significant bit.
+        self.write_bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    pub fn flush(&mut self) {
+        if self.bit_count > 0 {
+            self.bytes.push(self.bit_buffer as u8);
+            self.bit_buffer = 0;
+            self.bit_count = 0;
+        }
+    }
+
+    fn write_literal(&mut self, symbol: u32) {
+        // Fixed literal/length code of RFC 1951 section 3.2.6.
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_match(&mut self, length: usize, distance: usize) {
+        let code = LENGTH_BASE
+            .iter()
+            .rposition(|&b| b as usize <= length)
+            .unwrap();
+        self.write_literal(257 + code as u32);
+        self.write_bits(
+            (length - LENGTH_BASE[code] as usize) as u32,
+            LENGTH_EXTRA[code] as u32,
+        );
+        let code = DIST_BASE
+            .iter()
+            .rposition(|&b| b as usize <= distance)
+            .unwrap();
+        self.write_code(code as u32, 5);
+        self.write_bits(
+            (distance - DIST_BASE[code] as usize) as u32,
+            DIST_EXTRA[code] as u32,
+        );
+    }
+}
+
+fn hash3(data: &[u8], i: usize) -> usize {
+    let v = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+    (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn insert_hash(data: &[u8], i: usize, head: &mut [usize], prev: &mut [usize]) {
+    if i + MIN_MATCH <= data.len() {
+        let h = hash3(data, i);
+        prev[i % WINDOW_SIZE] = head[h];
+        head[h] = i;
+    }
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new(vec![0x78, 0x01]);
+    // A single final block with fixed Huffman codes.
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+        if i + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash3(data, i)];
+            let max_length = MAX_MATCH.min(data.len() - i);
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = data[candidate..candidate + max_length]
+                    .iter()
+                    .zip(&data[i..i + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = i - candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            writer.write_match(best_length, best_distance);
+            for k in i..i + best_length {
+                insert_hash(data, k, &mut head, &mut prev);
+            }
+            i += best_length;
+        } else {
+            writer.write_literal(data[i] as u32);
+            insert_hash(data, i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+    writer.write_literal(256);
+    writer.flush();
+
+    writer.bytes.extend_from_slice(&adler32(data).to_be_bytes());
+    writer.bytes
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // Reads the least significant bits of each byte first, as deflate and GIF store them.
+    pub struct BitReader<'a> {
+        pub bytes: &'a [u8],
+        pub position: usize, // In bits
+    }
+
+    impl BitReader<'_> {
+        pub fn bits(&mut self, count: u32) -> u32 {
+            let mut value = 0;
+            for k in 0..count {
+                let byte = self.bytes[self.position / 8];
+                value |= ((byte >> (self.position % 8)) as u32 & 1) << k;
+                self.position += 1;
+            }
+            value
+        }
+
+        fn code(&mut self, length: u32) -> u32 {
+            // Huffman codes start at their most significant bit.
+            (0..length).fold(0, |code, _| code << 1 | self.bits(1))
+        }
+
+        fn fixed_literal(&mut self) -> u32 {
+            let code = self.code(7);
+            if code <= 0x17 {
+                return code + 256;
+            }
+            let code = code << 1 | self.bits(1);
+            match code {
+                0x30..=0xbf => code - 0x30,
+                0xc0..=0xc7 => code - 0xc0 + 280,
+                _ => (code << 1 | self.bits(1)) - 0x190 + 144,
+            }
+        }
+    }
+
+    // Decoder of zlib streams with stored and fixed Huffman blocks, which is what the
+    // compressor writes, following RFC 1950 and 1951 rather than the compressor's code.
+    pub fn zlib_decompress(stream: &[u8]) -> Vec<u8> {
+        assert_eq!(stream[0] & 0x0f, 8, "compression method");
+        assert_eq!(
+            (stream[0] as u32 * 256 + stream[1] as u32) % 31,
+            0,
+            "header check"
+        );
+        let mut reader = BitReader {
+            bytes: &stream[..stream.len() - 4],
+            position: 16,
+        };
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let last = reader.bits(1);
+            match reader.bits(2) {
+                0 => {
+                    reader.position = reader.position.div_ceil(8) * 8;
+                    let length = reader.bits(16) as usize;
+                    assert_eq!(reader.bits(16) as usize, !length & 0xffff);
+                    let start = reader.position / 8;
+                    out.extend_from_slice(&reader.bytes[start..start + length]);
+                    reader.position += 8 * length;
+                }
+                1 => loop {
+                    let symbol = reader.fixed_literal();
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256 {
+                        break;
+                    }
+                    let code = (symbol - 257) as usize;
+                    let length = LENGTH_BASE[code] as usize
+                        + reader.bits(LENGTH_EXTRA[code] as u32) as usize;
+                    let code = reader.code(5) as usize;
+                    let distance =
+                        DIST_BASE[code] as usize + reader.bits(DIST_EXTRA[code] as u32) as usize;
+                    assert!(distance <= out.len(), "distance before the start");
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                },
+                kind => panic!("unexpected block type {kind}"),
+            }
+            if last == 1 {
+                break;
+            }
+        }
+        let checksum = u32::from_be_bytes(stream[stream.len() - 4..].try_into().unwrap());
+        assert_eq!(checksum, adler32(&out), "Adler-32 of the decompressed data");
+        out
+    }
+
+    #[test]
+    fn adler32_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"a"), 0x00620062);
+        assert_eq!(adler32(b"abc"), 0x024d0127);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        // Long enough that the sums are reduced modulo 65521 on the way.
+        assert_eq!(adler32(&[0xff; 100_000]), 0x149a302c);
+    }
+
+    #[test]
+    fn zlib_round_trip() {
+        let mut random = 12345u32;
+        let noise: Vec<u8> = (0..5000)
+            .map(|_| {
+                random = random.wrapping_mul(1103515245).wrapping_add(12345);
+                (random >> 16) as u8
+            })
+            .collect();
+        let repeats: Vec<u8> = b"abcabcabd".iter().cycle().take(70_000).copied().collect();
+        let mut mixed = noise.clone();
+        mixed.extend_from_slice(&noise[1000..3000]); // A match far back in the window
+        mixed.extend([7; 1000]); // Overlapping matches of length 258
+        for data in [
+            &[][..],
+            b"a",
+            b"hello hello hello",
+            &noise,
+            &repeats,
+            &mixed,
+        ] {
+            assert_eq!(zlib_decompress(&zlib_compress(data)), data);
+        }
+    }
+
+    #[test]
+    fn zlib_compresses_repeats() {
+        let data = vec![42; 10_000];
+        assert!(zlib_compress(&data).len() < 100);
+    }
+}
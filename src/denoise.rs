@@ -0,0 +1,207 @@
+// Denoising of the film as a post-process, after SVGF (Schied et al. 2017, "Spatiotemporal
+// Variance-Guided Filtering"), without the temporal part.
+//
+// The albedo is divided out of the pixel colors so that textures are not blurred, and the
+// remaining illumination is smoothed by an edge-avoiding a-trous wavelet filter. The
+// filter is a 5x5 B3 spline kernel applied with growing holes between its taps, whose
+// weights fall off across changes in the normal and depth AOVs and across luminance
+// differences that are large compared to the estimated noise of the pixel.
+
+use crate::aov::Aov;
+use crate::color::luminance;
+use crate::film::Film;
+use crate::Color;
+use crate::Vec3;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Denoising {
+    pub iterations: i32, // Filter passes, each doubling the distance between taps
+    pub sigma_luminance: f64, // Luminance difference, in standard deviations, that is kept
+    pub sigma_normal: f64, // Exponent of the normal similarity, higher keeps more edges
+    pub sigma_depth: f64, // Depth difference, relative to the local slope, that is kept
+}
+
+impl Denoising {
+    pub fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_luminance: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 1.0,
+        }
+    }
+}
+
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// Guide buffers, row by row from the top.
+struct Guides {
+    width: i32,
+    height: i32,
+    normal: Vec<Vec3>,
+    depth: Vec<f64>,
+    depth_slope: Vec<f64>, // Largest depth change to a neighbouring pixel
+}
+
+impl Guides {
+    fn weight(&self, p: usize, q: usize, distance: f64, settings: &Denoising) -> f64 {
+        // Edge-stopping weight of the geometry between pixels p and q.
+        let (zp, zq) = (self.depth[p], self.depth[q]);
+        if zp.is_infinite() || zq.is_infinite() {
+            // Background only blends with background.
+            return if zp.is_infinite() && zq.is_infinite() {
+                1.0
+            } else {
+                0.0
+            };
+        }
+        let w_normal = self.normal[p]
+            .dot(&self.normal[q])
+            .max(0.0)
+            .powf(settings.sigma_normal);
+        let w_depth = (-(zp - zq).abs()
+            / (settings.sigma_depth * self.depth_slope[p] * distance + 1e-6))
+            .exp();
+        w_normal * w_depth
+    }
+}
+
+fn demodulation_albedo(albedo: Color) -> Color {
+    // Components too dark to divide by are left modulated.
+    let component = |a: f64| if a > 0.01 { a } else { 1.0 };
+    Color::new(
+        component(albedo.x()),
+        component(albedo.y()),
+        component(albedo.z()),
+    )
+}
+
+pub fn denoise(film: &Film, settings: &Denoising) -> Vec<Color> {
+    // Returns the denoised pixel colors, row by row from the top. The film must hold
+    // AOVs.
+    assert!(film.has_aovs(), "denoising needs the film AOVs");
+    let (width, height) = (film.width, film.height);
+    let index = |i: i32, j: i32| (j * width + i) as usize;
+
+    let albedo: Vec<Color> = film
+        .aov_values(Aov::Albedo)
+        .into_iter()
+        .map(demodulation_albedo)
+        .collect();
+    let normal = film.aov_values(Aov::Normal);
+    let depth: Vec<f64> = film.aov_values(Aov::Depth).iter().map(|c| c.x()).collect();
+    let mut depth_slope = vec![0.0; depth.len()];
+    for j in 0..height {
+        for i in 0..width {
+            let z = depth[index(i, j)];
+            for (di, dj) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let (ni, nj) = (i + di, j + dj);
+                if ni >= 0 && ni < width && nj >= 0 && nj < height {
+                    let dz = (depth[index(ni, nj)] - z).abs();
+                    if dz.is_finite() {
+                        depth_slope[index(i, j)] = f64::max(depth_slope[index(i, j)], dz);
+                    }
+                }
+            }
+        }
+    }
+    let guides = Guides {
+        width,
+        height,
+        normal,
+        depth,
+        depth_slope,
+    };
+
+    // Illumination and the variance of its luminance
+    let mut illumination: Vec<Color> = film
+        .pixel_colors()
+        .iter()
+        .zip(&albedo)
+        .map(|(&c, &a)| Color::new(c.x() / a.x(), c.y() / a.y(), c.z() / a.z()))
+        .collect();
+    let mut variance: Vec<f64> = film
+        .pixels()
+        .iter()
+        .zip(&albedo)
+        .map(|(pixel, &a)| {
+            if pixel.sample_count < 2 {
+                return 0.0;
+            }
+            let sample_variance = pixel.luminance_m2 / (pixel.sample_count - 1) as f64;
+            sample_variance / pixel.sample_count as f64 / luminance(a).powi(2)
+        })
+        .collect();
+
+    for iteration in 0..settings.iterations {
+        let step = 1 << iteration;
+        let mut next_illumination = illumination.clone();
+        let mut next_variance = variance.clone();
+        for j in 0..height {
+            for i in 0..width {
+                let p = index(i, j);
+                let sigma = blurred_variance(&variance, &guides, i, j).sqrt();
+                let lp = luminance(illumination[p]);
+
+                let mut color_sum = Color::zero();
+                let mut variance_sum = 0.0;
+                let mut weight_sum = 0.0;
+                for (dj, kj) in KERNEL.iter().enumerate() {
+                    for (di, ki) in KERNEL.iter().enumerate() {
+                        let qi = i + (di as i32 - 2) * step;
+                        let qj = j + (dj as i32 - 2) * step;
+                        if qi < 0 || qi >= width || qj < 0 || qj >= height {
+                            continue;
+                        }
+                        let q = index(qi, qj);
+                        let distance = (((qi - i).pow(2) + (qj - j).pow(2)) as f64).sqrt();
+                        let lq = luminance(illumination[q]);
+                        let w_luminance =
+                            (-(lp - lq).abs() / (settings.sigma_luminance * sigma + 1e-6)).exp();
+                        let weight = ki
+                            * kj
+                            * w_luminance
+                            * if q == p {
+                                1.0
+                            } else {
+                                guides.weight(p, q, distance, settings)
+                            };
+                        color_sum += illumination[q] * weight;
+                        variance_sum += variance[q] * weight * weight;
+                        weight_sum += weight;
+                    }
+                }
+                // The center tap always has a positive weight.
+                next_illumination[p] = color_sum / weight_sum;
+                next_variance[p] = variance_sum / (weight_sum * weight_sum);
+            }
+        }
+        illumination = next_illumination;
+        variance = next_variance;
+    }
+
+    illumination
+        .iter()
+        .zip(&albedo)
+        .map(|(&c, &a)| c * a)
+        .collect()
+}
+
+fn blurred_variance(variance: &[f64], guides: &Guides, i: i32, j: i32) -> f64 {
+    // 3x3 Gaussian blur of the variance, which is too noisy to use per pixel.
+    let mut sum = 0.0;
+    let mut weight_sum = 0.0;
+    for dj in -1..=1 {
+        for di in -1..=1 {
+            let (qi, qj) = (i + di, j + dj);
+            if qi < 0 || qi >= guides.width || qj < 0 || qj >= guides.height {
+                continue;
+            }
+            let weight =
+                [0.25, 0.5, 0.25][(di + 1) as usize] * [0.25, 0.5, 0.25][(dj + 1) as usize];
+            sum += variance[(qj * guides.width + qi) as usize] * weight;
+            weight_sum += weight;
+        }
+    }
+    sum / weight_sum
+}
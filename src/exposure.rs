@@ -0,0 +1,44 @@
+// Exposure of a physical camera from its aperture, shutter and sensor sensitivity, for
+// scenes lit in photometric units: emitters and the sky in nits (cd/m^2), which the film
+// then records scaled to the exposure.
+
+use crate::utils::degrees_to_radians;
+
+// Calibration constant of the saturation based ISO speed (ISO 12232) over the lens
+// transmittance of 0.65: an exposure saturates the sensor at luminance 78 N^2 / (0.65 t S).
+const SATURATION: f64 = 78.0 / 0.65;
+
+// Lumens per watt of light at 555 nm, the most efficient a light source can be.
+pub const LUMINOUS_EFFICACY: f64 = 683.0;
+
+#[derive(Debug, Copy, Clone)]
+pub struct PhysicalCamera {
+    pub f_number: f64,      // Focal length over the aperture diameter
+    pub shutter: f64,       // Time the shutter is open in seconds
+    pub iso: f64,           // Sensitivity of the sensor
+    pub sensor_height: f64, // Height of the sensor in meters, 24 mm for full frame
+}
+
+impl PhysicalCamera {
+    pub fn new(f_number: f64, shutter: f64, iso: f64) -> Self {
+        Self {
+            f_number,
+            shutter,
+            iso,
+            sensor_height: 0.024,
+        }
+    }
+
+    pub fn exposure(&self) -> f64 {
+        // Scale of the film from luminance, so that 1 is where the sensor saturates.
+        self.shutter * self.iso / (SATURATION * self.f_number * self.f_number)
+    }
+
+    pub fn defocus_angle(&self, vfov: f64, focus_dist: f64) -> f64 {
+        // The defocus angle of a thin lens with the focal length that gives `vfov` on the
+        // sensor, opened to the f-number, in degrees. Scene units are taken to be meters.
+        let focal_length = self.sensor_height / 2.0 / (degrees_to_radians(vfov) / 2.0).tan();
+        let aperture_radius = focal_length / self.f_number / 2.0;
+        2.0 * (aperture_radius / focus_dist).atan().to_degrees()
+    }
+}
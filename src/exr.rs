@@ -0,0 +1,432 @@
+// OpenEXR writer for scanline images, uncompressed or ZIP compressed, with half or float
+// channels.
+
+use std::fs;
+
+use crate::color::ColorSpace;
+use crate::deflate::zlib_compress;
+use crate::Color;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PixelType {
+    Half,
+    Float,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Compression {
+    None,
+    Zip,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ExrSettings {
+    pub pixel_type: PixelType,
+    pub compression: Compression,
+    pub alpha: bool, // Write an A channel next to R, G and B
+}
+
+impl ExrSettings {
+    pub fn default() -> Self {
+        Self {
+            pixel_type: PixelType::Half,
+            compression: Compression::Zip,
+            alpha: false,
+        }
+    }
+}
+
+pub struct Channel {
+    pub name: String,
+    pub values: Vec<f32>, // One value per pixel, row by row from the top
+    pub exact: bool,      // Always stored as 32-bit float, for values such as IDs
+}
+
+impl Channel {
+    fn pixel_type(&self, settings: &ExrSettings) -> PixelType {
+        if self.exact {
+            PixelType::Float
+        } else {
+            settings.pixel_type
+        }
+    }
+}
+
+pub fn rgba_channels(pixels: &[Color], alpha: Option<&[f32]>) -> Vec<Channel> {
+    let mut channels = vec![
+        Channel {
+            name: String::from("R"),
+            values: pixels.iter().map(|c| c.x() as f32).collect(),
+            exact: false,
+        },
+        Channel {
+            name: String::from("G"),
+            values: pixels.iter().map(|c| c.y() as f32).collect(),
+            exact: false,
+        },
+        Channel {
+            name: String::from("B"),
+            values: pixels.iter().map(|c| c.z() as f32).collect(),
+            exact: false,
+        },
+    ];
+    if let Some(alpha) = alpha {
+        channels.push(Channel {
+            name: String::from("A"),
+            values: alpha.to_vec(),
+            exact: false,
+        });
+    }
+    channels
+}
+
+pub fn f32_to_half(value: f32) -> u16 {
+    // Rounds to the nearest half, overflowing to infinity.
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7fffff;
+
+    if exponent == 0xff {
+        // Infinity or NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal half, or zero
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x800000;
+        let shift = (14 - exponent) as u32;
+        let half_mantissa = mantissa >> shift;
+        let round_bit = 1 << (shift - 1);
+        let rounded = if mantissa & round_bit != 0 && mantissa & (3 * round_bit - 1) != 0 {
+            half_mantissa + 1
+        } else {
+            half_mantissa
+        };
+        return sign | rounded as u16;
+    }
+    let half = sign as u32 | (exponent as u32) << 10 | mantissa >> 13;
+    // Round to nearest even; a carry into the exponent is still correct.
+    let rounded = if mantissa & 0x1000 != 0 && mantissa & 0x2fff != 0 {
+        half + 1
+    } else {
+        half
+    };
+    rounded as u16
+}
+
+fn write_attribute(bytes: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    bytes.extend_from_slice(name.as_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(kind.as_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    bytes.extend_from_slice(value);
+}
+
+fn i32s(values: &[i32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn zip_block(raw: &[u8]) -> Vec<u8> {
+    // Splits the bytes into two interleaved halves and delta codes them before zlib, as
+    // OpenEXR expects.
+    let mut tmp: Vec<u8> = Vec::with_capacity(raw.len());
+    tmp.extend(raw.iter().step_by(2));
+    tmp.extend(raw.iter().skip(1).step_by(2));
+    let mut previous = tmp[0];
+    for v in tmp.iter_mut().skip(1) {
+        let current = *v;
+        *v = current.wrapping_sub(previous).wrapping_add(128);
+        previous = current;
+    }
+    zlib_compress(&tmp)
+}
+
+pub fn write_exr(
+    image_path: &str,
+    width: i32,
+    height: i32,
+    channels: &[Channel],
+    settings: &ExrSettings,
+    color_space: ColorSpace,
+) -> Result<(), std::io::Error> {
+    // Channels have to be listed, and stored, in alphabetical order.
+    let mut channels: Vec<&Channel> = channels.iter().collect();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut bytes = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+
+    // Header
+    let mut channel_list = Vec::new();
+    for channel in &channels {
+        channel_list.extend_from_slice(channel.name.as_bytes());
+        channel_list.push(0);
+        let pixel_type: i32 = match channel.pixel_type(settings) {
+            PixelType::Half => 1,
+            PixelType::Float => 2,
+        };
+        channel_list.extend_from_slice(&pixel_type.to_le_bytes());
+        channel_list.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+        channel_list.extend_from_slice(&i32s(&[1, 1])); // x and y sampling
+    }
+    channel_list.push(0);
+    write_attribute(&mut bytes, "channels", "chlist", &channel_list);
+    let chromaticities: Vec<u8> = color_space
+        .chromaticities()
+        .iter()
+        .flat_map(|&v| (v as f32).to_le_bytes())
+        .collect();
+    write_attribute(
+        &mut bytes,
+        "chromaticities",
+        "chromaticities",
+        &chromaticities,
+    );
+    let (compression, lines_per_block) = match settings.compression {
+        Compression::None => (0, 1),
+        Compression::Zip => (3, 16),
+    };
+    write_attribute(&mut bytes, "compression", "compression", &[compression]);
+    let window = i32s(&[0, 0, width - 1, height - 1]);
+    write_attribute(&mut bytes, "dataWindow", "box2i", &window);
+    write_attribute(&mut bytes, "displayWindow", "box2i", &window);
+    write_attribute(&mut bytes, "lineOrder", "lineOrder", &[0]);
+    write_attribute(
+        &mut bytes,
+        "pixelAspectRatio",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    write_attribute(&mut bytes, "screenWindowCenter", "v2f", &[0; 8]);
+    write_attribute(
+        &mut bytes,
+        "screenWindowWidth",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    bytes.push(0);
+
+    // Scanline blocks, after a table of their file offsets
+    let block_count = (height + lines_per_block - 1) / lines_per_block;
+    let mut blocks = Vec::new();
+    for block in 0..block_count {
+        let y0 = block * lines_per_block;
+        let mut raw = Vec::new();
+        for j in y0..(y0 + lines_per_block).min(height) {
+            for channel in &channels {
+                let row = &channel.values[(j * width) as usize..((j + 1) * width) as usize];
+                for &v in row {
+                    match channel.pixel_type(settings) {
+                        PixelType::Half => raw.extend_from_slice(&f32_to_half(v).to_le_bytes()),
+                        PixelType::Float => raw.extend_from_slice(&v.to_le_bytes()),
+                    }
+                }
+            }
+        }
+        let data = match settings.compression {
+            Compression::None => raw,
+            Compression::Zip => {
+                // Blocks that do not shrink are stored as they are.
+                let compressed = zip_block(&raw);
+                if compressed.len() < raw.len() {
+                    compressed
+                } else {
+                    raw
+                }
+            }
+        };
+        let mut chunk = i32s(&[y0, data.len() as i32]);
+        chunk.extend_from_slice(&data);
+        blocks.push(chunk);
+    }
+
+    let mut offset = (bytes.len() + 8 * blocks.len()) as u64;
+    for block in &blocks {
+        bytes.extend_from_slice(&offset.to_le_bytes());
+        offset += block.len() as u64;
+    }
+    for block in blocks {
+        bytes.extend_from_slice(&block);
+    }
+
+    fs::write(image_path, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::tests::zlib_decompress;
+
+    fn written(
+        name: &str,
+        width: i32,
+        height: i32,
+        channels: &[Channel],
+        settings: &ExrSettings,
+    ) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("ray_tracing_exr_{name}.exr"));
+        let path = path.to_str().unwrap();
+        write_exr(
+            path,
+            width,
+            height,
+            channels,
+            settings,
+            ColorSpace::LinearSrgb,
+        )
+        .unwrap();
+        let bytes = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        bytes
+    }
+
+    fn header_end(bytes: &[u8]) -> usize {
+        // Skips the attributes, name, type, size and value each, up to the empty name.
+        let mut position = 8;
+        while bytes[position] != 0 {
+            for _ in 0..2 {
+                position += bytes[position..].iter().position(|&b| b == 0).unwrap() + 1;
+            }
+            let size = i32::from_le_bytes(bytes[position..position + 4].try_into().unwrap());
+            position += 4 + size as usize;
+        }
+        position + 1
+    }
+
+    #[test]
+    fn half_conversion() {
+        assert_eq!(f32_to_half(0.0), 0x0000);
+        assert_eq!(f32_to_half(-0.0), 0x8000);
+        assert_eq!(f32_to_half(1.0), 0x3c00);
+        assert_eq!(f32_to_half(-2.0), 0xc000);
+        assert_eq!(f32_to_half(0.333_333_34), 0x3555);
+        assert_eq!(f32_to_half(65504.0), 0x7bff); // Largest half
+        assert_eq!(f32_to_half(2.0f32.powi(-14)), 0x0400); // Smallest normal half
+    }
+
+    #[test]
+    fn half_infinity_and_nan() {
+        assert_eq!(f32_to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_half(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f32_to_half(65520.0), 0x7c00); // Rounds up past the largest half
+        assert_eq!(f32_to_half(1e10), 0x7c00);
+        assert_eq!(f32_to_half(-1e10), 0xfc00);
+        let nan = f32_to_half(f32::NAN);
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x03ff, 0);
+    }
+
+    #[test]
+    fn half_denormals() {
+        let tiny = 2.0f32.powi(-24); // Smallest subnormal half
+        assert_eq!(f32_to_half(tiny), 0x0001);
+        assert_eq!(f32_to_half(-tiny), 0x8001);
+        assert_eq!(f32_to_half(1023.0 * tiny), 0x03ff); // Largest subnormal
+        assert_eq!(f32_to_half(2.0f32.powi(-26)), 0x0000);
+        assert_eq!(f32_to_half(f32::from_bits(1)), 0x0000); // Subnormal float
+                                                            // Halfway cases round to even.
+        assert_eq!(f32_to_half(0.5 * tiny), 0x0000);
+        assert_eq!(f32_to_half(1.5 * tiny), 0x0002);
+        assert_eq!(f32_to_half(2.5 * tiny), 0x0002);
+        assert_eq!(f32_to_half(0.500_001 * tiny), 0x0001);
+        // Rounding up from the largest subnormal gives the smallest normal.
+        assert_eq!(f32_to_half(1023.75 * tiny), 0x0400);
+    }
+
+    #[test]
+    fn half_rounding() {
+        let ulp = 2.0f32.powi(-10); // Spacing of halves in [1, 2)
+        assert_eq!(f32_to_half(1.0 + 0.5 * ulp), 0x3c00); // Tie, to even
+        assert_eq!(f32_to_half(1.0 + 1.5 * ulp), 0x3c02); // Tie, to even
+        assert_eq!(f32_to_half(1.0 + 0.500_1 * ulp), 0x3c01);
+        assert_eq!(f32_to_half(1.0 + 0.499_9 * ulp), 0x3c00);
+        assert_eq!(f32_to_half(2.0 - 0.25 * ulp), 0x4000); // Carries into the exponent
+    }
+
+    #[test]
+    fn uncompressed_golden_file() {
+        // Header, offset table and the single scanline of a 2x1 half RGB image, written
+        // from the OpenEXR file layout documentation.
+        let pixels = [
+            Color::new(0.0, 0.5, 65504.0),
+            Color::new(1.0, -2.0, 2.0f64.powi(-24)),
+        ];
+        let settings = ExrSettings {
+            pixel_type: PixelType::Half,
+            compression: Compression::None,
+            alpha: false,
+        };
+        let bytes = written("golden", 2, 1, &rgba_channels(&pixels, None), &settings);
+        assert_eq!(bytes, include_bytes!("../tests/data/rgb_2x1_half.exr"));
+    }
+
+    #[test]
+    fn zip_blocks_and_offsets() {
+        // 20 rows make a full block of 16 and one of 4.
+        let (width, height) = (3, 20);
+        let values: Vec<f32> = (0..width * height).map(|k| k as f32 * 0.25).collect();
+        let channels = [
+            Channel {
+                name: String::from("Z"),
+                values: values.clone(),
+                exact: true,
+            },
+            Channel {
+                name: String::from("Y"),
+                values: values.iter().map(|v| -v).collect(),
+                exact: false,
+            },
+        ];
+        let bytes = written("zip", width, height, &channels, &ExrSettings::default());
+
+        let end = header_end(&bytes);
+        let offset = |k: usize| {
+            u64::from_le_bytes(bytes[end + 8 * k..end + 8 * k + 8].try_into().unwrap()) as usize
+        };
+        let i32_at = |p: usize| i32::from_le_bytes(bytes[p..p + 4].try_into().unwrap());
+        assert_eq!(offset(0), end + 16);
+        for (k, y0) in [(0, 0), (1, 16)] {
+            let start = offset(k);
+            assert_eq!(i32_at(start), y0);
+            let size = i32_at(start + 4) as usize;
+            let next = if k == 0 { offset(1) } else { bytes.len() };
+            assert_eq!(start + 8 + size, next);
+
+            // Undo the compression, the delta coding and the interleaving.
+            let mut data = zlib_decompress(&bytes[start + 8..next]);
+            for p in 1..data.len() {
+                data[p] = data[p].wrapping_add(data[p - 1]).wrapping_sub(128);
+            }
+            let half = data.len().div_ceil(2);
+            let raw: Vec<u8> = (0..data.len())
+                .map(|p| {
+                    if p % 2 == 0 {
+                        data[p / 2]
+                    } else {
+                        data[half + p / 2]
+                    }
+                })
+                .collect();
+
+            // Rows hold Y as half, then Z as float.
+            let mut expected = Vec::new();
+            for j in y0..(y0 + 16).min(height) {
+                let row = &values[(j * width) as usize..((j + 1) * width) as usize];
+                for v in row {
+                    expected.extend_from_slice(&f32_to_half(-v).to_le_bytes());
+                }
+                for v in row {
+                    expected.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+            assert_eq!(raw, expected);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use crate::aov::{Aov, AovPixel, AovSample};
+use crate::color::luminance;
+use crate::filter::Filter;
+use crate::utils;
+use crate::Color;
+
+// Pixels with a smaller weight sum are black. Filters with negative lobes can leave a sum
+// near zero, which the division would blow up.
+const MIN_WEIGHT_SUM: f64 = 1e-3;
+
+#[derive(Debug, Copy, Clone)]
+pub struct FilmPixel {
+    pub rgb_sum: Color,  // Sum of the filter weighted samples
+    pub weight_sum: f64, // Sum of the filter weights
+
+    // Statistics of the samples taken inside this pixel, before filtering
+    pub sample_count: i32,
+    pub luminance_mean: f64,
+    pub luminance_m2: f64, // Sum of squared differences from the mean (Welford)
+}
+
+// Accumulation buffer of the rendered image.
+#[derive(Debug, Clone)]
+pub struct Film {
+    pub width: i32,
+    pub height: i32,
+    pixels: Vec<FilmPixel>,
+    aov_pixels: Vec<AovPixel>, // Empty unless AOVs are enabled
+}
+
+impl Film {
+    pub fn new(width: i32, height: i32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![
+                FilmPixel {
+                    rgb_sum: Color::zero(),
+                    weight_sum: 0.0,
+                    sample_count: 0,
+                    luminance_mean: 0.0,
+                    luminance_m2: 0.0,
+                };
+                (width * height) as usize
+            ],
+            aov_pixels: Vec::new(),
+        }
+    }
+
+    pub fn from_pixels(width: i32, height: i32, pixels: Vec<FilmPixel>) -> Self {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        Self {
+            width,
+            height,
+            pixels,
+            aov_pixels: Vec::new(),
+        }
+    }
+
+    pub fn enable_aovs(&mut self) {
+        self.aov_pixels = vec![AovPixel::new(); self.pixels.len()];
+    }
+
+    pub fn set_aov_pixels(&mut self, aov_pixels: Vec<AovPixel>) {
+        assert_eq!(aov_pixels.len(), self.pixels.len());
+        self.aov_pixels = aov_pixels;
+    }
+
+    pub fn has_aovs(&self) -> bool {
+        !self.aov_pixels.is_empty()
+    }
+
+    pub fn pixels(&self) -> &[FilmPixel] {
+        &self.pixels
+    }
+
+    pub fn aov_pixels(&self) -> &[AovPixel] {
+        &self.aov_pixels
+    }
+
+    fn index(&self, i: i32, j: i32) -> usize {
+        (j * self.width + i) as usize
+    }
+
+    pub fn pixel(&self, i: i32, j: i32) -> &FilmPixel {
+        &self.pixels[self.index(i, j)]
+    }
+
+    pub fn add_sample(
+        &mut self,
+        i: i32,
+        j: i32,
+        offset: (f64, f64),
+        color: Color,
+        filter: &dyn Filter,
+    ) {
+        // Records a sample taken at `offset` inside pixel (i, j) and splats it into every
+        // pixel whose center lies within the filter radius.
+        let index = self.index(i, j);
+        let pixel = &mut self.pixels[index];
+        pixel.sample_count += 1;
+        let y = luminance(color);
+        let delta = y - pixel.luminance_mean;
+        pixel.luminance_mean += delta / pixel.sample_count as f64;
+        pixel.luminance_m2 += delta * (y - pixel.luminance_mean);
+
+        self.splat(i as f64 + offset.0, j as f64 + offset.1, color, filter);
+    }
+
+    fn splat(&mut self, x: f64, y: f64, color: Color, filter: &dyn Filter) {
+        // Pixel (i, j) has its center at (i + 0.5, j + 0.5).
+        let (rx, ry) = filter.radius();
+        let i0 = ((x - 0.5 - rx).ceil() as i32).max(0);
+        let i1 = ((x - 0.5 + rx).floor() as i32).min(self.width - 1);
+        let j0 = ((y - 0.5 - ry).ceil() as i32).max(0);
+        let j1 = ((y - 0.5 + ry).floor() as i32).min(self.height - 1);
+        for j in j0..=j1 {
+            for i in i0..=i1 {
+                let weight = filter.evaluate(i as f64 + 0.5 - x, j as f64 + 0.5 - y);
+                if weight == 0.0 {
+                    continue;
+                }
+                let index = self.index(i, j);
+                let pixel = &mut self.pixels[index];
+                pixel.rgb_sum += color * weight;
+                pixel.weight_sum += weight;
+            }
+        }
+    }
+
+    pub fn add_aov_sample(&mut self, i: i32, j: i32, sample: &AovSample) {
+        let index = self.index(i, j);
+        self.aov_pixels[index].add(sample);
+    }
+
+    pub fn pixel_color(&self, i: i32, j: i32) -> Color {
+        // Negative lobes can also push a channel below zero, which is clamped.
+        let pixel = self.pixel(i, j);
+        if pixel.weight_sum <= MIN_WEIGHT_SUM {
+            return Color::zero();
+        }
+        let c = pixel.rgb_sum / pixel.weight_sum;
+        Color::new(c.x().max(0.0), c.y().max(0.0), c.z().max(0.0))
+    }
+
+    pub fn pixel_colors(&self) -> Vec<Color> {
+        // Returns all pixel colors, row by row from the top.
+        (0..self.height)
+            .flat_map(|j| (0..self.width).map(move |i| (i, j)))
+            .map(|(i, j)| self.pixel_color(i, j))
+            .collect()
+    }
+
+    pub fn aov_values(&self, aov: Aov) -> Vec<Color> {
+        // Returns the AOV for all pixels, row by row from the top.
+        self.aov_pixels.iter().map(|p| p.value(aov)).collect()
+    }
+
+    pub fn relative_error(&self, i: i32, j: i32) -> f64 {
+        // Standard error of the pixel mean relative to the mean. Dark pixels are measured
+        // against a small floor so that noise in near-black areas does not dominate.
+        let pixel = self.pixel(i, j);
+        if pixel.sample_count < 2 {
+            return utils::INF;
+        }
+        let variance = pixel.luminance_m2 / (pixel.sample_count - 1) as f64;
+        (variance / pixel.sample_count as f64).sqrt() / pixel.luminance_mean.max(0.01)
+    }
+
+    #[allow(dead_code)]
+    pub fn min_sample_count(&self) -> i32 {
+        self.pixels
+            .iter()
+            .map(|p| p.sample_count)
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn max_sample_count(&self) -> i32 {
+        self.pixels
+            .iter()
+            .map(|p| p.sample_count)
+            .max()
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,231 @@
+// Pixel reconstruction filters.
+//
+// A filter weights a sample by its offset (x, y) from a pixel center, in pixels. Every
+// sample is splatted to all pixels within the filter radius and each pixel is divided by
+// the sum of the weights it received.
+
+use std::fmt::Debug;
+
+use crate::utils::PI;
+
+pub trait Filter: Debug + Sync {
+    fn radius(&self) -> (f64, f64);
+    fn evaluate(&self, x: f64, y: f64) -> f64;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+impl FilterKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "box" => Some(Self::Box),
+            "tent" => Some(Self::Tent),
+            "gaussian" => Some(Self::Gaussian),
+            "mitchell" => Some(Self::Mitchell),
+            "lanczos" => Some(Self::Lanczos),
+            _ => None,
+        }
+    }
+
+    pub fn default_radius(&self) -> f64 {
+        match self {
+            Self::Box => 0.5,
+            Self::Tent => 1.0,
+            Self::Gaussian => 1.5,
+            Self::Mitchell => 2.0,
+            Self::Lanczos => 3.0,
+        }
+    }
+
+    pub fn build(&self, radius: f64) -> Box<dyn Filter> {
+        // The Gaussian is cut off at three standard deviations, Mitchell uses the
+        // recommended B = C = 1/3 and Lanczos has as many lobes as its radius.
+        match self {
+            Self::Box => Box::new(BoxFilter::new(radius)),
+            Self::Tent => Box::new(TentFilter::new(radius)),
+            Self::Gaussian => Box::new(GaussianFilter::new(radius, radius / 3.0)),
+            Self::Mitchell => Box::new(MitchellFilter::new(radius, 1.0 / 3.0, 1.0 / 3.0)),
+            Self::Lanczos => Box::new(LanczosFilter::new(radius, radius)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BoxFilter {
+    radius: (f64, f64),
+}
+
+impl BoxFilter {
+    pub fn new(radius: f64) -> Self {
+        Self {
+            radius: (radius, radius),
+        }
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> (f64, f64) {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        if x.abs() <= self.radius.0 && y.abs() <= self.radius.1 {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct TentFilter {
+    radius: (f64, f64),
+}
+
+impl TentFilter {
+    pub fn new(radius: f64) -> Self {
+        Self {
+            radius: (radius, radius),
+        }
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> (f64, f64) {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        (self.radius.0 - x.abs()).max(0.0) * (self.radius.1 - y.abs()).max(0.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct GaussianFilter {
+    radius: (f64, f64),
+    sigma: f64,
+    exp_x: f64,
+    exp_y: f64,
+}
+
+fn gaussian(x: f64, sigma: f64) -> f64 {
+    (-x * x / (2.0 * sigma * sigma)).exp() / (2.0 * PI * sigma * sigma).sqrt()
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f64, sigma: f64) -> Self {
+        Self {
+            radius: (radius, radius),
+            sigma,
+            exp_x: gaussian(radius, sigma),
+            exp_y: gaussian(radius, sigma),
+        }
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> (f64, f64) {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        // The value at the radius is subtracted so the filter falls off to zero there.
+        (gaussian(x, self.sigma) - self.exp_x).max(0.0)
+            * (gaussian(y, self.sigma) - self.exp_y).max(0.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct MitchellFilter {
+    radius: (f64, f64),
+    b: f64,
+    c: f64,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: f64, b: f64, c: f64) -> Self {
+        Self {
+            radius: (radius, radius),
+            b,
+            c,
+        }
+    }
+
+    fn mitchell_1d(&self, x: f64) -> f64 {
+        // x is in [-2, 2]
+        let (b, c) = (self.b, self.c);
+        let x = x.abs();
+        if x <= 1.0 {
+            ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b))
+                / 6.0
+        } else if x <= 2.0 {
+            ((-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c))
+                / 6.0
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> (f64, f64) {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.mitchell_1d(2.0 * x / self.radius.0) * self.mitchell_1d(2.0 * y / self.radius.1)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LanczosFilter {
+    radius: (f64, f64),
+    tau: f64,
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+fn windowed_sinc(x: f64, radius: f64, tau: f64) -> f64 {
+    if x.abs() > radius {
+        0.0
+    } else {
+        sinc(x) * sinc(x / tau)
+    }
+}
+
+impl LanczosFilter {
+    pub fn new(radius: f64, tau: f64) -> Self {
+        Self {
+            radius: (radius, radius),
+            tau,
+        }
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> (f64, f64) {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        windowed_sinc(x, self.radius.0, self.tau) * windowed_sinc(y, self.radius.1, self.tau)
+    }
+}
@@ -0,0 +1,459 @@
+// Animated GIF writer for previews of frame sequences.
+//
+// GIF stores up to 256 colors, so the frames are reduced to one palette shared by all of
+// them, which keeps colors from flickering between frames. The palette comes from median
+// cut or an octree, and error diffusion dithering can hide the banding it leaves.
+
+use std::collections::HashMap;
+use std::fs;
+
+use crate::deflate::BitWriter;
+
+const MAX_COLORS: usize = 256;
+const MAX_CODE: u32 = 4095; // Codes are at most 12 bits, the last one is left unused
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Quantizer {
+    MedianCut, // Splits the colors into boxes of about equal pixel counts
+    Octree,    // Merges the least used branches of a tree of color cubes
+}
+
+fn histogram(frames: &[Vec<[u8; 3]>]) -> Vec<([u8; 3], u32)> {
+    // Sorted by color, since the hash map's order changes from run to run and would change
+    // the palette with it.
+    let mut counts: HashMap<[u8; 3], u32> = HashMap::new();
+    for pixel in frames.iter().flatten() {
+        *counts.entry(*pixel).or_insert(0) += 1;
+    }
+    let mut colors: Vec<([u8; 3], u32)> = counts.into_iter().collect();
+    colors.sort_unstable();
+    colors
+}
+
+fn average(colors: &[([u8; 3], u32)]) -> [u8; 3] {
+    // Mean color weighted by the pixel counts.
+    let mut sum = [0u64; 3];
+    let mut count = 0u64;
+    for &(color, n) in colors {
+        for c in 0..3 {
+            sum[c] += color[c] as u64 * n as u64;
+        }
+        count += n as u64;
+    }
+    sum.map(|s| ((s + count / 2) / count.max(1)) as u8)
+}
+
+fn median_cut(mut colors: Vec<([u8; 3], u32)>, size: usize) -> Vec<[u8; 3]> {
+    // Repeatedly splits the box with the widest range of a channel at the pixel count median
+    // along that channel.
+    let range = |colors: &[([u8; 3], u32)], c: usize| {
+        let (min, max) = colors.iter().fold((255, 0), |(min, max), (color, _)| {
+            (color[c].min(min), color[c].max(max))
+        });
+        max.saturating_sub(min)
+    };
+    let mut boxes: Vec<&mut [([u8; 3], u32)]> = vec![colors.as_mut_slice()];
+    while boxes.len() < size {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(index, b)| {
+                let channel = (0..3).max_by_key(|&c| range(b, c)).unwrap();
+                (index, channel, range(b, channel))
+            })
+            .max_by_key(|&(_, _, range)| range);
+        let Some((index, channel, _)) = widest else {
+            break;
+        };
+        let b = boxes.swap_remove(index);
+        b.sort_unstable_by_key(|(color, _)| color[channel]);
+        let total: u64 = b.iter().map(|&(_, n)| n as u64).sum();
+        let mut below = 0;
+        let mut split = 1;
+        for (k, &(_, n)) in b.iter().enumerate() {
+            below += n as u64;
+            if 2 * below >= total {
+                split = (k + 1).clamp(1, b.len() - 1);
+                break;
+            }
+        }
+        let (first, second) = b.split_at_mut(split);
+        boxes.push(first);
+        boxes.push(second);
+    }
+    boxes.iter().map(|b| average(b)).collect()
+}
+
+struct OctreeNode {
+    children: [usize; 8], // Indices in the node list, 0 for none
+    sum: [u64; 3],        // Sums over the pixels below the node
+    count: u64,
+    leaf: bool,
+}
+
+fn octree(colors: &[([u8; 3], u32)], size: usize) -> Vec<[u8; 3]> {
+    // Sorts the colors into a tree that halves the color cube on every level, then merges
+    // the least used nodes into their parents, deepest first, until few enough leaves are
+    // left.
+    let new_node = |leaf| OctreeNode {
+        children: [0; 8],
+        sum: [0; 3],
+        count: 0,
+        leaf,
+    };
+    let mut nodes = vec![new_node(false)];
+    let mut levels: Vec<Vec<usize>> = vec![Vec::new(); 8]; // Inner nodes of each depth
+    levels[0].push(0);
+    let mut leaves = 0;
+    for &(color, n) in colors {
+        let mut node = 0;
+        for depth in 0..=8 {
+            nodes[node].count += n as u64;
+            for (sum, &value) in nodes[node].sum.iter_mut().zip(&color) {
+                *sum += value as u64 * n as u64;
+            }
+            if depth == 8 {
+                break;
+            }
+            let bit = 7 - depth;
+            let child = ((color[0] >> bit & 1) << 2
+                | (color[1] >> bit & 1) << 1
+                | (color[2] >> bit & 1)) as usize;
+            if nodes[node].children[child] == 0 {
+                let leaf = depth == 7;
+                nodes.push(new_node(leaf));
+                nodes[node].children[child] = nodes.len() - 1;
+                if leaf {
+                    leaves += 1;
+                } else {
+                    levels[depth + 1].push(nodes.len() - 1);
+                }
+            }
+            node = nodes[node].children[child];
+        }
+    }
+
+    for level in levels.iter_mut().rev() {
+        level.sort_unstable_by_key(|&node| nodes[node].count);
+        for &node in level.iter() {
+            if leaves <= size {
+                break;
+            }
+            // The children are all leaves, the deeper levels were merged before.
+            let children = nodes[node].children.iter().filter(|&&c| c != 0).count();
+            nodes[node].children = [0; 8];
+            nodes[node].leaf = true;
+            leaves -= children - 1;
+        }
+    }
+
+    let mut palette = Vec::with_capacity(leaves);
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+        let node = &nodes[node];
+        if node.leaf {
+            palette.push(node.sum.map(|s| ((s + node.count / 2) / node.count) as u8));
+        } else {
+            stack.extend(node.children.iter().filter(|&&c| c != 0));
+        }
+    }
+    palette
+}
+
+// Nearest palette entry of a color, remembered for the colors already looked up.
+struct Matcher<'a> {
+    palette: &'a [[u8; 3]],
+    cache: HashMap<[u8; 3], u8>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(palette: &'a [[u8; 3]]) -> Self {
+        Self {
+            palette,
+            cache: HashMap::new(),
+        }
+    }
+
+    fn nearest(&mut self, color: [u8; 3]) -> u8 {
+        let palette = self.palette;
+        *self.cache.entry(color).or_insert_with(|| {
+            let distance = |p: &[u8; 3]| {
+                (0..3)
+                    .map(|c| (p[c] as i32 - color[c] as i32).pow(2))
+                    .sum::<i32>()
+            };
+            let (index, _) = palette
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, p)| distance(p))
+                .unwrap();
+            index as u8
+        })
+    }
+}
+
+fn indices(pixels: &[[u8; 3]], width: usize, matcher: &mut Matcher, dither: bool) -> Vec<u8> {
+    // Palette indices of the pixels. Dithering spreads the difference to the chosen color
+    // over the neighbors still to come (Floyd-Steinberg).
+    if !dither {
+        return pixels.iter().map(|&p| matcher.nearest(p)).collect();
+    }
+    let mut values: Vec<[f32; 3]> = pixels.iter().map(|p| p.map(|c| c as f32)).collect();
+    let mut result = Vec::with_capacity(pixels.len());
+    for k in 0..values.len() {
+        let (i, value) = (k % width, values[k]);
+        let index = matcher.nearest(value.map(|c| c.round().clamp(0.0, 255.0) as u8));
+        result.push(index);
+        let chosen = matcher.palette[index as usize];
+        let error: [f32; 3] = std::array::from_fn(|c| value[c] - chosen[c] as f32);
+        let mut spread = |k: usize, weight: f32| {
+            if let Some(v) = values.get_mut(k) {
+                for c in 0..3 {
+                    v[c] += error[c] * weight;
+                }
+            }
+        };
+        if i + 1 < width {
+            spread(k + 1, 7.0 / 16.0);
+            spread(k + width + 1, 1.0 / 16.0);
+        }
+        if i > 0 {
+            spread(k + width - 1, 3.0 / 16.0);
+        }
+        spread(k + width, 5.0 / 16.0);
+    }
+    result
+}
+
+fn lzw_compress(indices: &[u8], min_code_size: u32) -> Vec<u8> {
+    // GIF's variable length LZW: codes grow from min_code_size + 1 to 12 bits as the table
+    // fills, and a clear code starts over when it is full.
+    let clear = 1 << min_code_size;
+    let end = clear + 1;
+    let mut writer = BitWriter::new(Vec::new());
+    let mut table: HashMap<(u32, u8), u32> = HashMap::new();
+    let mut next = end + 1;
+    let mut code_size = min_code_size + 1;
+    writer.write_bits(clear, code_size);
+    let mut prefix: Option<u32> = None;
+    for &index in indices {
+        let Some(code) = prefix else {
+            prefix = Some(index as u32);
+            continue;
+        };
+        if let Some(&longer) = table.get(&(code, index)) {
+            prefix = Some(longer);
+            continue;
+        }
+        writer.write_bits(code, code_size);
+        if next < MAX_CODE {
+            table.insert((code, index), next);
+            next += 1;
+            if next > 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        } else {
+            writer.write_bits(clear, code_size);
+            table.clear();
+            next = end + 1;
+            code_size = min_code_size + 1;
+        }
+        prefix = Some(index as u32);
+    }
+    if let Some(code) = prefix {
+        writer.write_bits(code, code_size);
+    }
+    writer.write_bits(end, code_size);
+    writer.flush();
+    writer.bytes
+}
+
+fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn write_gif(
+    path: &str,
+    width: i32,
+    height: i32,
+    frames: &[Vec<[u8; 3]>],
+    fps: f64,
+    quantizer: Quantizer,
+    dither: bool,
+) -> Result<(), std::io::Error> {
+    // Frames of 8-bit display values, looping forever at `fps`.
+    let colors = histogram(frames);
+    let mut palette = match quantizer {
+        _ if colors.len() <= MAX_COLORS => colors.iter().map(|&(color, _)| color).collect(),
+        Quantizer::MedianCut => median_cut(colors, MAX_COLORS),
+        Quantizer::Octree => octree(&colors, MAX_COLORS),
+    };
+    // The color table has a power of two entries, at least four for the smallest LZW codes.
+    let table_bits = (palette.len().max(4) as f64).log2().ceil() as u32;
+    let mut matcher = Matcher::new(&palette);
+    let frame_indices: Vec<Vec<u8>> = frames
+        .iter()
+        .map(|pixels| indices(pixels, width as usize, &mut matcher, dither))
+        .collect();
+    palette.resize(1 << table_bits, [0; 3]);
+
+    let mut bytes = b"GIF89a".to_vec();
+    push_u16(&mut bytes, width as u16);
+    push_u16(&mut bytes, height as u16);
+    // Global color table present, 8 bits per primary, then the size of the table.
+    bytes.extend_from_slice(&[0xf0 | (table_bits - 1) as u8, 0, 0]);
+    bytes.extend(palette.iter().flatten());
+    // Application extension that loops the animation forever.
+    bytes.extend_from_slice(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00");
+
+    for (n, indices) in frame_indices.iter().enumerate() {
+        // Delays are in hundredths of a second, rounded so that they add up to the time of
+        // the frames.
+        let centiseconds = |n: usize| (100.0 * n as f64 / fps).round() as u16;
+        bytes.extend_from_slice(&[0x21, 0xf9, 0x04, 0x00]);
+        push_u16(&mut bytes, centiseconds(n + 1) - centiseconds(n));
+        bytes.extend_from_slice(&[0x00, 0x00]);
+
+        // Image descriptor covering the whole screen, then its data in blocks of at most
+        // 255 bytes.
+        bytes.push(0x2c);
+        for value in [0, 0, width as u16, height as u16] {
+            push_u16(&mut bytes, value);
+        }
+        bytes.extend_from_slice(&[0x00, table_bits as u8]);
+        for block in lzw_compress(indices, table_bits).chunks(255) {
+            bytes.push(block.len() as u8);
+            bytes.extend_from_slice(block);
+        }
+        bytes.push(0x00);
+    }
+    bytes.push(0x3b);
+    fs::write(path, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::tests::BitReader;
+
+    fn lzw_decompress(bytes: &[u8], min_code_size: u32) -> (Vec<u8>, usize) {
+        // Decodes the way GIF readers do, growing the code size as soon as the table
+        // reaches the next power of two. Also returns the number of clear codes.
+        let clear = 1 << min_code_size;
+        let end = clear + 1;
+        let mut reader = BitReader { bytes, position: 0 };
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut code_size = min_code_size + 1;
+        let mut previous: Option<usize> = None;
+        let mut clears = 0;
+        let mut out = Vec::new();
+        loop {
+            let code = reader.bits(code_size) as usize;
+            if code == clear {
+                table = (0..clear).map(|index| vec![index as u8]).collect();
+                table.extend([Vec::new(), Vec::new()]); // The clear and end codes
+                code_size = min_code_size + 1;
+                previous = None;
+                clears += 1;
+                continue;
+            }
+            if code == end {
+                break;
+            }
+            let entry = match (table.get(code), previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) if code == table.len() => {
+                    let mut entry = table[previous].clone();
+                    entry.push(entry[0]);
+                    entry
+                }
+                _ => panic!("code {code} is not in the table"),
+            };
+            assert!(code < clear || code > end, "code {code} is reserved");
+            out.extend_from_slice(&entry);
+            if let Some(previous) = previous {
+                assert!(table.len() < 4096, "table overflow");
+                let mut longer = table[previous].clone();
+                longer.push(entry[0]);
+                table.push(longer);
+                if table.len() == 1 << code_size && code_size < 12 {
+                    code_size += 1;
+                }
+            }
+            previous = Some(code);
+        }
+        assert_eq!(reader.position.div_ceil(8), bytes.len());
+        (out, clears)
+    }
+
+    fn noise(count: usize, colors: u32) -> Vec<u8> {
+        let mut state = 12345u32;
+        (0..count)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                ((state >> 16) % colors) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        for (indices, min_code_size) in [
+            (vec![], 2),
+            (vec![0], 2),
+            (vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1], 2),
+            (vec![0, 1, 0, 1, 0, 1, 0, 1, 2, 3, 3, 3, 2], 2),
+            (noise(500, 4), 2),
+            (noise(500, 256), 8),
+        ] {
+            let (decoded, clears) =
+                lzw_decompress(&lzw_compress(&indices, min_code_size), min_code_size);
+            assert_eq!(decoded, indices);
+            assert_eq!(clears, 1);
+        }
+    }
+
+    #[test]
+    fn lzw_table_clears_when_full() {
+        // Random colors add a code for almost every index, so the table fills several times,
+        // and the code size has to grow to 12 bits and come back down each time.
+        for (count, colors, min_code_size) in [(40000, 256, 8), (40000, 16, 4), (100000, 2, 2)] {
+            let indices = noise(count, colors);
+            let (decoded, clears) =
+                lzw_decompress(&lzw_compress(&indices, min_code_size), min_code_size);
+            assert_eq!(decoded, indices);
+            assert!(clears > 2, "{clears} clears");
+        }
+        // A single repeated index adds ever longer strings instead, without filling it.
+        let indices = vec![3; 100_000];
+        let (decoded, clears) = lzw_decompress(&lzw_compress(&indices, 2), 2);
+        assert_eq!(decoded, indices);
+        assert_eq!(clears, 1);
+    }
+
+    #[test]
+    fn output_does_not_change_between_runs() {
+        // More colors than the palette holds, so that it has to be quantized.
+        let frames: Vec<Vec<[u8; 3]>> = (0..2)
+            .map(|frame| {
+                (0..64 * 64)
+                    .map(|n| [(n % 64 * 4) as u8, (n / 64 * 4) as u8, frame * 100])
+                    .collect()
+            })
+            .collect();
+        for quantizer in [Quantizer::MedianCut, Quantizer::Octree] {
+            let gifs: Vec<Vec<u8>> = (0..2)
+                .map(|run| {
+                    let path = std::env::temp_dir().join(format!("ray_tracing_gif_{run}.gif"));
+                    let path = path.to_str().unwrap();
+                    write_gif(path, 64, 64, &frames, 10.0, quantizer, true).unwrap();
+                    let gif = fs::read(path).unwrap();
+                    fs::remove_file(path).unwrap();
+                    gif
+                })
+                .collect();
+            assert_eq!(gifs[0], gifs[1]);
+        }
+    }
+}
@@ -1,7 +1,9 @@
+use crate::aabb::Aabb;
 use crate::interval::Interval;
 use crate::material::Material;
 use crate::material::DEFAULT_MATERIAL;
 use crate::ray::Ray;
+use crate::utils::hash_double;
 use crate::vec3::Point3;
 use crate::vec3::Vec3;
 
@@ -11,7 +13,13 @@ pub struct HitRecord<'a> {
     pub normal: Vec3,
     pub mat: &'a dyn Material,
     pub t: f64,
+    pub u: f64, // Surface texture coordinates of the hit point
+    pub v: f64,
+    pub dpdu: Vec3, // Derivatives of the surface position along u and v, zero if unknown
+    pub dpdv: Vec3,
     pub front_face: bool,
+    pub object_id: i32, // 1 + index of the hit object in the scene's list, 0 if unset
+    pub choice: f64,    // In [0,1), for materials that pick one of several at the hit
 }
 
 impl HitRecord<'_> {
@@ -20,7 +28,13 @@ impl HitRecord<'_> {
             p: Point3::zero(),
             normal: Vec3::zero(),
             t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            dpdu: Vec3::zero(),
+            dpdv: Vec3::zero(),
             front_face: false,
+            object_id: 0,
+            choice: 0.0,
             mat: &DEFAULT_MATERIAL,
         }
     }
@@ -35,8 +49,19 @@ impl HitRecord<'_> {
             -*outward_normal
         }
     }
+
+    pub fn set_choice(&mut self, r: &Ray) {
+        // Hashes the ray and the hit, with one more value than `Cutout` so that the two are
+        // independent. The value then goes with the hit, so that a material can make the
+        // same choice when the hit is cut out and when it scatters, even from a moved ray.
+        let (o, d) = (r.origin(), r.direction());
+        self.choice = hash_double(&[o.x(), o.y(), o.z(), d.x(), d.y(), d.z(), self.t, 1.0]);
+    }
 }
 
 pub trait Hittable: std::fmt::Debug {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord>;
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>>;
+
+    // Box around the object for all ray times, None if it has no fixed bounds.
+    fn bounding_box(&self) -> Option<Aabb>;
 }
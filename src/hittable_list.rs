@@ -1,9 +1,7 @@
+use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::interval::Interval;
-use crate::material::DEFAULT_MATERIAL;
 use crate::ray::Ray;
-use crate::vec3::Point3;
-use crate::vec3::Vec3;
 
 #[derive(Debug)]
 pub struct HittableList {
@@ -15,6 +13,7 @@ impl HittableList {
         Self { objects: vec![] }
     }
 
+    #[allow(dead_code)]
     pub fn clear(&mut self) {
         self.objects.clear();
     }
@@ -25,7 +24,7 @@ impl HittableList {
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
         // let mut temp_rec = HitRecord {
         //     p: Point3::zero(),
         //     normal: Vec3::zero(),
@@ -36,13 +35,23 @@ impl Hittable for HittableList {
         let mut hit_anything: Option<HitRecord> = None;
         let mut closest_so_far = ray_t.max;
 
-        for object in self.objects.iter() {
-            if let Some(hit) = object.hit(r, Interval::new(ray_t.min, closest_so_far)) {
+        for (index, object) in self.objects.iter().enumerate() {
+            if let Some(mut hit) = object.hit(r, Interval::new(ray_t.min, closest_so_far)) {
                 closest_so_far = hit.t;
+                // Lists of objects inside this one, such as a BVH, number them themselves.
+                if hit.object_id == 0 {
+                    hit.object_id = index as i32 + 1;
+                }
                 hit_anything = Some(hit);
             }
         }
 
         hit_anything
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.objects.iter().try_fold(Aabb::empty(), |bbox, object| {
+            Some(bbox.union(&object.bounding_box()?))
+        })
+    }
 }
@@ -1,3 +1,4 @@
+#[derive(Debug, Copy, Clone)]
 pub struct Interval {
     pub min: f64,
     pub max: f64,
@@ -7,9 +8,10 @@ pub const EMPTY_INTERVAL: Interval = Interval {
     min: 0.0,
     max: -1.0,
 };
+#[allow(dead_code)]
 pub const FULL_INTERVAL: Interval = Interval {
-    min: std::f64::NEG_INFINITY,
-    max: std::f64::INFINITY,
+    min: f64::NEG_INFINITY,
+    max: f64::INFINITY,
 };
 
 impl Interval {
@@ -17,13 +19,22 @@ impl Interval {
         Self { min, max }
     }
 
+    #[allow(dead_code)]
     pub fn full() -> Self {
         Self {
-            min: std::f64::NEG_INFINITY,
-            max: std::f64::INFINITY,
+            min: f64::NEG_INFINITY,
+            max: f64::INFINITY,
         }
     }
 
+    pub fn size(&self) -> f64 {
+        self.max - self.min
+    }
+
+    pub fn union(&self, other: &Interval) -> Self {
+        Self::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
     pub fn contains(&self, x: f64) -> bool {
         self.min <= x && x <= self.max
     }
@@ -0,0 +1,488 @@
+// A camera that traces its rays through the spherical elements of a real lens, after the
+// realistic camera of pbrt (Kolb et al. 1995). It shows the distortion, vignetting, focus
+// breathing and, with dispersive glasses in spectral mode, chromatic aberration of the lens.
+//
+// Lens prescriptions are text files with one interface per line, from the front element
+// on the scene side to the rear element on the film side:
+//
+//   # radius thickness ior aperture [abbe]
+//   29.475  3.76  1.67  25.2  47.2
+//   0       4.5   0     17.1
+//
+// All lengths are in millimeters, and scene units are taken to be meters. `radius` is the
+// curvature radius of the interface, positive when its center is towards the film and 0
+// for the aperture stop, `thickness` the distance along the axis to the next interface
+// (or the film), `ior` the index of refraction behind the interface (0 or 1 for air),
+// `aperture` the diameter of the interface and `abbe` the optional Abbe number of the
+// glass, which makes it dispersive.
+
+use std::fmt::Debug;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+use crate::material::Dispersion;
+use crate::projection::Projection;
+use crate::utils::hash_double;
+use crate::vec3::{Point3, Vec3};
+
+// Wavelengths in nanometers of the Fraunhofer F, d and C lines, which the Abbe number and
+// the index of refraction of glasses are given at.
+const LAMBDA_F: f64 = 486.13;
+const LAMBDA_D: f64 = 587.56;
+const LAMBDA_C: f64 = 656.27;
+
+// Number of rings of the film the exit pupil is bounded for, and the samples per ring.
+const PUPIL_RINGS: usize = 64;
+const PUPIL_SAMPLES: usize = 128 * 128;
+
+#[derive(Debug, Clone)]
+struct Interface {
+    radius: f64,                    // Curvature radius, 0 for the aperture stop
+    thickness: f64,                 // Distance to the next interface towards the film
+    ior: f64,                       // Index of refraction of the medium behind
+    dispersion: Option<Dispersion>, // Used instead of `ior` for paths with a wavelength
+    aperture_radius: f64,
+}
+
+impl Interface {
+    fn ior(&self, wavelength: Option<f64>) -> f64 {
+        match (self.dispersion, wavelength) {
+            (Some(dispersion), Some(wavelength)) => dispersion.ior(wavelength),
+            _ => self.ior,
+        }
+    }
+}
+
+// Rectangle in the plane of the rear element.
+#[derive(Debug, Copy, Clone)]
+struct Bounds {
+    min: (f64, f64),
+    max: (f64, f64),
+}
+
+impl Bounds {
+    fn empty() -> Self {
+        Self {
+            min: (f64::INFINITY, f64::INFINITY),
+            max: (f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min.0 > self.max.0
+    }
+
+    fn area(&self) -> f64 {
+        (self.max.0 - self.min.0) * (self.max.1 - self.min.1)
+    }
+
+    fn include(&mut self, p: (f64, f64)) {
+        self.min = (self.min.0.min(p.0), self.min.1.min(p.1));
+        self.max = (self.max.0.max(p.0), self.max.1.max(p.1));
+    }
+
+    fn lerp(&self, t: (f64, f64)) -> (f64, f64) {
+        (
+            self.min.0 + t.0 * (self.max.0 - self.min.0),
+            self.min.1 + t.1 * (self.max.1 - self.min.1),
+        )
+    }
+}
+
+// The lens sits in front of the film, which is centered on the camera with the view along
+// -z. Rays are sampled towards the exit pupil, the part of the rear element that light
+// from the scene reaches the film point through, rather than towards all of it.
+pub struct Realistic {
+    pub name: String,       // Lens file the prescription was read from
+    pub film_diagonal: f64, // Diagonal of the film in meters
+    pub focus_dist: f64,    // Distance from the film to the plane in focus
+    interfaces: Vec<Interface>,
+    exit_pupils: Vec<Bounds>, // Bounds of the exit pupil for each ring of the film
+    pupil_area: f64,          // Area of the exit pupil seen from the center of the film
+}
+
+impl Debug for Realistic {
+    // The interfaces and pupils follow from the lens file.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Realistic")
+            .field("name", &self.name)
+            .field("film_diagonal", &self.film_diagonal)
+            .field("focus_dist", &self.focus_dist)
+            .finish()
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn intersect_interface(
+    radius: f64,
+    z_center: f64,
+    origin: Point3,
+    direction: Vec3,
+) -> Option<(f64, Vec3)> {
+    // The hit of a ray with the spherical interface, and its normal facing the ray. Of the
+    // two intersections with the sphere, the interface is the cap closer to the axis.
+    let oc = origin - Point3::new(0.0, 0.0, z_center);
+    let a = direction.dot_square();
+    let half_b = oc.dot(&direction);
+    let c = oc.dot_square() - radius * radius;
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let sqrtd = discriminant.sqrt();
+    let closer = (direction.z() > 0.0) != (radius < 0.0);
+    let t = if closer {
+        (-half_b - sqrtd) / a
+    } else {
+        (-half_b + sqrtd) / a
+    };
+    if t < 0.0 {
+        return None;
+    }
+    let normal = (oc + direction * t).unit();
+    let normal = if normal.dot(&direction) > 0.0 {
+        -normal
+    } else {
+        normal
+    };
+    Some((t, normal))
+}
+
+fn refract(direction: Vec3, normal: Vec3, etai_over_etat: f64) -> Option<Vec3> {
+    // The refracted unit direction, or None for total internal reflection.
+    let d = direction.unit();
+    let cos_i = -d.dot(&normal);
+    let sin2_t = etai_over_etat * etai_over_etat * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(d * etai_over_etat + normal * (etai_over_etat * cos_i - cos_t))
+}
+
+fn cardinal_points(r_in: (Point3, Vec3), r_out: (Point3, Vec3)) -> (f64, f64) {
+    // The principal plane and focal point along z of a ray parallel to the axis at
+    // `r_in`, which leaves the lens as `r_out`.
+    let (o, d) = r_out;
+    let t_focus = -o.x() / d.x();
+    let t_principal = (r_in.0.x() - o.x()) / d.x();
+    (o.z() + t_principal * d.z(), o.z() + t_focus * d.z())
+}
+
+impl Realistic {
+    pub fn load(
+        path: &str,
+        film_diagonal: f64,
+        focus_dist: f64,
+        stop_diameter: Option<f64>,
+    ) -> Result<Self, Error> {
+        // Reads the lens file, with the aperture stop opened to `stop_diameter` millimeters
+        // if given, and focuses the lens at `focus_dist`.
+        let text = fs::read_to_string(path)?;
+        let mut interfaces = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            if line.trim().is_empty() {
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(|value| value.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| invalid(format!("{path}:{}: {e}", index + 1)))?;
+            if values.len() < 4 || values.len() > 5 {
+                return Err(invalid(format!(
+                    "{path}:{}: expected radius, thickness, ior, aperture and abbe number",
+                    index + 1
+                )));
+            }
+            let ior = if values[2] == 0.0 { 1.0 } else { values[2] };
+            // Cauchy's equation through the index at the d line with the dispersion from
+            // the F to the C line the Abbe number gives.
+            let dispersion = match values.get(4) {
+                Some(&abbe) if ior > 1.0 && abbe > 0.0 => {
+                    let inverse_square = |lambda: f64| 1.0 / (lambda / 1000.0).powi(2);
+                    let b =
+                        (ior - 1.0) / abbe / (inverse_square(LAMBDA_F) - inverse_square(LAMBDA_C));
+                    Some(Dispersion::Cauchy {
+                        a: ior - b * inverse_square(LAMBDA_D),
+                        b,
+                    })
+                }
+                _ => None,
+            };
+            interfaces.push(Interface {
+                radius: values[0] * 0.001,
+                thickness: values[1] * 0.001,
+                ior,
+                dispersion,
+                aperture_radius: values[3] * 0.001 / 2.0,
+            });
+        }
+        if interfaces.is_empty() {
+            return Err(invalid(format!("{path}: no lens interfaces")));
+        }
+        if let Some(diameter) = stop_diameter {
+            let stop = interfaces
+                .iter_mut()
+                .find(|interface| interface.radius == 0.0)
+                .ok_or_else(|| invalid(format!("{path}: the lens has no aperture stop")))?;
+            stop.aperture_radius = diameter * 0.001 / 2.0;
+        }
+
+        let mut lens = Self {
+            name: path.to_string(),
+            film_diagonal,
+            focus_dist,
+            interfaces,
+            exit_pupils: Vec::new(),
+            pupil_area: 0.0,
+        };
+        let rear = lens.focus(focus_dist).ok_or_else(|| {
+            invalid(format!(
+                "{path}: the lens cannot focus at distance {focus_dist}"
+            ))
+        })?;
+        lens.interfaces.last_mut().unwrap().thickness = rear;
+        lens.bound_exit_pupils();
+        if lens.pupil_area <= 0.0 {
+            return Err(invalid(format!("{path}: no light passes through the lens")));
+        }
+        Ok(lens)
+    }
+
+    fn front_z(&self) -> f64 {
+        // Distance from the film to the front element.
+        self.interfaces
+            .iter()
+            .map(|interface| interface.thickness)
+            .sum()
+    }
+
+    fn rear_z(&self) -> f64 {
+        // Distance from the film to the rear element.
+        self.interfaces.last().unwrap().thickness
+    }
+
+    fn trace_from_film(
+        &self,
+        origin: Point3,
+        direction: Vec3,
+        wavelength: Option<f64>,
+    ) -> Option<(Point3, Vec3)> {
+        // The ray leaving the front element of the lens for a ray from the film, or None if
+        // it is blocked.
+        let (mut origin, mut direction) = (origin, direction);
+        let mut z = 0.0;
+        for (k, interface) in self.interfaces.iter().enumerate().rev() {
+            z -= interface.thickness;
+            let stop = interface.radius == 0.0;
+            let (t, normal) = if stop {
+                ((z - origin.z()) / direction.z(), Vec3::zero())
+            } else {
+                intersect_interface(interface.radius, z + interface.radius, origin, direction)?
+            };
+            if t < 0.0 || !t.is_finite() {
+                return None;
+            }
+            origin += direction * t;
+            if origin.x() * origin.x() + origin.y() * origin.y()
+                > interface.aperture_radius * interface.aperture_radius
+            {
+                return None;
+            }
+            if !stop {
+                let eta_t = match k {
+                    0 => 1.0,
+                    _ => self.interfaces[k - 1].ior(wavelength),
+                };
+                direction = refract(direction, normal, interface.ior(wavelength) / eta_t)?;
+            }
+        }
+        Some((origin, direction))
+    }
+
+    fn trace_from_scene(&self, origin: Point3, direction: Vec3) -> Option<(Point3, Vec3)> {
+        // The ray reaching the film side of the lens for a ray from the scene, or None if
+        // it is blocked.
+        let (mut origin, mut direction) = (origin, direction);
+        let mut z = -self.front_z();
+        for (k, interface) in self.interfaces.iter().enumerate() {
+            let stop = interface.radius == 0.0;
+            let (t, normal) = if stop {
+                ((z - origin.z()) / direction.z(), Vec3::zero())
+            } else {
+                intersect_interface(interface.radius, z + interface.radius, origin, direction)?
+            };
+            if t < 0.0 || !t.is_finite() {
+                return None;
+            }
+            origin += direction * t;
+            if origin.x() * origin.x() + origin.y() * origin.y()
+                > interface.aperture_radius * interface.aperture_radius
+            {
+                return None;
+            }
+            if !stop {
+                let eta_i = match k {
+                    0 => 1.0,
+                    _ => self.interfaces[k - 1].ior,
+                };
+                direction = refract(direction, normal, eta_i / interface.ior)?;
+            }
+            z += interface.thickness;
+        }
+        Some((origin, direction))
+    }
+
+    fn focus(&self, focus_dist: f64) -> Option<f64> {
+        // The distance of the rear element from the film that brings the plane at
+        // `focus_dist` into focus, from a thick lens approximation of the lens: parallel
+        // rays close to the axis give its principal planes and focal length.
+        let x = 0.001 * self.film_diagonal;
+        let from_scene = (
+            Point3::new(x, 0.0, -self.front_z() - 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        let (principal_film, focus_film) = cardinal_points(
+            from_scene,
+            self.trace_from_scene(from_scene.0, from_scene.1)?,
+        );
+        let from_film = (Point3::new(x, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let (principal_scene, _) = cardinal_points(
+            from_film,
+            self.trace_from_film(from_film.0, from_film.1, None)?,
+        );
+        let focal_length = focus_film - principal_film;
+        if focal_length <= 0.0 {
+            return None;
+        }
+
+        // Moving the lens towards the scene by `delta` moves the principal planes with it.
+        // The object is in front of the scene side plane by a = principal_scene + focus_dist
+        // - delta and the film behind the film side one by b = delta - principal_film, with
+        // 1/a + 1/b = 1/f.
+        let a = principal_scene + focus_dist;
+        let b = -principal_film;
+        let c = (a + b) * (a + b - 4.0 * focal_length);
+        if c < 0.0 {
+            return None;
+        }
+        let delta = 0.5 * (a - b - c.sqrt());
+        let rear = self.rear_z() + delta;
+        if rear > 0.0 {
+            Some(rear)
+        } else {
+            None
+        }
+    }
+
+    fn bound_exit_pupils(&mut self) {
+        // Bounds the exit pupil over rings of the film, by tracing rays from points of each
+        // ring to a grid over the rear element.
+        let rear_radius = self.interfaces.last().unwrap().aperture_radius;
+        let rear = Bounds {
+            min: (-1.5 * rear_radius, -1.5 * rear_radius),
+            max: (1.5 * rear_radius, 1.5 * rear_radius),
+        };
+        let grid = (PUPIL_SAMPLES as f64).sqrt() as usize;
+        let half_diagonal = self.film_diagonal / 2.0;
+        let rear_z = self.rear_z();
+        let mut exit_pupils = Vec::with_capacity(PUPIL_RINGS);
+        for ring in 0..PUPIL_RINGS {
+            let mut bounds = Bounds::empty();
+            let mut exiting = 0;
+            for k in 0..PUPIL_SAMPLES {
+                let t = (ring as f64 + hash_double(&[ring as f64, k as f64])) / PUPIL_RINGS as f64;
+                let film = Point3::new(t * half_diagonal, 0.0, 0.0);
+                let p = rear.lerp((
+                    ((k % grid) as f64 + 0.5) / grid as f64,
+                    ((k / grid) as f64 + 0.5) / grid as f64,
+                ));
+                let target = Point3::new(p.0, p.1, -rear_z);
+                if self.trace_from_film(film, target - film, None).is_some() {
+                    bounds.include(p);
+                    exiting += 1;
+                }
+            }
+            if ring == 0 {
+                self.pupil_area = rear.area() * exiting as f64 / PUPIL_SAMPLES as f64;
+            }
+            if bounds.is_empty() {
+                exit_pupils.push(rear);
+                continue;
+            }
+            // Grow the bounds by about a grid cell, for the parts between the samples.
+            let margin = 2.0 * (rear.max.0 - rear.min.0) / grid as f64;
+            bounds.min = (bounds.min.0 - margin, bounds.min.1 - margin);
+            bounds.max = (bounds.max.0 + margin, bounds.max.1 + margin);
+            exit_pupils.push(bounds);
+        }
+        self.exit_pupils = exit_pupils;
+    }
+}
+
+impl Projection for Realistic {
+    fn ray(&self, screen: (f64, f64), aspect: f64, lens: (f64, f64)) -> Option<(Point3, Vec3)> {
+        let (origin, direction, _) = self.weighted_ray(screen, aspect, lens, None)?;
+        Some((origin, direction))
+    }
+
+    fn weighted_ray(
+        &self,
+        screen: (f64, f64),
+        aspect: f64,
+        lens: (f64, f64),
+        wavelength: Option<f64>,
+    ) -> Option<(Point3, Vec3, f64)> {
+        // The lens turns the image over, so the right of the image is on the left of the
+        // film and its top at the bottom.
+        let height = self.film_diagonal / (1.0 + aspect * aspect).sqrt();
+        let width = height * aspect;
+        let film = Point3::new(-screen.0 * width / 2.0, -screen.1 * height / 2.0, 0.0);
+
+        // A point in the exit pupil bounds of the film point's ring, turned from the x axis
+        // the bounds were found on to the film point.
+        let r = (film.x() * film.x() + film.y() * film.y()).sqrt();
+        let ring =
+            ((r / (self.film_diagonal / 2.0) * PUPIL_RINGS as f64) as usize).min(PUPIL_RINGS - 1);
+        let bounds = self.exit_pupils[ring];
+        let p = bounds.lerp(lens);
+        let (sin, cos) = if r > 0.0 {
+            (film.y() / r, film.x() / r)
+        } else {
+            (0.0, 1.0)
+        };
+        let target = Point3::new(cos * p.0 - sin * p.1, sin * p.0 + cos * p.1, -self.rear_z());
+        let direction = target - film;
+        let (origin, direction) = self.trace_from_film(film, direction, wavelength)?;
+
+        // The irradiance of the film falls off with the fourth power of the cosine to the
+        // rays, relative to the center of the film.
+        let cos_theta = (target - film).unit().z();
+        let weight = cos_theta.powi(4) * bounds.area() / self.pupil_area;
+        Some((origin, direction, weight))
+    }
+
+    fn dispersive(&self) -> bool {
+        self.interfaces
+            .iter()
+            .any(|interface| interface.dispersion.is_some())
+    }
+
+    fn set_lens(&mut self, _vfov: f64, _defocus_angle: f64, focus_dist: f64) {
+        // The prescription sets the field of view and the aperture, only the focus moves.
+        // A distance the lens cannot focus at keeps the previous focus.
+        if focus_dist == self.focus_dist {
+            return;
+        }
+        if let Some(rear) = self.focus(focus_dist) {
+            self.interfaces.last_mut().unwrap().thickness = rear;
+            self.focus_dist = focus_dist;
+            self.bound_exit_pupils();
+        }
+    }
+}
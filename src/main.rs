@@ -1,16 +1,47 @@
+mod aabb;
+mod animation;
+mod aov;
+mod aperture;
+mod bump;
+mod bvh;
 mod camera;
+mod checkpoint;
+mod cli;
 mod color;
+mod deflate;
+mod denoise;
+mod exposure;
+mod exr;
+mod film;
+mod filter;
+mod gif;
 mod hittable;
 mod hittable_list;
 mod interval;
+mod lens;
 mod material;
+mod medium;
+mod microfacet;
+mod onb;
+mod pfm;
+mod png;
+mod principled;
+mod projection;
 mod ray;
+mod sampler;
+mod scene;
+mod spectrum;
 mod sphere;
+mod stereo;
+mod texture;
+mod tonemap;
 mod utils;
 mod vec3;
+mod video;
+mod y4m;
 
 use camera::Camera;
-use color::Color;
+use color::{Color, ColorSpace};
 use hittable::HitRecord;
 use hittable_list::HittableList;
 use interval::Interval;
@@ -18,8 +49,134 @@ use vec3::Point3;
 use vec3::Vec3;
 
 fn main() -> Result<(), std::io::Error> {
+    let options = match cli::Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}");
+            std::process::exit(2);
+        }
+    };
+
     // World
+    let working_space = options.working_space;
+    color::set_working_space(working_space);
+    let scene = match &options.scene_path {
+        Some(path) => scene::load(path, working_space)?,
+        None => random_spheres(working_space),
+    };
+    // Built once, animated objects move within it at the time of each ray.
     let mut world = HittableList::new();
+    world.add(bvh::Bvh::new(scene.world));
+
+    // Camera
+    let image_width = options.image_width.unwrap_or(1200);
+    let samples_per_pixel = options.samples_per_pixel.unwrap_or(10); // 500
+    let max_depth = 50;
+    let mut view = scene.view;
+    let mut cam = Camera::new(
+        view.aspect_ratio,
+        image_width,
+        samples_per_pixel,
+        max_depth,
+        view.vfov,
+        view.lookfrom,
+        view.lookat,
+        view.vup,
+        view.defocus_angle,
+        view.focus_dist,
+    );
+    if let Some(projection) = view.take_projection() {
+        cam.projection = projection;
+    }
+    cam.physical = view.physical;
+    cam.sampler = options.sampler;
+    if let Some((kind, radius)) = options.filter {
+        cam.filter = kind.build(radius);
+    }
+    cam.adaptive = options.adaptive;
+    cam.sample_count_image = options.sample_count_image.clone();
+    cam.checkpoint = options.checkpoint.clone();
+    cam.exr_settings = options.exr_settings;
+    cam.tone_mapping = options.tone_mapping;
+    cam.working_space = working_space;
+    cam.display_space = options.display_space;
+    cam.spectral = options.spectral;
+    cam.aovs = options.aovs;
+    cam.denoise = options.denoise;
+    cam.background = scene.background;
+    let image_height = cam.image_height();
+    let region = match options.tile {
+        Some((index, columns, rows)) => Some(camera::Region::tile(
+            index,
+            columns,
+            rows,
+            image_width,
+            image_height,
+        )),
+        None => options.region,
+    };
+    if let Some(mut region) = region {
+        if !region.fits(image_width, image_height) {
+            eprintln!(
+                "region {},{},{},{} is not inside the {image_width}x{image_height} image",
+                region.x, region.y, region.width, region.height
+            );
+            std::process::exit(2);
+        }
+        region.canvas = options.canvas;
+        cam.region = Some(region);
+    }
+
+    let timing = scene.timing.unwrap_or(animation::Timing::default());
+    if scene.timing.is_some() {
+        cam.shutter = timing.shutter / timing.fps;
+    }
+
+    // Render a still at time 0, or every frame of the range at its time
+    let frames: Vec<Option<i32>> = match options.frames {
+        Some((first, last)) => (first..=last).map(Some).collect(),
+        None => vec![None],
+    };
+    let (output_width, output_height) = cam.output_size();
+    let mut video = options
+        .video
+        .clone()
+        .map(|settings| video::Video::new(settings, output_width, output_height));
+    for frame in frames {
+        let image_path = match frame {
+            Some(frame) => {
+                println!("Frame {frame}");
+                cam.time = timing.frame_time(frame);
+                if let (Some(checkpoint), Some(checkpointing)) =
+                    (&mut cam.checkpoint, &options.checkpoint)
+                {
+                    checkpoint.path = cli::frame_path(&checkpointing.path, frame);
+                }
+                cli::frame_path(&options.image_path, frame)
+            }
+            None => options.image_path.clone(),
+        };
+        cam.set_pose(view.pose(cam.time));
+        let (_, pixels) = match options.progressive {
+            Some(progressive) => cam.render_progressive(&world, &image_path, progressive)?,
+            None => cam.render(&world, &image_path, 1)?,
+        };
+        if let Some(video) = &mut video {
+            video.add_frame(cam.display_image(&pixels));
+        }
+    }
+    if let Some(video) = &video {
+        video.write(timing.fps)?;
+    }
+
+    Ok(())
+}
+
+fn random_spheres(working_space: ColorSpace) -> scene::Scene {
+    let mut world = HittableList::new();
+
+    // Colors below are given in linear sRGB and converted to the working space.
+    let srgb = |c: Color| ColorSpace::LinearSrgb.convert(c, working_space);
 
     // 3 different materials for the spheres
 
@@ -59,16 +216,18 @@ fn main() -> Result<(), std::io::Error> {
 
     // random spheres
 
-    let ground_material = material::Lambertian::new(Color::new(0.5, 0.5, 0.5));
+    // The scene is seeded so that it is the same on every run, which resuming relies on.
+    utils::seed_random(0);
+    let ground_material = material::Lambertian::new(srgb(Color::new(0.5, 0.5, 0.5)));
     world.add(sphere::Sphere::new(
         Point3::new(0.0, -1000.0, 0.0),
         1000.0,
         ground_material,
     ));
 
-    let RANDOM_RADIUS = 11;
-    for i in -RANDOM_RADIUS..RANDOM_RADIUS {
-        for j in -RANDOM_RADIUS..RANDOM_RADIUS {
+    let random_radius = 11;
+    for i in -random_radius..random_radius {
+        for j in -random_radius..random_radius {
             let choose_mat = utils::random_double();
             let center = Point3::new(
                 i as f64 + 0.9 * utils::random_double(),
@@ -79,7 +238,7 @@ fn main() -> Result<(), std::io::Error> {
             if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
                 if choose_mat < 0.8 {
                     // diffuse
-                    let albedo = Color::random() * Color::random();
+                    let albedo = srgb(Color::random() * Color::random());
                     world.add(sphere::Sphere::new(
                         center,
                         0.2,
@@ -87,7 +246,7 @@ fn main() -> Result<(), std::io::Error> {
                     ));
                 } else if choose_mat < 0.95 {
                     // metal
-                    let albedo = Color::random_in(0.5, 1.0);
+                    let albedo = srgb(Color::random_in(0.5, 1.0));
                     let fuzz = utils::random_double_in(0.0, 0.5);
                     world.add(sphere::Sphere::new(
                         center,
@@ -113,46 +272,27 @@ fn main() -> Result<(), std::io::Error> {
         material1,
     ));
 
-    let material2 = material::Lambertian::new(Color::new(0.4, 0.2, 0.1));
+    let material2 = material::Lambertian::new(srgb(Color::new(0.4, 0.2, 0.1)));
     world.add(sphere::Sphere::new(
         Point3::new(-4.0, 1.0, 0.0),
         1.0,
         material2,
     ));
 
-    let material3 = material::Metal::new(Color::new(0.7, 0.6, 0.5), 0.0);
+    let material3 = material::Metal::new(srgb(Color::new(0.7, 0.6, 0.5)), 0.0);
     world.add(sphere::Sphere::new(
         Point3::new(4.0, 1.0, 0.0),
         1.0,
         material3,
     ));
 
-    // Camera
-    let aspect_ratio = 16.0 / 9.0;
-    let image_width = 1200;
-    let samples_per_pixel = 10; // 500
-    let max_depth = 50;
-    let vfov = 20.0;
-    let lookfrom = Point3::new(13.0, 2.0, 3.0);
-    let lookat = Point3::new(0.0, 0.0, 0.0);
-    let vup = Vec3::new(0.0, 1.0, 0.0);
-    let defocus_angle = 0.6;
-    let focus_dist = 10.0;
-    let cam = Camera::new(
-        aspect_ratio,
-        image_width,
-        samples_per_pixel,
-        max_depth,
-        vfov,
-        lookfrom,
-        lookat,
-        vup,
-        defocus_angle,
-        focus_dist,
-    );
-
-    // Render
-    cam.render(&world, "image.ppm", 1)?;
-
-    Ok(())
+    scene::Scene {
+        world,
+        view: scene::View::default(),
+        background: camera::Sky {
+            horizon: srgb(Color::same(1.0)),
+            zenith: srgb(Color::new(0.5, 0.7, 1.0)),
+        },
+        timing: None,
+    }
 }
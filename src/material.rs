@@ -1,19 +1,84 @@
+use std::sync::Arc;
+
+use crate::color::luminance;
+use crate::medium::Medium;
+use crate::microfacet::TrowbridgeReitz;
+use crate::onb::Onb;
 use crate::ray::Ray;
-use crate::utils::random_double;
+use crate::texture::{SolidColor, Texture};
+use crate::utils::hash_double;
 use crate::vec3::Vec3;
 use crate::Color;
 use crate::HitRecord;
 
 use std::fmt::Debug;
 
-pub trait Material: Debug + Sync {
+pub trait Material: Debug + Send + Sync {
+    // `uc` and `u` are the sampler dimensions drawn for this bounce: `uc` for discrete
+    // choices (e.g. reflect or refract) and `u` for picking a direction.
     fn scatter(
         &self,
         r_in: &Ray,
         rec: &HitRecord,
+        uc: f64,
+        u: (f64, f64),
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool;
+
+    // The medium enclosed by surfaces of this material, which rays transmitted through
+    // them at the hit enter or leave.
+    fn interior(&self, _rec: &HitRecord) -> Option<Medium> {
+        None
+    }
+
+    // Whether scattering depends on the wavelength of `r_in`, which splits up the
+    // wavelengths of a spectral path.
+    fn dispersive(&self) -> bool {
+        false
+    }
+
+    // Whether the surface is missing at this hit, for alpha masking. Primitives skip such
+    // hits and look for the next one along the same ray.
+    fn cut_out(&self, _r: &Ray, _rec: &HitRecord) -> bool {
+        false
+    }
+
+    // Radiance the surface gives off at the hit towards the origin of `r_in`.
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        Color::zero()
+    }
+}
+
+// Lets scenes built at run time share materials between objects.
+impl<M: Material + ?Sized> Material for Arc<M> {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        uc: f64,
+        u: (f64, f64),
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        (**self).scatter(r_in, rec, uc, u, attenuation, scattered)
+    }
+
+    fn interior(&self, rec: &HitRecord) -> Option<Medium> {
+        (**self).interior(rec)
+    }
+
+    fn dispersive(&self) -> bool {
+        (**self).dispersive()
+    }
+
+    fn cut_out(&self, r: &Ray, rec: &HitRecord) -> bool {
+        (**self).cut_out(r, rec)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        (**self).emitted(r_in, rec)
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -26,6 +91,8 @@ impl Material for DefaultMaterial {
         &self,
         _r_in: &Ray,
         _rec: &HitRecord,
+        _uc: f64,
+        _u: (f64, f64),
         _attenuation: &mut Color,
         _scattered: &mut Ray,
     ) -> bool {
@@ -33,35 +100,79 @@ impl Material for DefaultMaterial {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct Lambertian {
-    pub albedo: Color,
+    pub tex: Arc<dyn Texture>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Self {
-        Self { albedo }
+        Self::textured(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn textured(tex: Arc<dyn Texture>) -> Self {
+        Self { tex }
     }
 }
 
 impl Material for Lambertian {
     fn scatter(
         &self,
-        _r_in: &Ray,
+        r_in: &Ray,
         rec: &HitRecord,
+        _uc: f64,
+        u: (f64, f64),
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
-        let mut scatter_direction = rec.normal + crate::Vec3::random_unit();
+        let mut scatter_direction = rec.normal + Vec3::unit_from_2d(u);
         if scatter_direction.near_zero() {
             scatter_direction = rec.normal;
         }
-        *scattered = Ray::new(rec.p, scatter_direction);
-        *attenuation = self.albedo;
+        *scattered = Ray::with_time(rec.p, scatter_direction, r_in.time());
+        *attenuation = self.tex.value(rec.u, rec.v, &rec.p);
         true
     }
 }
 
+// Light source that gives off the same radiance in all directions from the front of the
+// surface and scatters nothing.
+#[derive(Debug, Clone)]
+pub struct DiffuseLight {
+    pub emit: Arc<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        Self::textured(Arc::new(SolidColor::new(emit)))
+    }
+
+    pub fn textured(emit: Arc<dyn Texture>) -> Self {
+        Self { emit }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _uc: f64,
+        _u: (f64, f64),
+        _attenuation: &mut Color,
+        _scattered: &mut Ray,
+    ) -> bool {
+        false
+    }
+
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord) -> Color {
+        if !rec.front_face {
+            return Color::zero();
+        }
+        self.emit.value(rec.u, rec.v, &rec.p)
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Metal {
     albedo: Color,
@@ -79,24 +190,212 @@ impl Material for Metal {
         &self,
         r_in: &Ray,
         rec: &HitRecord,
+        _uc: f64,
+        u: (f64, f64),
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
         let reflected = r_in.direction().unit().reflect(&rec.normal);
-        *scattered = Ray::new(rec.p, reflected + Vec3::random_unit() * self.fuzz);
+        *scattered = Ray::with_time(
+            rec.p,
+            reflected + Vec3::unit_from_2d(u) * self.fuzz,
+            r_in.time(),
+        );
         *attenuation = self.albedo;
         scattered.direction().dot(&rec.normal) > 0.0
     }
 }
 
+fn fresnel_conductor(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    // Unpolarized reflectance of a conductor with complex index of refraction eta + ik.
+    let cos2 = cos_theta * cos_theta;
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cos_theta * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+// Microfacet metal with a complex index of refraction per color channel.
+#[derive(Debug, Copy, Clone)]
+pub struct Conductor {
+    pub eta: Color, // Real part of the index of refraction
+    pub k: Color,   // Absorption coefficient, the imaginary part
+    pub distribution: TrowbridgeReitz,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64, anisotropy: f64) -> Self {
+        Self {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::from_roughness(roughness, anisotropy),
+        }
+    }
+
+    // Indices of refraction at the linear sRGB primaries.
+
+    pub fn gold(roughness: f64, anisotropy: f64) -> Self {
+        Self::new(
+            Color::new(0.143119, 0.374957, 1.44248),
+            Color::new(3.98316, 2.38572, 1.60322),
+            roughness,
+            anisotropy,
+        )
+    }
+
+    pub fn copper(roughness: f64, anisotropy: f64) -> Self {
+        Self::new(
+            Color::new(0.200438, 0.924033, 1.10221),
+            Color::new(3.91295, 2.45285, 2.14219),
+            roughness,
+            anisotropy,
+        )
+    }
+
+    pub fn aluminum(roughness: f64, anisotropy: f64) -> Self {
+        Self::new(
+            Color::new(1.65746, 0.880369, 0.521229),
+            Color::new(9.22387, 6.26952, 4.837),
+            roughness,
+            anisotropy,
+        )
+    }
+
+    pub fn silver(roughness: f64, anisotropy: f64) -> Self {
+        Self::new(
+            Color::new(0.155265, 0.116723, 0.138342),
+            Color::new(4.82835, 3.12225, 2.14696),
+            roughness,
+            anisotropy,
+        )
+    }
+
+    fn fresnel(&self, cos_theta: f64) -> Color {
+        Color::new(
+            fresnel_conductor(cos_theta, self.eta.x(), self.k.x()),
+            fresnel_conductor(cos_theta, self.eta.y(), self.k.y()),
+            fresnel_conductor(cos_theta, self.eta.z(), self.k.z()),
+        )
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        _uc: f64,
+        u: (f64, f64),
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        // Anisotropic highlights stretch along the u direction of the surface.
+        let frame = Onb::with_tangent(&rec.normal, &rec.dpdu);
+        let wo = frame.world_to_local(&-r_in.direction().unit());
+        if wo.z() <= 0.0 {
+            return false;
+        }
+
+        if self.distribution.effectively_smooth() {
+            let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+            *scattered = Ray::with_time(rec.p, frame.local(&wi), r_in.time());
+            *attenuation = self.fresnel(wo.z());
+            return true;
+        }
+
+        // Reflect about a visible micro normal. The BRDF times the cosine over the pdf
+        // reduces to F G2 / G1.
+        let wm = self.distribution.sample_wm(&wo, u);
+        let wi = -wo + wm * (2.0 * wo.dot(&wm));
+        if wi.z() <= 0.0 {
+            return false;
+        }
+        *scattered = Ray::with_time(rec.p, frame.local(&wi), r_in.time());
+        *attenuation =
+            self.fresnel(wo.dot(&wm)) * (self.distribution.g(&wo, &wi) / self.distribution.g1(&wo));
+        true
+    }
+}
+
+// Wavelength dependent index of refraction, with wavelengths in micrometers.
+#[derive(Debug, Copy, Clone)]
+pub enum Dispersion {
+    Cauchy { a: f64, b: f64 },              // n = a + b / lambda^2
+    Sellmeier { b: [f64; 3], c: [f64; 3] }, // n^2 = 1 + sum of b lambda^2 / (lambda^2 - c)
+}
+
+impl Dispersion {
+    // Schott N-BK7 crown glass
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+    // Schott SF11 dense flint glass
+    pub const SF11: Dispersion = Dispersion::Sellmeier {
+        b: [1.73759695, 0.313747346, 1.89878101],
+        c: [0.013188707, 0.0623068142, 155.23629],
+    };
+
+    pub fn ior(&self, wavelength: f64) -> f64 {
+        // `wavelength` is in nanometers.
+        let lambda2 = (wavelength / 1000.0).powi(2);
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / lambda2,
+            Dispersion::Sellmeier { b, c } => (1.0
+                + (0..3)
+                    .map(|k| b[k] * lambda2 / (lambda2 - c[k]))
+                    .sum::<f64>())
+            .sqrt(),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Dielectric {
-    ir: f64,
+    ir: f64,                        // Index of refraction, at the sodium d line if dispersive
+    dispersion: Option<Dispersion>, // Used instead of `ir` for paths with wavelengths
+    distribution: TrowbridgeReitz,  // Micro surface roughness, for frosted glass
+    absorption: Color,              // Absorption coefficient of the interior per unit length
 }
 
 impl Dielectric {
     pub fn new(ir: f64) -> Self {
-        Self { ir }
+        Self::rough(ir, 0.0, Color::zero())
+    }
+
+    pub fn rough(ir: f64, roughness: f64, absorption: Color) -> Self {
+        Self {
+            ir,
+            distribution: TrowbridgeReitz::from_roughness(roughness, 0.0),
+            absorption,
+            dispersion: None,
+        }
+    }
+
+    pub fn dispersive(dispersion: Dispersion, roughness: f64, absorption: Color) -> Self {
+        Self {
+            dispersion: Some(dispersion),
+            ..Self::rough(dispersion.ior(587.56), roughness, absorption)
+        }
+    }
+
+    fn refraction_ratio(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        let ir = match (self.dispersion, r_in.wavelengths()) {
+            (Some(dispersion), Some(wavelengths)) => dispersion.ior(wavelengths.hero()),
+            _ => self.ir,
+        };
+        if rec.front_face {
+            1.0 / ir
+        } else {
+            ir
+        }
     }
 }
 
@@ -107,34 +406,474 @@ fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
 
+pub fn fresnel_dielectric(cos_theta_i: f64, etai_over_etat: f64) -> f64 {
+    // Unpolarized reflectance of a dielectric interface, 1 for total internal reflection.
+    let sin2_theta_t = etai_over_etat * etai_over_etat * (1.0 - cos_theta_i * cos_theta_i);
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    let r_perp =
+        (etai_over_etat * cos_theta_i - cos_theta_t) / (etai_over_etat * cos_theta_i + cos_theta_t);
+    let r_parallel =
+        (cos_theta_i - etai_over_etat * cos_theta_t) / (cos_theta_i + etai_over_etat * cos_theta_t);
+    (r_perp * r_perp + r_parallel * r_parallel) / 2.0
+}
+
 impl Material for Dielectric {
     fn scatter(
         &self,
         r_in: &Ray,
         rec: &HitRecord,
+        uc: f64,
+        u: (f64, f64),
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
+        if !self.distribution.effectively_smooth() {
+            return self.scatter_rough(r_in, rec, uc, u, attenuation, scattered);
+        }
+
         *attenuation = Color::same(1.0);
-        let refraction_ratio = if rec.front_face {
-            1.0 / self.ir
-        } else {
-            self.ir
-        };
+        let refraction_ratio = self.refraction_ratio(r_in, rec);
 
         let unit_direction = r_in.direction().unit();
         let cos_theta = (-unit_direction).dot(&rec.normal).min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let direction = if refraction_ratio * sin_theta > 1.0
-            || reflectance(cos_theta, refraction_ratio) > random_double()
+            || reflectance(cos_theta, refraction_ratio) > uc
         {
             unit_direction.reflect(&rec.normal)
         } else {
             unit_direction.refract(&rec.normal, refraction_ratio)
         };
 
-        *scattered = Ray::new(rec.p, direction);
+        *scattered = Ray::with_time(rec.p, direction, r_in.time());
         true
     }
+
+    fn interior(&self, _rec: &HitRecord) -> Option<Medium> {
+        Some(Medium::new(self.absorption))
+    }
+
+    fn dispersive(&self) -> bool {
+        self.dispersion.is_some()
+    }
+}
+
+impl Dielectric {
+    fn scatter_rough(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        uc: f64,
+        u: (f64, f64),
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        // Reflects or refracts through a visible micro normal, chosen by its Fresnel
+        // reflectance. Either way the BSDF times the cosine over the pdf reduces to G2 / G1.
+        let refraction_ratio = self.refraction_ratio(r_in, rec);
+        let frame = Onb::new(&rec.normal);
+        let wo = frame.world_to_local(&-r_in.direction().unit());
+        if wo.z() <= 0.0 {
+            return false;
+        }
+        let wm = self.distribution.sample_wm(&wo, u);
+        let cos_theta = wo.dot(&wm);
+        let wi = if fresnel_dielectric(cos_theta, refraction_ratio) > uc {
+            let wi = -wo + wm * (2.0 * cos_theta);
+            if wi.z() <= 0.0 {
+                return false;
+            }
+            wi
+        } else {
+            let wi = (-wo).refract(&wm, refraction_ratio);
+            if wi.z() >= 0.0 {
+                return false;
+            }
+            wi
+        };
+
+        *scattered = Ray::with_time(rec.p, frame.local(&wi), r_in.time());
+        *attenuation = Color::same(self.distribution.g(&wo, &wi) / self.distribution.g1(&wo));
+        true
+    }
+}
+
+// Picks one of two materials per hit, the second with probability `weight`.
+#[derive(Debug, Clone)]
+pub struct MixMaterial {
+    pub first: Arc<dyn Material>,
+    pub second: Arc<dyn Material>,
+    pub weight: Arc<dyn Texture>, // Luminance is the fraction of `second`
+}
+
+impl MixMaterial {
+    #[allow(dead_code)]
+    pub fn new(first: Arc<dyn Material>, second: Arc<dyn Material>, weight: f64) -> Self {
+        Self::textured(
+            first,
+            second,
+            Arc::new(SolidColor::new(Color::same(weight))),
+        )
+    }
+
+    pub fn textured(
+        first: Arc<dyn Material>,
+        second: Arc<dyn Material>,
+        weight: Arc<dyn Texture>,
+    ) -> Self {
+        Self {
+            first,
+            second,
+            weight,
+        }
+    }
+
+    fn choose<'a>(&self, rec: &HitRecord<'a>) -> (&dyn Material, HitRecord<'a>) {
+        // The material picked by the hit's choice, which is the same whether the hit is cut
+        // out or shaded. The part of the choice left over is stretched back to [0,1) for
+        // mixes inside the picked material.
+        let weight = luminance(self.weight.value(rec.u, rec.v, &rec.p)).clamp(0.0, 1.0);
+        let mut picked = rec.clone();
+        if rec.choice < weight {
+            picked.choice = (rec.choice / weight).min(ONE_MINUS_EPSILON);
+            (self.second.as_ref(), picked)
+        } else {
+            picked.choice = ((rec.choice - weight) / (1.0 - weight)).min(ONE_MINUS_EPSILON);
+            (self.first.as_ref(), picked)
+        }
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        uc: f64,
+        u: (f64, f64),
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let (material, rec) = self.choose(rec);
+        material.scatter(r_in, &rec, uc, u, attenuation, scattered)
+    }
+
+    fn interior(&self, rec: &HitRecord) -> Option<Medium> {
+        // That of the material the hit scattered off.
+        let (material, rec) = self.choose(rec);
+        material.interior(&rec)
+    }
+
+    fn dispersive(&self) -> bool {
+        self.first.dispersive() || self.second.dispersive()
+    }
+
+    fn cut_out(&self, r: &Ray, rec: &HitRecord) -> bool {
+        // Hits that are kept are shaded with the material that kept them.
+        let (material, rec) = self.choose(rec);
+        material.cut_out(r, &rec)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        let (material, rec) = self.choose(rec);
+        material.emitted(r_in, &rec)
+    }
+}
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+// Random numbers for the steps of a random walk after the first one, seeded from the
+// bounce's sample so that renders stay reproducible (SplitMix64).
+struct WalkRng(u64);
+
+impl WalkRng {
+    fn new(uc: f64, u: (f64, f64)) -> Self {
+        Self(uc.to_bits() ^ u.0.to_bits().rotate_left(21) ^ u.1.to_bits().rotate_left(42))
+    }
+
+    fn next(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.next(), self.next())
+    }
+}
+
+const MAX_LAYER_BOUNCES: usize = 16;
+
+// An opaque base material under a thin dielectric coat, such as varnish or car paint
+// lacquer. Light is followed stochastically between the coat and the base until it leaves,
+// so whatever the coat reflects is missing from what reaches the base.
+#[derive(Debug, Clone)]
+pub struct Coated {
+    pub base: Arc<dyn Material>,
+    pub ior: f64,
+    pub distribution: TrowbridgeReitz, // Roughness of the coat's surface
+    pub tint: Color, // Transmittance of one crossing of the coat at normal incidence
+}
+
+impl Coated {
+    pub fn new(base: Arc<dyn Material>, ior: f64, roughness: f64, tint: Color) -> Self {
+        Self {
+            base,
+            ior,
+            distribution: TrowbridgeReitz::from_roughness(roughness, 0.0),
+            tint,
+        }
+    }
+
+    fn cross(&self, d: &Vec3, n: &Vec3, eta: f64, uc: f64, u: (f64, f64)) -> Option<(Vec3, f64)> {
+        // Reflects the unit direction `d` off the coat's surface with normal `n` facing it,
+        // or refracts it with relative index `eta`, as the rough `Dielectric` does.
+        let frame = Onb::new(n);
+        let wo = frame.world_to_local(&-*d);
+        let smooth = self.distribution.effectively_smooth();
+        let wm = if smooth {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            self.distribution.sample_wm(&wo, u)
+        };
+        let cos_theta = wo.dot(&wm);
+        let reflect = fresnel_dielectric(cos_theta, eta) > uc;
+        let wi = if reflect {
+            -wo + wm * (2.0 * cos_theta)
+        } else {
+            (-wo).refract(&wm, eta)
+        };
+        if wo.z() <= 0.0 || (wi.z() > 0.0) != reflect || wi.z() == 0.0 {
+            return None;
+        }
+        let weight = match smooth {
+            true => 1.0,
+            false => self.distribution.g(&wo, &wi) / self.distribution.g1(&wo),
+        };
+        Some((frame.local(&wi), weight))
+    }
+
+    fn absorb(&self, cos_theta: f64) -> Color {
+        // Transmittance of one crossing of the coat at an angle.
+        let t = |c: f64| c.max(0.0).powf(1.0 / cos_theta.abs().max(1e-3));
+        Color::new(t(self.tint.x()), t(self.tint.y()), t(self.tint.z()))
+    }
+}
+
+impl Material for Coated {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        uc: f64,
+        u: (f64, f64),
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let n = rec.normal;
+        let mut rng = WalkRng::new(uc, u);
+
+        // Enter the coat, unless it reflects.
+        let d = r_in.direction().unit();
+        let Some((mut d, weight)) = self.cross(&d, &n, 1.0 / self.ior, uc, u) else {
+            return false;
+        };
+        let mut weight = Color::same(weight);
+        if d.dot(&n) > 0.0 {
+            *scattered = Ray::with_time(rec.p, d, r_in.time());
+            *attenuation = weight;
+            return true;
+        }
+
+        for _ in 0..MAX_LAYER_BOUNCES {
+            // Down through the coat onto the base, and back up.
+            weight = weight * self.absorb(d.dot(&n));
+            let mut base_attenuation = Color::zero();
+            let mut base_scattered = Ray::new(rec.p, d);
+            let base_in = Ray::with_time(rec.p, d, r_in.time());
+            if !self.base.scatter(
+                &base_in,
+                rec,
+                rng.next(),
+                rng.next_2d(),
+                &mut base_attenuation,
+                &mut base_scattered,
+            ) {
+                return false;
+            }
+            d = base_scattered.direction().unit();
+            if d.dot(&n) <= 0.0 {
+                return false;
+            }
+            weight = weight * base_attenuation * self.absorb(d.dot(&n));
+
+            // Leave through the coat, or be reflected back down onto the base.
+            let Some((next, step)) = self.cross(&d, &-n, self.ior, rng.next(), rng.next_2d())
+            else {
+                return false;
+            };
+            weight = weight * step;
+            if next.dot(&n) > 0.0 {
+                *scattered = Ray::with_time(rec.p, next, r_in.time());
+                *attenuation = weight;
+                return true;
+            }
+            d = next;
+        }
+        false
+    }
+
+    fn dispersive(&self) -> bool {
+        self.base.dispersive()
+    }
+
+    fn cut_out(&self, r: &Ray, rec: &HitRecord) -> bool {
+        self.base.cut_out(r, rec)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        // The base's emission as it leaves through the coat towards the viewer, less what
+        // the coat reflects back and absorbs on the way, as if the coat were smooth.
+        let emitted = self.base.emitted(r_in, rec);
+        if emitted.near_zero() {
+            return emitted;
+        }
+        let cos_o = r_in.direction().unit().dot(&rec.normal).abs();
+        let transmitted = 1.0 - fresnel_dielectric(cos_o, 1.0 / self.ior);
+        let cos_inside = (1.0 - (1.0 - cos_o * cos_o) / (self.ior * self.ior)).sqrt();
+        emitted * self.absorb(cos_inside) * transmitted
+    }
+}
+
+// A material with holes, such as leaves or a fence, cut out by an opacity texture. Hits
+// are dropped where the opacity is below `threshold`, or with a threshold of `None`,
+// dropped at random with probability one minus the opacity, which blends partially
+// transparent edges over many samples.
+#[derive(Debug, Clone)]
+pub struct Cutout {
+    pub base: Arc<dyn Material>,
+    pub opacity: Arc<dyn Texture>, // Luminance is the opacity
+    pub threshold: Option<f64>,
+}
+
+impl Cutout {
+    pub fn new(base: Arc<dyn Material>, opacity: Arc<dyn Texture>, threshold: Option<f64>) -> Self {
+        Self {
+            base,
+            opacity,
+            threshold,
+        }
+    }
+}
+
+impl Material for Cutout {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        uc: f64,
+        u: (f64, f64),
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.base.scatter(r_in, rec, uc, u, attenuation, scattered)
+    }
+
+    fn interior(&self, rec: &HitRecord) -> Option<Medium> {
+        self.base.interior(rec)
+    }
+
+    fn dispersive(&self) -> bool {
+        self.base.dispersive()
+    }
+
+    fn cut_out(&self, r: &Ray, rec: &HitRecord) -> bool {
+        let opacity = luminance(self.opacity.value(rec.u, rec.v, &rec.p));
+        match self.threshold {
+            Some(threshold) => opacity < threshold,
+            None if opacity >= 1.0 => false,
+            None if opacity <= 0.0 => true,
+            // Hashing the ray and the hit point, rather than drawing from the sampler,
+            // keeps the choice the same however often the ray is intersected.
+            None => {
+                let (o, d) = (r.origin(), r.direction());
+                let values = [o.x(), o.y(), o.z(), d.x(), d.y(), d.z(), rec.t];
+                hash_double(&values) >= opacity
+            }
+        }
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.base.emitted(r_in, rec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hittable;
+    use crate::interval::Interval;
+    use crate::sphere::Sphere;
+    use crate::vec3::Point3;
+
+    #[test]
+    fn mix_shades_what_it_keeps() {
+        // Half opaque red and half fully transparent blue: the blue half is never hit, so
+        // every hit that is kept must be red.
+        let red = Color::new(1.0, 0.0, 0.0);
+        let blue = Arc::new(Lambertian::new(Color::new(0.0, 0.0, 1.0)));
+        let clear = Arc::new(SolidColor::new(Color::zero()));
+        let mix = MixMaterial::textured(
+            Arc::new(Lambertian::new(red)),
+            Arc::new(Cutout::new(blue, clear, None)),
+            Arc::new(SolidColor::new(Color::same(0.5))),
+        );
+        let sphere = Sphere::new(Point3::zero(), 1.0, mix);
+
+        let count = 4000;
+        let mut kept = 0;
+        for n in 0..count {
+            // Rays from the same place through points spread over the sphere.
+            let (x, y) = ((n % 63) as f64 / 63.0 - 0.5, (n / 63) as f64 / 64.0 - 0.5);
+            let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(x, y, -5.0));
+            let Some(rec) = sphere.hit(&r, Interval::new(0.001, f64::INFINITY)) else {
+                continue;
+            };
+            kept += 1;
+            let (mut attenuation, mut scattered) = (Color::zero(), r);
+            let uc = (n as f64 + 0.5) / count as f64;
+            assert!(rec
+                .mat
+                .scatter(&r, &rec, uc, (0.3, 0.7), &mut attenuation, &mut scattered));
+            let channels = |c: Color| [c.x(), c.y(), c.z()];
+            assert_eq!(channels(attenuation), channels(red));
+        }
+        // The front of the sphere is kept half the time, and the back behind it the other
+        // half of that.
+        let fraction = kept as f64 / count as f64;
+        assert!((fraction - 0.75).abs() < 0.05, "{fraction} kept");
+    }
+
+    #[test]
+    fn mix_enters_the_medium_it_scattered_off() {
+        let absorption = Color::new(0.5, 0.2, 0.1);
+        let mix = MixMaterial::textured(
+            Arc::new(Dielectric::rough(1.5, 0.0, absorption)),
+            Arc::new(Dielectric::new(1.5)),
+            Arc::new(SolidColor::new(Color::same(0.25))),
+        );
+        let mut rec = HitRecord::new();
+        for (choice, expected) in [(0.1, 0.0), (0.2, 0.0), (0.3, 0.5), (0.9, 0.5)] {
+            rec.choice = choice;
+            let interior = mix.interior(&rec).unwrap();
+            assert_eq!(interior.absorption.x(), expected);
+        }
+    }
 }
@@ -0,0 +1,78 @@
+// Absorbing media inside closed surfaces, tracked along a path.
+
+use crate::Color;
+
+// Homogeneous medium that absorbs light per Beer-Lambert's law.
+#[derive(Debug, Copy, Clone)]
+pub struct Medium {
+    pub absorption: Color, // Absorption coefficient per unit length
+}
+
+impl Medium {
+    pub fn new(absorption: Color) -> Self {
+        Self { absorption }
+    }
+
+    #[allow(dead_code)]
+    pub fn from_transmittance(color: Color, distance: f64) -> Self {
+        // The medium that lets `color` through after `distance`, which is easier to pick
+        // than a coefficient.
+        let absorption = |c: f64| -c.max(1e-6).ln() / distance;
+        Self::new(Color::new(
+            absorption(color.x()),
+            absorption(color.y()),
+            absorption(color.z()),
+        ))
+    }
+
+    pub fn transmittance(&self, distance: f64) -> Color {
+        Color::new(
+            (-self.absorption.x() * distance).exp(),
+            (-self.absorption.y() * distance).exp(),
+            (-self.absorption.z() * distance).exp(),
+        )
+    }
+}
+
+const MAX_NESTING: usize = 4;
+
+// Media the path is currently inside, innermost last. Paths start outside of everything.
+#[derive(Debug, Copy, Clone)]
+pub struct MediumStack {
+    media: [Medium; MAX_NESTING],
+    len: usize,
+}
+
+impl MediumStack {
+    pub fn new() -> Self {
+        Self {
+            media: [Medium::new(Color::zero()); MAX_NESTING],
+            len: 0,
+        }
+    }
+
+    pub fn current(&self) -> Option<&Medium> {
+        self.media[..self.len].last()
+    }
+
+    pub fn enter(&mut self, medium: Medium) {
+        // Deeper nesting than supported replaces the innermost medium.
+        if self.len == MAX_NESTING {
+            self.len -= 1;
+        }
+        self.media[self.len] = medium;
+        self.len += 1;
+    }
+
+    pub fn exit(&mut self) {
+        self.len = self.len.saturating_sub(1);
+    }
+
+    pub fn transmittance(&self, distance: f64) -> Color {
+        // Fraction of the light that survives `distance` in the current medium.
+        match self.current() {
+            Some(medium) => medium.transmittance(distance),
+            None => Color::same(1.0),
+        }
+    }
+}
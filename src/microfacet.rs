@@ -0,0 +1,100 @@
+// Trowbridge-Reitz (GGX) microfacet distribution with Smith masking-shadowing.
+//
+// Directions are given in a local shading frame where the macro surface normal is +z and
+// the x and y axes are the directions of `alpha_x` and `alpha_y`.
+
+use crate::utils::PI;
+use crate::vec3::Vec3;
+
+#[derive(Debug, Copy, Clone)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        Self { alpha_x, alpha_y }
+    }
+
+    pub fn from_roughness(roughness: f64, anisotropy: f64) -> Self {
+        // Perceptual roughness in [0,1] is squared into alpha. Anisotropy in [0,1)
+        // stretches the highlight along x, as in the Disney BRDF.
+        let alpha = roughness * roughness;
+        let aspect = (1.0 - 0.9 * anisotropy).sqrt();
+        Self::new(alpha / aspect, alpha * aspect)
+    }
+
+    pub fn effectively_smooth(&self) -> bool {
+        // Below this the lobe is narrower than what sampling it could resolve, and the
+        // surface is treated as a perfect mirror.
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        // Density of micro normals wm, projected onto the macro surface.
+        let cos2 = wm.z() * wm.z();
+        if cos2 == 0.0 {
+            return 0.0;
+        }
+        let e = (wm.x() / self.alpha_x).powi(2) + (wm.y() / self.alpha_y).powi(2) + cos2;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    pub fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2 = w.z() * w.z();
+        if cos2 == 0.0 {
+            return 0.0;
+        }
+        let alpha2_tan2 = ((w.x() * self.alpha_x).powi(2) + (w.y() * self.alpha_y).powi(2)) / cos2;
+        ((1.0 + alpha2_tan2).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        // Fraction of the micro normals visible from w.
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        // Fraction of the micro normals visible from both wo and wi (height-correlated).
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    pub fn visible_d(&self, w: &Vec3, wm: &Vec3) -> f64 {
+        // Density of the micro normals seen from w.
+        self.g1(w) / w.z().abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    pub fn sample_wm(&self, w: &Vec3, u: (f64, f64)) -> Vec3 {
+        // Samples a micro normal from the distribution of visible normals `visible_d`
+        // (Heitz 2018, "Sampling the GGX Distribution of Visible Normals").
+
+        // Stretch w to the hemisphere configuration of a unit roughness.
+        let wh = Vec3::new(self.alpha_x * w.x(), self.alpha_y * w.y(), w.z()).unit();
+        let wh = if wh.z() < 0.0 { -wh } else { wh };
+
+        // Orthonormal basis around wh
+        let t1 = if wh.z() < 0.99999 {
+            Vec3::new(0.0, 0.0, 1.0).cross(&wh).unit()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(&t1);
+
+        // Uniform point on the disk, warped to the projection of the visible hemisphere.
+        let p = Vec3::disk_from_2d(u);
+        let h = (1.0 - p.x() * p.x()).sqrt();
+        let s = (1.0 + wh.z()) / 2.0;
+        let py = h * (1.0 - s) + p.y() * s;
+        let pz = (1.0 - p.x() * p.x() - py * py).max(0.0).sqrt();
+
+        // Reproject onto the hemisphere and unstretch.
+        let nh = t1 * p.x() + t2 * py + wh * pz;
+        Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        )
+        .unit()
+    }
+}
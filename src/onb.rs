@@ -0,0 +1,49 @@
+use crate::vec3::Vec3;
+
+// Orthonormal basis, used to move directions in and out of a local frame where `w` is the
+// +z axis.
+#[derive(Debug, Copy, Clone)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn new(w: &Vec3) -> Self {
+        // Builds u and v from the unit vector w without branches on its orientation besides
+        // the sign of z (Duff et al. 2017, "Building an Orthonormal Basis, Revisited").
+        let sign = 1f64.copysign(w.z());
+        let a = -1.0 / (sign + w.z());
+        let b = w.x() * w.y() * a;
+        let u = Vec3::new(1.0 + sign * w.x() * w.x() * a, sign * b, -sign * w.x());
+        let v = Vec3::new(b, sign + w.y() * w.y() * a, -w.y());
+        Self { u, v, w: *w }
+    }
+
+    pub fn with_tangent(w: &Vec3, tangent: &Vec3) -> Self {
+        // The basis whose u follows the tangent, e.g. dpdu to orient anisotropic
+        // reflection along the surface parameterization. Falls back to `new` if the
+        // tangent is missing or parallel to w.
+        let u = *tangent - *w * w.dot(tangent);
+        if u.near_zero() {
+            return Self::new(w);
+        }
+        let u = u.unit();
+        Self {
+            u,
+            v: w.cross(&u),
+            w: *w,
+        }
+    }
+
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        // Local coordinates to world coordinates.
+        self.u * a.x() + self.v * a.y() + self.w * a.z()
+    }
+
+    pub fn world_to_local(&self, a: &Vec3) -> Vec3 {
+        // World coordinates to local coordinates.
+        Vec3::new(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+}
@@ -0,0 +1,39 @@
+use std::fs;
+
+use crate::Color;
+
+pub fn write_pfm(
+    image_path: &str,
+    width: i32,
+    height: i32,
+    pixels: &[Color],
+) -> Result<(), std::io::Error> {
+    // Portable Float Map: linear 32-bit float RGB. The negative scale marks little-endian
+    // data, and rows are stored bottom to top.
+    let mut bytes = format!("PF\n{} {}\n-1.0\n", width, height).into_bytes();
+    for j in (0..height).rev() {
+        for i in 0..width {
+            let c = pixels[(j * width + i) as usize];
+            for v in [c.x(), c.y(), c.z()] {
+                bytes.extend_from_slice(&(v as f32).to_le_bytes());
+            }
+        }
+    }
+    fs::write(image_path, bytes)
+}
+
+pub fn write_pfm_gray(
+    image_path: &str,
+    width: i32,
+    height: i32,
+    values: &[f32],
+) -> Result<(), std::io::Error> {
+    // Single channel variant of `write_pfm`.
+    let mut bytes = format!("Pf\n{} {}\n-1.0\n", width, height).into_bytes();
+    for j in (0..height).rev() {
+        for v in &values[(j * width) as usize..((j + 1) * width) as usize] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+    fs::write(image_path, bytes)
+}
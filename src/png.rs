@@ -0,0 +1,161 @@
+use std::fs;
+
+use crate::color::to_bytes;
+use crate::deflate::zlib_compress;
+use crate::Color;
+
+fn crc32(bytes: &[u8]) -> u32 {
+    // CRC-32 as in zlib, bit by bit since chunks are few.
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    // Length, type, data and the CRC of type and data.
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+pub fn write_png(
+    image_path: &str,
+    width: i32,
+    height: i32,
+    alpha: Option<&[f32]>,
+    pixel_color: impl Fn(i32, i32) -> Color,
+) -> Result<(), std::io::Error> {
+    // 8-bit RGB PNG with the same encoding as the PPM output, or RGBA with `alpha` given
+    // row by row from the top. Every row is stored as the difference to the pixel on its
+    // left (filter type 1), which compresses better.
+    let channels = if alpha.is_some() { 4 } else { 3 };
+    let mut rows = Vec::with_capacity((height * (1 + channels * width)) as usize);
+    for j in 0..height {
+        rows.push(1);
+        let mut left = [0u8; 4];
+        for i in 0..width {
+            let [r, g, b] = to_bytes(pixel_color(i, j));
+            let a = alpha.map_or(255, |alpha| {
+                (255.0 * alpha[(j * width + i) as usize].clamp(0.0, 1.0)).round() as u8
+            });
+            let bytes = [r, g, b, a];
+            for c in 0..channels as usize {
+                rows.push(bytes[c].wrapping_sub(left[c]));
+            }
+            left = bytes;
+        }
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth 8, color type 2 (RGB) or 6 (RGBA), then the default compression, filtering
+    // and no interlacing.
+    let color_type = if alpha.is_some() { 6 } else { 2 };
+    header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+    let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_compress(&rows));
+    write_chunk(&mut png, b"IEND", &[]);
+    fs::write(image_path, png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::tests::zlib_decompress;
+
+    fn chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        // Splits a PNG into its chunks, checking the signature and every CRC.
+        assert_eq!(
+            png[..8],
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n']
+        );
+        let mut chunks = Vec::new();
+        let mut at = 8;
+        while at < png.len() {
+            let length = u32::from_be_bytes(png[at..at + 4].try_into().unwrap()) as usize;
+            let kind: [u8; 4] = png[at + 4..at + 8].try_into().unwrap();
+            let data = png[at + 8..at + 8 + length].to_vec();
+            let crc =
+                u32::from_be_bytes(png[at + 8 + length..at + 12 + length].try_into().unwrap());
+            assert_eq!(crc, crc32(&png[at + 4..at + 8 + length]));
+            chunks.push((kind, data));
+            at += 12 + length;
+        }
+        chunks
+    }
+
+    fn write(name: &str, alpha: Option<&[f32]>, colors: &[Color; 4]) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("ray_tracing_png_{name}.png"));
+        let path = path.to_str().unwrap();
+        write_png(path, 2, 2, alpha, |i, j| colors[(2 * j + i) as usize]).unwrap();
+        let png = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        png
+    }
+
+    #[test]
+    fn crc_vectors() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b"IEND"), 0xae426082);
+    }
+
+    #[test]
+    fn rgb_rows_are_sub_filtered() {
+        let colors = [
+            Color::new(1.0, 0.0, 0.0),
+            Color::new(0.0, 1.0, 0.0),
+            Color::new(0.0, 0.0, 1.0),
+            Color::new(1.0, 1.0, 1.0),
+        ];
+        let png = write("rgb", None, &colors);
+        let chunks = chunks(&png);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 2, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        assert!(chunks[2].1.is_empty());
+
+        // Each row is the filter type, then the first pixel, then the difference to it.
+        #[rustfmt::skip]
+        let rows = [
+            1, 255, 0, 0, 1, 255, 0,
+            1, 0, 0, 255, 255, 255, 0,
+        ];
+        assert_eq!(zlib_decompress(&chunks[1].1), rows);
+    }
+
+    #[test]
+    fn alpha_is_a_fourth_channel() {
+        let colors = [
+            Color::same(1.0),
+            Color::same(1.0),
+            Color::zero(),
+            Color::zero(),
+        ];
+        let png = write("rgba", Some(&[1.0, 0.5, 0.0, 2.0]), &colors);
+        let chunks = chunks(&png);
+        assert_eq!(chunks[0].1[8..10], [8, 6]);
+        // Alpha 0.5 is 128, stored as 128 - 255 wrapped around, and 2 is clamped to 255.
+        #[rustfmt::skip]
+        let rows = [
+            1, 255, 255, 255, 255, 0, 0, 0, 129,
+            1, 0, 0, 0, 0, 0, 0, 0, 255,
+        ];
+        assert_eq!(zlib_decompress(&chunks[1].1), rows);
+    }
+}
@@ -0,0 +1,334 @@
+// Principled BSDF after the Disney BRDF (Burley 2012) and its extension to transmission
+// (Burley 2015), covering diffuse, metals, plastics, clear coated paints and glass with a
+// single set of artist friendly parameters.
+
+use std::sync::Arc;
+
+use crate::color::luminance;
+use crate::material::{fresnel_dielectric, Material};
+use crate::microfacet::TrowbridgeReitz;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::texture::{SolidColor, Texture};
+use crate::utils::PI;
+use crate::vec3::Vec3;
+use crate::Color;
+use crate::HitRecord;
+
+// Parameters are in [0,1] except for `ior`. Scalar parameters given as textures use the
+// luminance of the texture.
+#[derive(Debug, Clone)]
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>, // Reflectance of dielectrics, 0.5 is 4%
+    pub specular_tint: Arc<dyn Texture>, // Tints dielectric reflections towards the base color
+    pub sheen: Arc<dyn Texture>,    // Extra grazing reflection, for cloth
+    pub sheen_tint: Arc<dyn Texture>, // Tints the sheen towards the base color
+    pub clearcoat: Arc<dyn Texture>, // Strength of a second, colorless specular layer
+    pub clearcoat_gloss: Arc<dyn Texture>, // Smoothness of the clear coat
+    pub transmission: Arc<dyn Texture>, // Fraction of the dielectric part that is glass
+    pub ior: Arc<dyn Texture>,      // Index of refraction of the transmissive part
+}
+
+fn solid(value: f64) -> Arc<dyn Texture> {
+    Arc::new(SolidColor::new(Color::same(value)))
+}
+
+impl Principled {
+    #[allow(dead_code)]
+    pub fn new(base_color: Color) -> Self {
+        Self::textured(Arc::new(SolidColor::new(base_color)))
+    }
+
+    pub fn textured(base_color: Arc<dyn Texture>) -> Self {
+        // The defaults of the Disney BRDF: a rough plastic.
+        Self {
+            base_color,
+            metallic: solid(0.0),
+            roughness: solid(0.5),
+            specular: solid(0.5),
+            specular_tint: solid(0.0),
+            sheen: solid(0.0),
+            sheen_tint: solid(0.5),
+            clearcoat: solid(0.0),
+            clearcoat_gloss: solid(1.0),
+            transmission: solid(0.0),
+            ior: solid(1.5),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn set_metallic(&mut self, metallic: f64) {
+        self.metallic = solid(metallic);
+    }
+
+    #[allow(dead_code)]
+    pub fn set_roughness(&mut self, roughness: f64) {
+        self.roughness = solid(roughness);
+    }
+
+    fn lobes(&self, rec: &HitRecord, cos_theta_o: f64) -> Lobes {
+        let base = self.base_color.value(rec.u, rec.v, &rec.p);
+        let scalar = |texture: &Arc<dyn Texture>| luminance(texture.value(rec.u, rec.v, &rec.p));
+        let unit = |texture: &Arc<dyn Texture>| scalar(texture).clamp(0.0, 1.0);
+        let metallic = unit(&self.metallic);
+        let roughness = unit(&self.roughness);
+        let specular_tint = unit(&self.specular_tint);
+        let sheen_tint = unit(&self.sheen_tint);
+        let transmission = unit(&self.transmission);
+
+        let base_luminance = luminance(base).max(0.0);
+        let tint = if base_luminance > 0.0 {
+            base / base_luminance
+        } else {
+            Color::same(1.0)
+        };
+        let dielectric_specular =
+            lerp_color(Color::same(1.0), tint, specular_tint) * (0.08 * unit(&self.specular));
+        let specular = lerp_color(dielectric_specular, base, metallic);
+
+        // Near zero roughness the lobes are clamped to a very sharp highlight rather than
+        // handled as mirrors, which keeps them combinable.
+        let alpha = (roughness * roughness).max(1e-3);
+
+        let diffuse_weight = (1.0 - metallic) * (1.0 - transmission);
+        let glass_weight = (1.0 - metallic) * transmission;
+        let specular_weight = 1.0 - glass_weight;
+        let clearcoat_weight = 0.25 * unit(&self.clearcoat);
+
+        // Lobes are picked roughly in proportion to the light they reflect from this angle.
+        let probabilities = [
+            diffuse_weight * base_luminance,
+            specular_weight * luminance(schlick(specular, cos_theta_o)),
+            clearcoat_weight * schlick(Color::same(0.04), cos_theta_o).x(),
+            glass_weight,
+        ];
+        let total: f64 = probabilities.iter().sum();
+
+        Lobes {
+            base,
+            specular,
+            sheen: lerp_color(Color::same(1.0), tint, sheen_tint) * unit(&self.sheen),
+            roughness,
+            distribution: TrowbridgeReitz::new(alpha, alpha),
+            clearcoat_alpha: lerp(0.1, 0.001, unit(&self.clearcoat_gloss)),
+            diffuse_weight,
+            specular_weight,
+            clearcoat_weight,
+            glass_weight,
+            probabilities: probabilities.map(|p| if total > 0.0 { p / total } else { 0.0 }),
+            eta: scalar(&self.ior),
+        }
+    }
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+fn lerp_color(a: Color, b: Color, t: f64) -> Color {
+    a * (1.0 - t) + b * t
+}
+
+fn schlick(f0: Color, cos_theta: f64) -> Color {
+    f0 + (Color::same(1.0) - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+fn gtr1(cos_theta_h: f64, alpha: f64) -> f64 {
+    // Generalized Trowbridge-Reitz with gamma 1, the long tailed distribution of the
+    // clear coat.
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_theta_h * cos_theta_h))
+}
+
+fn smith_g1(cos_theta: f64, alpha: f64) -> f64 {
+    // Isotropic GGX masking, with the clear coat's fixed alpha.
+    let a2 = alpha * alpha;
+    let cos2 = cos_theta * cos_theta;
+    2.0 * cos_theta / (cos_theta + (a2 + cos2 - a2 * cos2).sqrt())
+}
+
+const DIFFUSE: usize = 0;
+const SPECULAR: usize = 1;
+const CLEARCOAT: usize = 2;
+const GLASS: usize = 3;
+
+// The parameters evaluated at a hit point, in the local frame of its normal.
+struct Lobes {
+    base: Color,
+    specular: Color, // Reflectance of the specular lobe at normal incidence
+    sheen: Color,    // Sheen color times strength
+    roughness: f64,
+    distribution: TrowbridgeReitz,
+    clearcoat_alpha: f64,
+    diffuse_weight: f64,
+    specular_weight: f64,
+    clearcoat_weight: f64,
+    glass_weight: f64,
+    probabilities: [f64; 4], // Of sampling the diffuse, specular, clearcoat and glass lobes
+    eta: f64,                // Index of refraction on the far side over the near side
+}
+
+impl Lobes {
+    fn sample(&self, lobe: usize, wo: &Vec3, uc: f64, u: (f64, f64)) -> Vec3 {
+        let reflect = |wm: Vec3| -*wo + wm * (2.0 * wo.dot(&wm));
+        match lobe {
+            DIFFUSE => {
+                // Cosine weighted, by projecting the disk up onto the hemisphere.
+                let d = Vec3::disk_from_2d(u);
+                let z = (1.0 - d.x() * d.x() - d.y() * d.y()).max(0.0).sqrt();
+                Vec3::new(d.x(), d.y(), z)
+            }
+            SPECULAR => reflect(self.distribution.sample_wm(wo, u)),
+            CLEARCOAT => {
+                let a2 = self.clearcoat_alpha * self.clearcoat_alpha;
+                let cos_theta = ((1.0 - a2.powf(1.0 - u.0)) / (1.0 - a2)).max(0.0).sqrt();
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * u.1;
+                reflect(Vec3::new(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                ))
+            }
+            _ => {
+                let wm = self.distribution.sample_wm(wo, u);
+                if fresnel_dielectric(wo.dot(&wm), 1.0 / self.eta) > uc {
+                    reflect(wm)
+                } else {
+                    (-*wo).refract(&wm, 1.0 / self.eta)
+                }
+            }
+        }
+    }
+
+    fn evaluate(&self, wo: &Vec3, wi: &Vec3) -> (Color, f64) {
+        // The BSDF summed over all lobes, and the pdf of sampling wi with the lobe
+        // choice, so that paths are weighted as if the lobes were combined with the
+        // balance heuristic.
+        if wi.z() > 0.0 {
+            self.evaluate_reflection(wo, wi)
+        } else if wi.z() < 0.0 {
+            self.evaluate_transmission(wo, wi)
+        } else {
+            (Color::zero(), 0.0)
+        }
+    }
+
+    fn evaluate_reflection(&self, wo: &Vec3, wi: &Vec3) -> (Color, f64) {
+        let wh = (*wo + *wi).unit();
+        let cos_d = wi.dot(&wh);
+        let cos_o = wo.z();
+        let cos_i = wi.z();
+        let [p_diffuse, p_specular, p_clearcoat, p_glass] = self.probabilities;
+
+        // Diffuse with retro-reflection at grazing angles, plus sheen.
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fl = (1.0 - cos_i).powi(5);
+        let fv = (1.0 - cos_o).powi(5);
+        let diffuse = self.base / PI * ((1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv));
+        let sheen = self.sheen * (1.0 - cos_d).powi(5);
+        let mut f = (diffuse + sheen) * self.diffuse_weight;
+        let mut pdf = p_diffuse * cos_i / PI;
+
+        // Specular reflection of metals and of the dielectric base.
+        let d = self.distribution.d(&wh);
+        let g = self.distribution.g(wo, wi);
+        let microfacet = d * g / (4.0 * cos_o * cos_i);
+        let visible_pdf = self.distribution.visible_d(wo, &wh) / (4.0 * wo.dot(&wh).abs());
+        f += schlick(self.specular, cos_d) * (microfacet * self.specular_weight);
+        pdf += p_specular * visible_pdf;
+
+        // Clear coat
+        if self.clearcoat_weight > 0.0 {
+            let dc = gtr1(wh.z(), self.clearcoat_alpha);
+            let gc = smith_g1(cos_o, 0.25) * smith_g1(cos_i, 0.25);
+            let fc = schlick(Color::same(0.04), cos_d).x();
+            f += Color::same(self.clearcoat_weight * dc * gc * fc / (4.0 * cos_o * cos_i));
+            pdf += p_clearcoat * dc * wh.z() / (4.0 * wo.dot(&wh).abs());
+        }
+
+        // Reflection off the glass
+        if self.glass_weight > 0.0 {
+            let fresnel = fresnel_dielectric(wo.dot(&wh), 1.0 / self.eta);
+            f += Color::same(self.glass_weight * fresnel * microfacet);
+            pdf += p_glass * fresnel * visible_pdf;
+        }
+        (f, pdf)
+    }
+
+    fn evaluate_transmission(&self, wo: &Vec3, wi: &Vec3) -> (Color, f64) {
+        if self.glass_weight <= 0.0 {
+            return (Color::zero(), 0.0);
+        }
+        // The micro normal that refracts wo into wi, facing the side of wo.
+        let wm = (*wi * self.eta + *wo).unit();
+        let wm = if wm.z() < 0.0 { -wm } else { wm };
+        let (cos_om, cos_im) = (wo.dot(&wm), wi.dot(&wm));
+        if cos_om <= 0.0 || cos_im >= 0.0 {
+            return (Color::zero(), 0.0);
+        }
+
+        let fresnel = fresnel_dielectric(cos_om, 1.0 / self.eta);
+        let denom = (cos_im + cos_om / self.eta).powi(2);
+        let d = self.distribution.d(&wm);
+        let g = self.distribution.g(wo, wi);
+        let f = self.base
+            * (self.glass_weight * d * g * (1.0 - fresnel) * (cos_im * cos_om).abs()
+                / (denom * wi.z().abs() * wo.z()));
+        let pdf = self.probabilities[GLASS]
+            * (1.0 - fresnel)
+            * self.distribution.visible_d(wo, &wm)
+            * cos_im.abs()
+            / denom;
+        (f, pdf)
+    }
+}
+
+impl Material for Principled {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        uc: f64,
+        u: (f64, f64),
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let frame = Onb::new(&rec.normal);
+        let wo = frame.world_to_local(&-r_in.direction().unit());
+        if wo.z() <= 0.0 {
+            return false;
+        }
+        let mut lobes = self.lobes(rec, wo.z());
+        if !rec.front_face {
+            lobes.eta = 1.0 / lobes.eta;
+        }
+
+        // Pick a lobe with `uc`, then reuse what is left of it for the lobe's own choice.
+        let mut lobe = GLASS;
+        let mut uc = uc;
+        for (index, &probability) in lobes.probabilities.iter().enumerate() {
+            if uc < probability {
+                lobe = index;
+                uc /= probability;
+                break;
+            }
+            uc -= probability;
+        }
+        if lobes.probabilities[lobe] <= 0.0 {
+            return false;
+        }
+
+        let wi = lobes.sample(lobe, &wo, uc.min(1.0), u);
+        let (f, pdf) = lobes.evaluate(&wo, &wi);
+        if pdf <= 0.0 || !pdf.is_finite() {
+            return false;
+        }
+        *scattered = Ray::with_time(rec.p, frame.local(&wi), r_in.time());
+        *attenuation = f * (wi.z().abs() / pdf);
+        true
+    }
+}
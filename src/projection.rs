@@ -0,0 +1,196 @@
+// Projections from the image to camera rays.
+//
+// Camera space has x to the right, y up and the view along -z; the camera places it with
+// its lookfrom/lookat/vup frame.
+
+use std::fmt::Debug;
+
+use crate::aperture::Aperture;
+use crate::utils::{degrees_to_radians, PI};
+use crate::vec3::{Point3, Vec3};
+
+pub trait Projection: Debug + Send + Sync {
+    // The camera space ray through `screen`, the image point with x and y in [-1,1] from
+    // the left to the right and the bottom to the top edge. `aspect` is the image width
+    // over its height and `lens` a 2D sample for projections with an aperture. None for
+    // points outside of the projected image, which stay black.
+    fn ray(&self, screen: (f64, f64), aspect: f64, lens: (f64, f64)) -> Option<(Point3, Vec3)>;
+
+    // `ray` for a path at `wavelength` in nanometers, if it carries one, together with the
+    // weight of the ray. Projections whose rays depend on the wavelength, or are not sampled
+    // in proportion to the light they bring to the image, override it.
+    fn weighted_ray(
+        &self,
+        screen: (f64, f64),
+        aspect: f64,
+        lens: (f64, f64),
+        _wavelength: Option<f64>,
+    ) -> Option<(Point3, Vec3, f64)> {
+        let (origin, direction) = self.ray(screen, aspect, lens)?;
+        Some((origin, direction, 1.0))
+    }
+
+    // Whether the rays depend on the wavelength, so that a path can only follow one.
+    fn dispersive(&self) -> bool {
+        false
+    }
+
+    // Changes the lens settings of the camera, for animations. Projections that do not
+    // depend on them ignore them.
+    fn set_lens(&mut self, _vfov: f64, _defocus_angle: f64, _focus_dist: f64) {}
+}
+
+// Pinhole or thin lens perspective.
+#[derive(Debug, Clone)]
+pub struct Perspective {
+    pub vfov: f64,          // Vertical view angle in degrees
+    pub defocus_angle: f64, // Variation angle of rays through each pixel
+    pub focus_dist: f64,    // Distance to the plane of perfect focus
+    pub aperture: Aperture, // Shape of the lens opening
+    pub cat_eye: f64,       // Optical vignetting, 0 for none and 1 for strong
+}
+
+impl Perspective {
+    pub fn new(vfov: f64, defocus_angle: f64, focus_dist: f64) -> Self {
+        Self {
+            vfov,
+            defocus_angle,
+            focus_dist,
+            aperture: Aperture::Circle,
+            cat_eye: 0.0,
+        }
+    }
+
+    fn defocus_disk_sample(
+        &self,
+        u: (f64, f64),
+        screen: (f64, f64),
+        aspect: f64,
+    ) -> Option<Point3> {
+        // Returns a point in the camera defocus disk, or None if the lens barrel blocks it.
+        let p = self.aperture.sample(u);
+        if self.cat_eye > 0.0 {
+            // Towards the edges of the frame the front of the barrel, seen from the image,
+            // moves off the aperture and cuts the bokeh into a cat's eye.
+            let diagonal = (aspect * aspect + 1.0).sqrt();
+            let shift =
+                Vec3::new(screen.0 * aspect, screen.1, 0.0) * (2.0 * self.cat_eye / diagonal);
+            if (p - shift).length() > 1.0 {
+                return None;
+            }
+        }
+        let radius = self.focus_dist * degrees_to_radians(self.defocus_angle / 2.0).tan();
+        Some(p * radius)
+    }
+}
+
+impl Projection for Perspective {
+    fn ray(&self, screen: (f64, f64), aspect: f64, lens: (f64, f64)) -> Option<(Point3, Vec3)> {
+        let h = (degrees_to_radians(self.vfov) / 2.0).tan() * self.focus_dist;
+        let focus_point = Point3::new(screen.0 * h * aspect, screen.1 * h, -self.focus_dist);
+        let origin = if self.defocus_angle <= 0.0 {
+            Point3::zero()
+        } else {
+            self.defocus_disk_sample(lens, screen, aspect)?
+        };
+        Some((origin, focus_point - origin))
+    }
+
+    fn set_lens(&mut self, vfov: f64, defocus_angle: f64, focus_dist: f64) {
+        self.vfov = vfov;
+        self.defocus_angle = defocus_angle;
+        self.focus_dist = focus_dist;
+    }
+}
+
+// Parallel rays, for elevations without perspective distortion.
+#[derive(Debug, Copy, Clone)]
+pub struct Orthographic {
+    pub view_width: f64, // Width of the viewed area in scene units
+}
+
+impl Orthographic {
+    pub fn new(view_width: f64) -> Self {
+        Self { view_width }
+    }
+}
+
+impl Projection for Orthographic {
+    fn ray(&self, screen: (f64, f64), aspect: f64, _lens: (f64, f64)) -> Option<(Point3, Vec3)> {
+        let half_width = self.view_width / 2.0;
+        let origin = Point3::new(screen.0 * half_width, screen.1 * half_width / aspect, 0.0);
+        Some((origin, Vec3::new(0.0, 0.0, -1.0)))
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum FisheyeMapping {
+    Equidistant, // Distance from the center proportional to the angle from the view axis
+    Equisolid,   // Area in the image proportional to the solid angle
+}
+
+// Circular fisheye filling the shorter side of the image.
+#[derive(Debug, Copy, Clone)]
+pub struct Fisheye {
+    pub fov: f64, // Angle covered by the image circle in degrees, up to 360
+    pub mapping: FisheyeMapping,
+}
+
+impl Fisheye {
+    pub fn new(fov: f64, mapping: FisheyeMapping) -> Self {
+        Self { fov, mapping }
+    }
+}
+
+impl Projection for Fisheye {
+    fn ray(&self, screen: (f64, f64), aspect: f64, _lens: (f64, f64)) -> Option<(Point3, Vec3)> {
+        let (x, y) = if aspect >= 1.0 {
+            (screen.0 * aspect, screen.1)
+        } else {
+            (screen.0, screen.1 / aspect)
+        };
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+        let half_fov = degrees_to_radians(self.fov.min(360.0)) / 2.0;
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * half_fov,
+            FisheyeMapping::Equisolid => 2.0 * (r * (half_fov / 2.0).sin()).asin(),
+        };
+        let phi = y.atan2(x);
+        let direction = Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            -theta.cos(),
+        );
+        Some((Point3::zero(), direction))
+    }
+}
+
+// Full 360 by 180 degree panorama with longitude along x and latitude along y, centered
+// on the view direction. Meant for images twice as wide as they are high.
+#[derive(Debug, Copy, Clone)]
+pub struct Equirectangular {}
+
+impl Equirectangular {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+pub fn equirectangular_direction(longitude: f64, latitude: f64) -> Vec3 {
+    // Camera space direction at angles in radians from the view direction.
+    Vec3::new(
+        latitude.cos() * longitude.sin(),
+        latitude.sin(),
+        -latitude.cos() * longitude.cos(),
+    )
+}
+
+impl Projection for Equirectangular {
+    fn ray(&self, screen: (f64, f64), _aspect: f64, _lens: (f64, f64)) -> Option<(Point3, Vec3)> {
+        let direction = equirectangular_direction(screen.0 * PI, screen.1 * PI / 2.0);
+        Some((Point3::zero(), direction))
+    }
+}
@@ -1,3 +1,4 @@
+use super::spectrum::SampledWavelengths;
 use super::vec3::Point3;
 use super::vec3::Vec3;
 
@@ -5,11 +6,22 @@ use super::vec3::Vec3;
 pub struct Ray {
     origin: Point3,
     direction: Vec3,
+    time: f64,
+    wavelengths: Option<SampledWavelengths>, // Set in spectral mode
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3) -> Self {
-        Self { origin, direction }
+        Self::with_time(origin, direction, 0.0)
+    }
+
+    pub fn with_time(origin: Point3, direction: Vec3, time: f64) -> Self {
+        Self {
+            origin,
+            direction,
+            time,
+            wavelengths: None,
+        }
     }
 
     pub fn origin(&self) -> Point3 {
@@ -20,6 +32,18 @@ impl Ray {
         self.direction
     }
 
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn wavelengths(&self) -> Option<&SampledWavelengths> {
+        self.wavelengths.as_ref()
+    }
+
+    pub fn set_wavelengths(&mut self, wavelengths: Option<SampledWavelengths>) {
+        self.wavelengths = wavelengths;
+    }
+
     pub fn at(&self, t: f64) -> Point3 {
         self.origin + self.direction * t
     }
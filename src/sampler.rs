@@ -0,0 +1,463 @@
+// Pixel samplers.
+//
+// A sampler hands out the random numbers of one camera path, one dimension at a time.
+// The camera always asks for the same dimensions in the same order (pixel 2D, lens 2D,
+// time 1D, then 1D + 2D per bounce), so samplers that correlate the values of a dimension
+// across the samples of a pixel (stratified, Halton, Sobol) spread them evenly over the
+// domain and converge with far fewer samples than independent uniform values do.
+//
+// All samplers are deterministic functions of the seed, the pixel and the sample index.
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+pub trait Sampler {
+    fn samples_per_pixel(&self) -> i32;
+
+    // Prepares the sampler for sample number `sample_index` of pixel (i, j).
+    fn start_pixel_sample(&mut self, i: i32, j: i32, sample_index: i32);
+
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64);
+
+    // Returns the offset of the sample inside its pixel, in [0,1)^2.
+    fn get_pixel_2d(&mut self) -> (f64, f64) {
+        self.get_2d()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SamplerType {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerType {
+    pub fn create(&self, samples_per_pixel: i32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerType::Independent => Box::new(IndependentSampler::new(samples_per_pixel, seed)),
+            SamplerType::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerType::Halton => Box::new(HaltonSampler::new(samples_per_pixel, seed)),
+            SamplerType::Sobol => Box::new(SobolSampler::new(samples_per_pixel, seed)),
+        }
+    }
+}
+
+// PCG32 random number generator (O'Neill), small and seekable.
+#[derive(Debug, Copy, Clone)]
+pub struct Rng {
+    state: u64,
+    inc: u64,
+}
+
+const PCG32_DEFAULT_STATE: u64 = 0x853c49e6748fea9b;
+const PCG32_DEFAULT_STREAM: u64 = 0xda3e39cb94b95bdb;
+const PCG32_MULT: u64 = 0x5851f42d4c957f2d;
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self {
+            state: PCG32_DEFAULT_STATE,
+            inc: PCG32_DEFAULT_STREAM,
+        };
+        rng.set_sequence(seed, mix_bits(seed));
+        rng
+    }
+
+    pub fn set_sequence(&mut self, sequence_index: u64, seed: u64) {
+        self.state = 0;
+        self.inc = (sequence_index << 1) | 1;
+        self.next_u32();
+        self.state = self.state.wrapping_add(seed);
+        self.next_u32();
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old_state = self.state;
+        self.state = old_state.wrapping_mul(PCG32_MULT).wrapping_add(self.inc);
+        let xorshifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        let rot = (old_state >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    pub fn next_f64(&mut self) -> f64 {
+        // Returns a random real in [0,1).
+        let bits = ((self.next_u32() as u64) << 21) ^ (self.next_u32() as u64 >> 11);
+        (bits as f64 * (1.0 / (1u64 << 53) as f64)).min(ONE_MINUS_EPSILON)
+    }
+
+    // Skips `delta` values ahead in the sequence in O(log delta) time.
+    pub fn advance(&mut self, delta: u64) {
+        let mut cur_mult = PCG32_MULT;
+        let mut cur_plus = self.inc;
+        let mut acc_mult: u64 = 1;
+        let mut acc_plus: u64 = 0;
+        let mut delta = delta;
+        while delta > 0 {
+            if delta & 1 == 1 {
+                acc_mult = acc_mult.wrapping_mul(cur_mult);
+                acc_plus = acc_plus.wrapping_mul(cur_mult).wrapping_add(cur_plus);
+            }
+            cur_plus = cur_mult.wrapping_add(1).wrapping_mul(cur_plus);
+            cur_mult = cur_mult.wrapping_mul(cur_mult);
+            delta /= 2;
+        }
+        self.state = acc_mult.wrapping_mul(self.state).wrapping_add(acc_plus);
+    }
+}
+
+pub fn mix_bits(v: u64) -> u64 {
+    let mut v = v;
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    v
+}
+
+pub fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0x9e3779b97f4a7c15, |h, &v| mix_bits(h ^ mix_bits(v)))
+}
+
+// Returns the i-th element of a pseudo-random permutation of 0..l selected by p (Kensler).
+fn permutation_element(i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = i;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+#[derive(Debug, Clone)]
+pub struct IndependentSampler {
+    samples_per_pixel: i32,
+    seed: u64,
+    rng: Rng,
+}
+
+impl IndependentSampler {
+    pub fn new(samples_per_pixel: i32, seed: u64) -> Self {
+        Self {
+            samples_per_pixel,
+            seed,
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn samples_per_pixel(&self) -> i32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, i: i32, j: i32, sample_index: i32) {
+        self.rng
+            .set_sequence(hash(&[i as u64, j as u64, self.seed]), mix_bits(self.seed));
+        self.rng.advance(sample_index as u64 * 65536);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.next_f64()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.next_f64(), self.rng.next_f64())
+    }
+}
+
+// Jittered grid: each dimension of a pixel is split into strata and every sample of the
+// pixel falls into a different one, with strata assigned in a per-dimension random order.
+#[derive(Debug, Clone)]
+pub struct StratifiedSampler {
+    x_samples: i32,
+    y_samples: i32,
+    seed: u64,
+    pixel: (i32, i32),
+    sample_index: i32,
+    dimension: u64,
+    rng: Rng,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: i32, seed: u64) -> Self {
+        // Split the sample count into the most square grid possible.
+        let mut x_samples = (samples_per_pixel.max(1) as f64).sqrt() as i32;
+        while samples_per_pixel % x_samples != 0 {
+            x_samples -= 1;
+        }
+        Self {
+            x_samples,
+            y_samples: samples_per_pixel.max(1) / x_samples,
+            seed,
+            pixel: (0, 0),
+            sample_index: 0,
+            dimension: 0,
+            rng: Rng::new(seed),
+        }
+    }
+
+    fn stratum(&self) -> u32 {
+        let p = hash(&[
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension,
+            self.seed,
+        ]);
+        let count = self.samples_per_pixel() as u32;
+        let index = self.sample_index as u32;
+        if index < count {
+            permutation_element(index, count, p as u32)
+        } else {
+            // Past the planned sample count the strata are revisited in a new order.
+            permutation_element(index % count, count, (p >> 32) as u32 ^ (index / count))
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn samples_per_pixel(&self) -> i32 {
+        self.x_samples * self.y_samples
+    }
+
+    fn start_pixel_sample(&mut self, i: i32, j: i32, sample_index: i32) {
+        self.pixel = (i, j);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+        self.rng
+            .set_sequence(hash(&[i as u64, j as u64, self.seed]), mix_bits(self.seed));
+        self.rng.advance(sample_index as u64 * 65536);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let stratum = self.stratum();
+        self.dimension += 1;
+        let delta = self.rng.next_f64();
+        ((stratum as f64 + delta) / self.samples_per_pixel() as f64).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let stratum = self.stratum() as i32;
+        self.dimension += 2;
+        let x = stratum % self.x_samples;
+        let y = stratum / self.x_samples;
+        let dx = self.rng.next_f64();
+        let dy = self.rng.next_f64();
+        (
+            ((x as f64 + dx) / self.x_samples as f64).min(ONE_MINUS_EPSILON),
+            ((y as f64 + dy) / self.y_samples as f64).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+const PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+// Radical inverse of `a` in the given base with every digit passed through a random
+// permutation that depends on the digits before it (Owen scrambling).
+fn owen_scrambled_radical_inverse(base: u64, a: u64, hash: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut reversed: u64 = 0;
+    let mut a = a;
+    while 1.0 - (base - 1) as f64 * inv_base_m < 1.0 {
+        let next = a / base;
+        let digit_value = a - next * base;
+        let digit_hash = mix_bits(hash ^ reversed) as u32;
+        let digit_value = permutation_element(digit_value as u32, base as u32, digit_hash) as u64;
+        reversed = reversed * base + digit_value;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+    (reversed as f64 * inv_base_m).min(ONE_MINUS_EPSILON)
+}
+
+// Halton sequence indexed by the pixel sample, with each dimension in its own prime base.
+// Scrambling differs per pixel so neighbouring pixels do not share the same pattern.
+#[derive(Debug, Clone)]
+pub struct HaltonSampler {
+    samples_per_pixel: i32,
+    seed: u64,
+    pixel_hash: u64,
+    sample_index: u64,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    pub fn new(samples_per_pixel: i32, seed: u64) -> Self {
+        Self {
+            samples_per_pixel,
+            seed,
+            pixel_hash: 0,
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn sample_dimension(&self, dimension: usize) -> f64 {
+        let scramble = hash(&[self.pixel_hash, dimension as u64]);
+        if dimension < PRIMES.len() {
+            owen_scrambled_radical_inverse(PRIMES[dimension], self.sample_index, scramble)
+        } else {
+            // Past the table the dimensions are uncorrelated.
+            let mut rng = Rng::new(scramble);
+            rng.advance(self.sample_index);
+            rng.next_f64()
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn samples_per_pixel(&self) -> i32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, i: i32, j: i32, sample_index: i32) {
+        self.pixel_hash = hash(&[i as u64, j as u64, self.seed]);
+        self.sample_index = sample_index as u64;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let x = self.sample_dimension(self.dimension);
+        self.dimension += 1;
+        x
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let x = self.sample_dimension(self.dimension);
+        let y = self.sample_dimension(self.dimension + 1);
+        self.dimension += 2;
+        (x, y)
+    }
+}
+
+// Generator matrices of the first two Sobol dimensions. The first is the van der Corput
+// sequence, the second follows from the primitive polynomial x + 1.
+fn sobol_sample(index: u32, dimension: usize) -> u32 {
+    let mut v: u32 = 1 << 31;
+    let mut result = 0;
+    let mut index = index;
+    while index != 0 {
+        if index & 1 == 1 {
+            result ^= v;
+        }
+        v = if dimension == 0 { v >> 1 } else { v ^ (v >> 1) };
+        index >>= 1;
+    }
+    result
+}
+
+// Owen scrambling of a 32-bit fixed point value (Laine-Karras style hash, as in pbrt-v4).
+fn fast_owen_scramble(v: u32, seed: u32) -> u32 {
+    let mut v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x05526c56);
+    v ^= v.wrapping_mul(0x53a22864);
+    v.reverse_bits()
+}
+
+// Padded Sobol: every 1D or 2D request is a scrambled (0,2)-sequence with the sample order
+// shuffled per dimension, so dimensions are well distributed without correlating each other.
+// Works best when the sample count is a power of two.
+#[derive(Debug, Clone)]
+pub struct SobolSampler {
+    samples_per_pixel: i32,
+    seed: u64,
+    pixel_hash: u64,
+    sample_index: i32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(samples_per_pixel: i32, seed: u64) -> Self {
+        Self {
+            samples_per_pixel,
+            seed,
+            pixel_hash: 0,
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn shuffled_index(&self, h: u64) -> u32 {
+        let count = self.samples_per_pixel.max(1) as u32;
+        let index = self.sample_index as u32;
+        if index < count {
+            permutation_element(index, count, h as u32)
+        } else {
+            index
+        }
+    }
+}
+
+fn sobol_to_f64(v: u32) -> f64 {
+    (v as f64 * (1.0 / (1u64 << 32) as f64)).min(ONE_MINUS_EPSILON)
+}
+
+impl Sampler for SobolSampler {
+    fn samples_per_pixel(&self) -> i32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, i: i32, j: i32, sample_index: i32) {
+        self.pixel_hash = hash(&[i as u64, j as u64, self.seed]);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let h = hash(&[self.pixel_hash, self.dimension]);
+        self.dimension += 1;
+        let index = self.shuffled_index(h);
+        sobol_to_f64(fast_owen_scramble(sobol_sample(index, 0), (h >> 32) as u32))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let h = hash(&[self.pixel_hash, self.dimension]);
+        self.dimension += 2;
+        let index = self.shuffled_index(h);
+        (
+            sobol_to_f64(fast_owen_scramble(sobol_sample(index, 0), (h >> 32) as u32)),
+            sobol_to_f64(fast_owen_scramble(
+                sobol_sample(index, 1),
+                mix_bits(h) as u32,
+            )),
+        )
+    }
+}
@@ -0,0 +1,964 @@
+// Text scene descriptions.
+//
+// Each line is a statement: a keyword, for some a name and a type, then `key=value`
+// parameters. `#` starts a comment. Colors are linear sRGB and converted to the working
+// space; a color is either `r,g,b`, a single gray value or the name of a texture, and
+// scalars are a number or the name of a texture. Paths are relative to the scene file.
+//
+//   camera lookfrom=13,2,3 lookat=0,0,0 vup=0,1,0 vfov=20 aspect_ratio=1.7778
+//          defocus_angle=0.6 focus_dist=10
+//          projection=perspective|orthographic|fisheye|equirectangular
+//          view_width=W (orthographic) fov=DEGREES mapping=equidistant|equisolid (fisheye)
+//          stereo=side_by_side|top_bottom|ods|none ipd=0.064 convergence=DISTANCE
+//          aperture=circle|polygon|annulus|image blades=N blade_rotation=DEGREES (polygon)
+//          inner=FRACTION (annulus) aperture_image=TEXTURE (image) cat_eye=STRENGTH
+//          projection=realistic lens=FILE film_diagonal=MM stop_diameter=MM
+//          f_number=N shutter=SECONDS iso=S sensor_height=MM   (physical exposure)
+//   background horizon=1,1,1 zenith=0.5,0.7,1 illuminance=LUX
+//   texture NAME solid color=COLOR
+//   texture NAME image path=FILE.ppm
+//   texture NAME data path=FILE.ppm       (values as stored, for normal and height maps)
+//   material NAME lambertian albedo=COLOR
+//   material NAME metal albedo=R,G,B fuzz=F
+//   material NAME conductor preset=gold|copper|aluminum|silver (or eta=R,G,B k=R,G,B)
+//            roughness=R anisotropy=A
+//   material NAME dielectric ior=N roughness=R absorption=R,G,B glass=bk7|sf11
+//            cauchy=A,B sellmeier=B1,B2,B3,C1,C2,C3   (wavelengths in micrometers)
+//   material NAME principled base_color=COLOR metallic=SCALAR roughness=SCALAR
+//            specular=SCALAR specular_tint=SCALAR sheen=SCALAR sheen_tint=SCALAR
+//            clearcoat=SCALAR clearcoat_gloss=SCALAR transmission=SCALAR ior=SCALAR
+//   material NAME mix first=MATERIAL second=MATERIAL weight=SCALAR
+//   material NAME coated base=MATERIAL ior=N roughness=R tint=COLOR
+//   material NAME normal_map base=MATERIAL map=TEXTURE
+//   material NAME bump base=MATERIAL height=TEXTURE scale=S
+//   material NAME cutout base=MATERIAL opacity=SCALAR threshold=T   (stochastic without T)
+//   sphere center=X,Y,Z radius=R material=NAME name=NAME
+//   light center=X,Y,Z radius=R color=COLOR luminance=NITS (or power=WATTS) name=NAME
+//   animation fps=24 shutter=0.5   (shutter open for a fraction of each frame)
+//   key camera time=SECONDS lookfrom=X,Y,Z lookat=X,Y,Z vfov=V focus_dist=D defocus_angle=A
+//       interpolation=linear|catmull_rom|bezier|monotone
+//   key NAME time=SECONDS translate=X,Y,Z rotate=X,Y,Z (degrees) scale=S interpolation=...
+//
+// Parameters left out take the defaults of the corresponding constructors. With physical
+// exposure, light is in photometric units: colors of lights and the sky in nits, and the
+// f-number sets the defocus angle. Without it, colors are displayed as they are.
+//
+// Keys animate the camera settings and named objects, which are rotated and scaled about
+// the center of their bounds; settings without keys keep their values. The interpolation
+// of a key applies up to the next one. Bezier curves take the handles of each value as
+// offsets from it, e.g. lookfrom_in=X,Y,Z and lookfrom_out=X,Y,Z, a third of the way to
+// the keys before and after in time, and follow Catmull-Rom where they are left out.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::animation::{
+    Animated, CameraAnimation, CameraPose, Handles, Interpolation, Key, Motion, Timing,
+};
+use crate::aperture::{Aperture, ApertureImage};
+use crate::bump::{Perturbed, ShadingNormal};
+use crate::camera::Sky;
+use crate::color::{luminance, ColorSpace};
+use crate::exposure::{PhysicalCamera, LUMINOUS_EFFICACY};
+use crate::hittable::Hittable;
+use crate::hittable_list::HittableList;
+use crate::lens::Realistic;
+use crate::material::{
+    Coated, Conductor, Cutout, Dielectric, DiffuseLight, Dispersion, Lambertian, Material, Metal,
+    MixMaterial,
+};
+use crate::principled::Principled;
+use crate::projection::{
+    Equirectangular, Fisheye, FisheyeMapping, Orthographic, Perspective, Projection,
+};
+use crate::sphere::Sphere;
+use crate::stereo::{Stereo, StereoLayout};
+use crate::texture::{ImageTexture, SolidColor, Texture};
+use crate::utils::{INF, PI};
+use crate::vec3::{Point3, Vec3};
+use crate::Color;
+
+// Placement and lens of the camera.
+#[derive(Debug)]
+pub struct View {
+    pub aspect_ratio: f64,
+    pub vfov: f64, // Vertical view angle (field of view)
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub defocus_angle: f64, // Variation angle of rays through each pixel
+    pub focus_dist: f64,    // Distance from camera lookfrom point to plane of perfect focus
+    pub projection: Option<Box<dyn Projection>>, // Replaces the perspective of the above
+    pub aperture: Aperture, // Lens opening of the perspective
+    pub cat_eye: f64,       // Optical vignetting of the perspective, 0 for none
+    pub stereo: Option<StereoRig>, // Renders the projection for two eyes
+    pub physical: Option<PhysicalCamera>, // Photometric exposure, sets defocus_angle if present
+    pub animation: CameraAnimation, // Keyframes of the settings above
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct StereoRig {
+    pub layout: StereoLayout,
+    pub interpupillary: f64,
+    pub convergence: f64,
+    pub omnidirectional: bool, // Omnidirectional stereo of a panorama, whatever the projection
+}
+
+impl View {
+    pub fn default() -> Self {
+        Self {
+            aspect_ratio: 16.0 / 9.0,
+            vfov: 20.0,
+            lookfrom: Point3::new(13.0, 2.0, 3.0),
+            lookat: Point3::new(0.0, 0.0, 0.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            defocus_angle: 0.6,
+            focus_dist: 10.0,
+            projection: None,
+            aperture: Aperture::Circle,
+            cat_eye: 0.0,
+            stereo: None,
+            physical: None,
+            animation: CameraAnimation::new(),
+        }
+    }
+
+    fn shaped_lens(&self) -> bool {
+        !matches!(self.aperture, Aperture::Circle) || self.cat_eye != 0.0
+    }
+
+    pub fn take_projection(&mut self) -> Option<Box<dyn Projection>> {
+        // The projection built from the settings, None for the camera's plain perspective.
+        // It is built once the scene is read, so that camera lines can come in any order.
+        let perspective = || -> Box<dyn Projection> {
+            let mut perspective = Perspective::new(self.vfov, self.defocus_angle, self.focus_dist);
+            perspective.aperture = self.aperture.clone();
+            perspective.cat_eye = self.cat_eye;
+            Box::new(perspective)
+        };
+        let projection = self.projection.take();
+        let Some(rig) = self.stereo else {
+            return projection.or_else(|| self.shaped_lens().then(perspective));
+        };
+        let eye = match (rig.omnidirectional, projection) {
+            (true, _) => Box::new(Equirectangular::new()),
+            (false, Some(projection)) => projection,
+            (false, None) => perspective(),
+        };
+        let mut stereo = Stereo::new(eye, rig.interpupillary, rig.convergence, rig.layout);
+        stereo.omnidirectional = rig.omnidirectional;
+        Some(Box::new(stereo))
+    }
+
+    pub fn pose(&self, time: f64) -> CameraPose {
+        // The camera settings at `time`. A physical camera opens its lens to the f-number
+        // for the animated field of view and focus, unless the defocus angle is keyed too.
+        let still = CameraPose {
+            lookfrom: self.lookfrom,
+            lookat: self.lookat,
+            vfov: self.vfov,
+            focus_dist: self.focus_dist,
+            defocus_angle: self.defocus_angle,
+        };
+        let mut pose = self.animation.pose(still, time);
+        if let Some(physical) = self.physical {
+            if self.animation.defocus_angle.is_empty() {
+                pose.defocus_angle = physical.defocus_angle(pose.vfov, pose.focus_dist);
+            }
+        }
+        pose
+    }
+}
+
+pub struct Scene {
+    pub world: HittableList,
+    pub view: View,
+    pub background: Sky,
+    pub timing: Option<Timing>, // Frame rate and shutter, for scenes with an animation
+}
+
+pub fn load(path: &str, working_space: ColorSpace) -> Result<Scene, Error> {
+    let text = fs::read_to_string(path)?;
+    let mut parser = Parser {
+        path,
+        line: 0,
+        working_space,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        objects: Vec::new(),
+        names: HashMap::new(),
+        scene: Scene {
+            world: HittableList::new(),
+            view: View::default(),
+            background: Sky {
+                horizon: ColorSpace::LinearSrgb.convert(Color::same(1.0), working_space),
+                zenith: ColorSpace::LinearSrgb.convert(Color::new(0.5, 0.7, 1.0), working_space),
+            },
+            timing: None,
+        },
+    };
+    for (index, line) in text.lines().enumerate() {
+        parser.line = index + 1;
+        let line = line.split('#').next().unwrap_or("");
+        let mut words = line.split_whitespace();
+        if let Some(keyword) = words.next() {
+            parser.statement(keyword, words.collect())?;
+        }
+    }
+
+    // Objects with keys move with them.
+    for (object, motion) in parser.objects {
+        match motion.is_empty() {
+            true => parser.scene.world.objects.push(object),
+            false => parser.scene.world.add(Animated::new(object, motion)),
+        }
+    }
+    Ok(parser.scene)
+}
+
+fn solid_gray(value: f64) -> Arc<dyn Texture> {
+    Arc::new(SolidColor::new(Color::same(value)))
+}
+
+struct Parser<'a> {
+    path: &'a str,
+    line: usize,
+    working_space: ColorSpace,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    objects: Vec<(Box<dyn Hittable>, Motion)>, // Added to the world once their keys are read
+    names: HashMap<String, usize>,             // Index in `objects` of named objects
+    scene: Scene,
+}
+
+// The `key=value` parameters of a statement, removed as they are read so that anything
+// left over can be reported.
+struct Params<'a> {
+    values: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Params<'a> {
+    fn take(&mut self, key: &str) -> Option<&'a str> {
+        let index = self.values.iter().position(|(k, _)| *k == key)?;
+        Some(self.values.remove(index).1)
+    }
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> Error {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{}:{}: {message}", self.path, self.line),
+        )
+    }
+
+    fn statement(&mut self, keyword: &str, words: Vec<&str>) -> Result<(), Error> {
+        // Named statements start with their name and type.
+        let positional = match keyword {
+            "texture" | "material" => 2,
+            "key" => 1,
+            _ => 0,
+        };
+        if words.len() < positional || words[..positional].iter().any(|w| w.contains('=')) {
+            return match positional {
+                1 => Err(self.error(&format!("{keyword} needs a name"))),
+                _ => Err(self.error(&format!("{keyword} needs a name and a type"))),
+            };
+        }
+        let mut params = Params { values: vec![] };
+        for word in &words[positional..] {
+            match word.split_once('=') {
+                Some((key, value)) => params.values.push((key, value)),
+                None => return Err(self.error(&format!("expected key=value, found {word}"))),
+            }
+        }
+
+        match keyword {
+            "camera" => self.camera(&mut params)?,
+            "background" => self.background(&mut params)?,
+            "texture" => {
+                let texture = self.texture(words[1], &mut params)?;
+                self.textures.insert(String::from(words[0]), texture);
+            }
+            "material" => {
+                let material = self.material(words[1], &mut params)?;
+                self.materials.insert(String::from(words[0]), material);
+            }
+            "sphere" => self.sphere(&mut params)?,
+            "light" => self.light(&mut params)?,
+            "animation" => self.animation(&mut params)?,
+            "key" => self.key(words[0], &mut params)?,
+            _ => return Err(self.error(&format!("unknown statement: {keyword}"))),
+        }
+
+        match params.values.first() {
+            Some((key, _)) => Err(self.error(&format!("unknown parameter for {keyword}: {key}"))),
+            None => Ok(()),
+        }
+    }
+
+    fn seconds(&self, value: &str) -> Result<f64, Error> {
+        // A number or a fraction such as 1/125.
+        match value.split_once('/') {
+            Some((numerator, denominator)) => {
+                Ok(self.number(numerator)? / self.number(denominator)?)
+            }
+            None => self.number(value),
+        }
+    }
+
+    fn number(&self, value: &str) -> Result<f64, Error> {
+        value
+            .parse()
+            .map_err(|_| self.error(&format!("invalid number: {value}")))
+    }
+
+    fn vector(&self, value: &str) -> Result<Vec3, Error> {
+        let parts = value
+            .split(',')
+            .map(|part| self.number(part))
+            .collect::<Result<Vec<f64>, Error>>()?;
+        match parts[..] {
+            [x, y, z] => Ok(Vec3::new(x, y, z)),
+            _ => Err(self.error(&format!("expected three components: {value}"))),
+        }
+    }
+
+    fn numbers<const N: usize>(&self, value: &str) -> Result<[f64; N], Error> {
+        let parts = value
+            .split(',')
+            .map(|part| self.number(part))
+            .collect::<Result<Vec<f64>, Error>>()?;
+        parts
+            .try_into()
+            .map_err(|_| self.error(&format!("expected {N} components: {value}")))
+    }
+
+    fn glass(&self, value: &str) -> Result<Dispersion, Error> {
+        match value {
+            "bk7" => Ok(Dispersion::BK7),
+            "sf11" => Ok(Dispersion::SF11),
+            _ => Err(self.error(&format!("unknown glass: {value}"))),
+        }
+    }
+
+    fn cauchy(&self, value: &str) -> Result<Dispersion, Error> {
+        // A,B with B in square micrometers.
+        let [a, b] = self.numbers(value)?;
+        Ok(Dispersion::Cauchy { a, b })
+    }
+
+    fn sellmeier(&self, value: &str) -> Result<Dispersion, Error> {
+        // B1,B2,B3,C1,C2,C3 with the C terms in square micrometers, as glass catalogs give
+        // them.
+        let [b1, b2, b3, c1, c2, c3] = self.numbers(value)?;
+        Ok(Dispersion::Sellmeier {
+            b: [b1, b2, b3],
+            c: [c1, c2, c3],
+        })
+    }
+
+    fn color(&self, value: &str) -> Result<Color, Error> {
+        let c = match value.contains(',') {
+            true => self.vector(value)?,
+            false => Color::same(self.number(value)?),
+        };
+        Ok(ColorSpace::LinearSrgb.convert(c, self.working_space))
+    }
+
+    fn color_texture(&self, value: &str) -> Result<Arc<dyn Texture>, Error> {
+        // A texture name or a constant color.
+        if let Some(texture) = self.textures.get(value) {
+            return Ok(texture.clone());
+        }
+        if value.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Err(self.error(&format!("unknown texture: {value}")));
+        }
+        Ok(Arc::new(SolidColor::new(self.color(value)?)))
+    }
+
+    fn scalar_texture(&self, value: &str) -> Result<Arc<dyn Texture>, Error> {
+        // Unlike colors, scalars are not converted between color spaces.
+        if self.textures.contains_key(value) {
+            return self.color_texture(value);
+        }
+        Ok(solid_gray(self.number(value)?))
+    }
+
+    fn read<T>(
+        &self,
+        params: &mut Params,
+        key: &str,
+        parse: impl Fn(&Self, &str) -> Result<T, Error>,
+        default: T,
+    ) -> Result<T, Error> {
+        match params.take(key) {
+            Some(value) => parse(self, value),
+            None => Ok(default),
+        }
+    }
+
+    fn required<T>(
+        &self,
+        params: &mut Params,
+        key: &str,
+        parse: impl Fn(&Self, &str) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        match params.take(key) {
+            Some(value) => parse(self, value),
+            None => Err(self.error(&format!("missing parameter: {key}"))),
+        }
+    }
+
+    fn optional<T>(
+        &self,
+        params: &mut Params,
+        key: &str,
+        parse: impl Fn(&Self, &str) -> Result<T, Error>,
+    ) -> Result<Option<T>, Error> {
+        match params.take(key) {
+            Some(value) => parse(self, value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn camera(&mut self, params: &mut Params) -> Result<(), Error> {
+        let previous = self.scene.view.projection.take();
+        let view = &self.scene.view;
+        let mut view = View {
+            aspect_ratio: self.read(params, "aspect_ratio", Self::number, view.aspect_ratio)?,
+            vfov: self.read(params, "vfov", Self::number, view.vfov)?,
+            lookfrom: self.read(params, "lookfrom", Self::vector, view.lookfrom)?,
+            lookat: self.read(params, "lookat", Self::vector, view.lookat)?,
+            vup: self.read(params, "vup", Self::vector, view.vup)?,
+            defocus_angle: self.read(params, "defocus_angle", Self::number, view.defocus_angle)?,
+            focus_dist: self.read(params, "focus_dist", Self::number, view.focus_dist)?,
+            projection: None,
+            aperture: view.aperture.clone(),
+            cat_eye: view.cat_eye,
+            stereo: view.stereo,
+            physical: self.physical(params, view.physical)?,
+            animation: view.animation.clone(),
+        };
+        if let Some(physical) = view.physical {
+            view.defocus_angle = physical.defocus_angle(view.vfov, view.focus_dist);
+        }
+        view.projection = match params.take("projection") {
+            None => previous,
+            Some("perspective") => None,
+            Some("orthographic") => Some(Box::new(Orthographic::new(self.read(
+                params,
+                "view_width",
+                Self::number,
+                10.0,
+            )?))),
+            Some("fisheye") => {
+                let fov = self.read(params, "fov", Self::number, 180.0)?;
+                let mapping = match params.take("mapping") {
+                    None | Some("equidistant") => FisheyeMapping::Equidistant,
+                    Some("equisolid") => FisheyeMapping::Equisolid,
+                    Some(mapping) => {
+                        return Err(self.error(&format!("unknown fisheye mapping: {mapping}")))
+                    }
+                };
+                Some(Box::new(Fisheye::new(fov, mapping)))
+            }
+            Some("equirectangular") => Some(Box::new(Equirectangular::new())),
+            Some("realistic") => Some(Box::new(self.lens(params, view.focus_dist)?)),
+            Some(projection) => {
+                return Err(self.error(&format!("unknown projection: {projection}")))
+            }
+        };
+        self.scene.view = view;
+        self.aperture(params)?;
+        self.stereo(params)?;
+        let view = &self.scene.view;
+        let panorama = view.stereo.is_some_and(|rig| rig.omnidirectional);
+        if view.shaped_lens() && (view.projection.is_some() || panorama) {
+            return Err(self.error("apertures need the perspective projection"));
+        }
+        Ok(())
+    }
+
+    fn physical(
+        &self,
+        params: &mut Params,
+        previous: Option<PhysicalCamera>,
+    ) -> Result<Option<PhysicalCamera>, Error> {
+        // The exposure settings, which turn the physical camera on when first given.
+        let f_number = params.take("f_number");
+        let shutter = params.take("shutter");
+        let iso = params.take("iso");
+        let sensor_height = params.take("sensor_height");
+        let given = [f_number, shutter, iso, sensor_height];
+        if previous.is_none() && given.iter().all(Option::is_none) {
+            return Ok(None);
+        }
+        let mut physical = previous.unwrap_or(PhysicalCamera::new(8.0, 1.0 / 125.0, 100.0));
+        if let Some(value) = f_number {
+            physical.f_number = self.number(value)?;
+        }
+        if let Some(value) = shutter {
+            physical.shutter = self.seconds(value)?;
+        }
+        if let Some(value) = iso {
+            physical.iso = self.number(value)?;
+        }
+        if let Some(value) = sensor_height {
+            physical.sensor_height = self.number(value)? * 0.001;
+        }
+        Ok(Some(physical))
+    }
+
+    fn lens(&self, params: &mut Params, focus_dist: f64) -> Result<Realistic, Error> {
+        // A lens from a prescription file, focused at `focus_dist`.
+        let file = params
+            .take("lens")
+            .ok_or_else(|| self.error("realistic projection needs a lens"))?;
+        let file = Path::new(self.path).with_file_name(file);
+        let film_diagonal = self.read(params, "film_diagonal", Self::number, 35.0)?;
+        let stop_diameter = self.optional(params, "stop_diameter", Self::number)?;
+        Realistic::load(
+            &file.to_string_lossy(),
+            film_diagonal * 0.001,
+            focus_dist,
+            stop_diameter,
+        )
+        .map_err(|e| self.error(&e.to_string()))
+    }
+
+    fn aperture(&mut self, params: &mut Params) -> Result<(), Error> {
+        // The shape of the perspective projection's lens opening, if given.
+        let aperture = match params.take("aperture") {
+            None => self.scene.view.aperture.clone(),
+            Some("circle") => Aperture::Circle,
+            Some("polygon") => Aperture::Polygon {
+                blades: self.read(params, "blades", Self::number, 6.0)? as i32,
+                rotation: self.read(params, "blade_rotation", Self::number, 0.0)?,
+            },
+            Some("annulus") => Aperture::Annulus {
+                inner: self.read(params, "inner", Self::number, 0.5)?,
+            },
+            Some("image") => Aperture::Image(Arc::new(ApertureImage::new(self.required(
+                params,
+                "aperture_image",
+                Self::texture_ref,
+            )?))),
+            Some(aperture) => return Err(self.error(&format!("unknown aperture: {aperture}"))),
+        };
+        let view = &self.scene.view;
+        let cat_eye = self.read(params, "cat_eye", Self::number, view.cat_eye)?;
+        let view = &mut self.scene.view;
+        view.aperture = aperture;
+        view.cat_eye = cat_eye;
+        Ok(())
+    }
+
+    fn stereo(&mut self, params: &mut Params) -> Result<(), Error> {
+        // The stereo rig the projection is rendered through, if given.
+        let (layout, omnidirectional) = match params.take("stereo") {
+            None => return Ok(()),
+            Some("none") => {
+                self.scene.view.stereo = None;
+                return Ok(());
+            }
+            Some("side_by_side") => (StereoLayout::SideBySide, false),
+            Some("top_bottom") => (StereoLayout::TopBottom, false),
+            Some("ods") => (StereoLayout::TopBottom, true),
+            Some(stereo) => return Err(self.error(&format!("unknown stereo layout: {stereo}"))),
+        };
+        self.scene.view.stereo = Some(StereoRig {
+            layout,
+            interpupillary: self.read(params, "ipd", Self::number, 0.064)?,
+            convergence: self.read(params, "convergence", Self::number, INF)?,
+            omnidirectional,
+        });
+        Ok(())
+    }
+
+    fn background(&mut self, params: &mut Params) -> Result<(), Error> {
+        let sky = self.scene.background;
+        let mut sky = Sky {
+            horizon: self.read(params, "horizon", Self::color, sky.horizon)?,
+            zenith: self.read(params, "zenith", Self::color, sky.zenith)?,
+        };
+        if let Some(value) = params.take("illuminance") {
+            // Scale the sky so that it lights a horizontal surface with this many lux. The
+            // gradient gives a luminance of (h + 5 z) pi / 6 over the upper hemisphere.
+            let illuminance = luminance(sky.horizon + sky.zenith * 5.0) * PI / 6.0;
+            if illuminance <= 0.0 {
+                return Err(self.error("a black sky has no illuminance"));
+            }
+            let scale = self.number(value)? / illuminance;
+            sky.horizon = sky.horizon * scale;
+            sky.zenith = sky.zenith * scale;
+        }
+        self.scene.background = sky;
+        Ok(())
+    }
+
+    fn texture(&self, kind: &str, params: &mut Params) -> Result<Arc<dyn Texture>, Error> {
+        match kind {
+            "solid" => Ok(Arc::new(SolidColor::new(self.read(
+                params,
+                "color",
+                Self::color,
+                Color::same(1.0),
+            )?))),
+            "image" => {
+                let file = params
+                    .take("path")
+                    .ok_or_else(|| self.error("image texture needs a path"))?;
+                let file = Path::new(self.path).with_file_name(file);
+                let file = file.to_string_lossy();
+                Ok(Arc::new(ImageTexture::load(&file, self.working_space)?))
+            }
+            "data" => {
+                let file = params
+                    .take("path")
+                    .ok_or_else(|| self.error("data texture needs a path"))?;
+                let file = Path::new(self.path).with_file_name(file);
+                Ok(Arc::new(ImageTexture::load_data(&file.to_string_lossy())?))
+            }
+            _ => Err(self.error(&format!("unknown texture type: {kind}"))),
+        }
+    }
+
+    fn material(&self, kind: &str, params: &mut Params) -> Result<Arc<dyn Material>, Error> {
+        let white: Arc<dyn Texture> = Arc::new(SolidColor::new(Color::same(1.0)));
+        let material: Arc<dyn Material> = match kind {
+            "lambertian" => Arc::new(Lambertian::textured(self.read(
+                params,
+                "albedo",
+                Self::color_texture,
+                white,
+            )?)),
+            "metal" => Arc::new(Metal::new(
+                self.read(params, "albedo", Self::color, Color::same(1.0))?,
+                self.read(params, "fuzz", Self::number, 0.0)?,
+            )),
+            "conductor" => {
+                let roughness = self.read(params, "roughness", Self::number, 0.0)?;
+                let anisotropy = self.read(params, "anisotropy", Self::number, 0.0)?;
+                match params.take("preset") {
+                    Some("gold") => Arc::new(Conductor::gold(roughness, anisotropy)),
+                    Some("copper") => Arc::new(Conductor::copper(roughness, anisotropy)),
+                    Some("aluminum") => Arc::new(Conductor::aluminum(roughness, anisotropy)),
+                    Some("silver") => Arc::new(Conductor::silver(roughness, anisotropy)),
+                    Some(preset) => {
+                        return Err(self.error(&format!("unknown conductor preset: {preset}")))
+                    }
+                    // The index of refraction is per channel, and not converted.
+                    None => Arc::new(Conductor::new(
+                        self.read(params, "eta", Self::vector, Color::same(1.0))?,
+                        self.read(params, "k", Self::vector, Color::zero())?,
+                        roughness,
+                        anisotropy,
+                    )),
+                }
+            }
+            "dielectric" => {
+                let roughness = self.read(params, "roughness", Self::number, 0.0)?;
+                let absorption = self.read(params, "absorption", Self::vector, Color::zero())?;
+                let ior = self.read(params, "ior", Self::number, 1.5)?;
+                let dispersions = [
+                    self.optional(params, "glass", Self::glass)?,
+                    self.optional(params, "cauchy", Self::cauchy)?,
+                    self.optional(params, "sellmeier", Self::sellmeier)?,
+                ];
+                match dispersions.into_iter().flatten().collect::<Vec<_>>()[..] {
+                    [] => Arc::new(Dielectric::rough(ior, roughness, absorption)),
+                    [dispersion] => {
+                        Arc::new(Dielectric::dispersive(dispersion, roughness, absorption))
+                    }
+                    _ => {
+                        return Err(
+                            self.error("dielectric takes one of glass, cauchy and sellmeier")
+                        )
+                    }
+                }
+            }
+            "principled" => {
+                let defaults = Principled::textured(white);
+                Arc::new(Principled {
+                    base_color: self.read(
+                        params,
+                        "base_color",
+                        Self::color_texture,
+                        defaults.base_color,
+                    )?,
+                    metallic: self.read(
+                        params,
+                        "metallic",
+                        Self::scalar_texture,
+                        defaults.metallic,
+                    )?,
+                    roughness: self.read(
+                        params,
+                        "roughness",
+                        Self::scalar_texture,
+                        defaults.roughness,
+                    )?,
+                    specular: self.read(
+                        params,
+                        "specular",
+                        Self::scalar_texture,
+                        defaults.specular,
+                    )?,
+                    specular_tint: self.read(
+                        params,
+                        "specular_tint",
+                        Self::scalar_texture,
+                        defaults.specular_tint,
+                    )?,
+                    sheen: self.read(params, "sheen", Self::scalar_texture, defaults.sheen)?,
+                    sheen_tint: self.read(
+                        params,
+                        "sheen_tint",
+                        Self::scalar_texture,
+                        defaults.sheen_tint,
+                    )?,
+                    clearcoat: self.read(
+                        params,
+                        "clearcoat",
+                        Self::scalar_texture,
+                        defaults.clearcoat,
+                    )?,
+                    clearcoat_gloss: self.read(
+                        params,
+                        "clearcoat_gloss",
+                        Self::scalar_texture,
+                        defaults.clearcoat_gloss,
+                    )?,
+                    transmission: self.read(
+                        params,
+                        "transmission",
+                        Self::scalar_texture,
+                        defaults.transmission,
+                    )?,
+                    ior: self.read(params, "ior", Self::scalar_texture, defaults.ior)?,
+                })
+            }
+            "mix" => Arc::new(MixMaterial::textured(
+                self.required(params, "first", Self::material_ref)?,
+                self.required(params, "second", Self::material_ref)?,
+                self.read(params, "weight", Self::scalar_texture, solid_gray(0.5))?,
+            )),
+            "coated" => Arc::new(Coated::new(
+                self.required(params, "base", Self::material_ref)?,
+                self.read(params, "ior", Self::number, 1.5)?,
+                self.read(params, "roughness", Self::number, 0.0)?,
+                self.read(params, "tint", Self::color, Color::same(1.0))?,
+            )),
+            "normal_map" => Arc::new(Perturbed::new(
+                self.required(params, "base", Self::material_ref)?,
+                ShadingNormal::NormalMap(self.required(params, "map", Self::texture_ref)?),
+            )),
+            "bump" => Arc::new(Perturbed::new(
+                self.required(params, "base", Self::material_ref)?,
+                ShadingNormal::Bump {
+                    height: self.required(params, "height", Self::texture_ref)?,
+                    scale: self.read(params, "scale", Self::number, 1.0)?,
+                },
+            )),
+            "cutout" => Arc::new(Cutout::new(
+                self.required(params, "base", Self::material_ref)?,
+                self.required(params, "opacity", Self::scalar_texture)?,
+                self.optional(params, "threshold", Self::number)?,
+            )),
+            _ => return Err(self.error(&format!("unknown material type: {kind}"))),
+        };
+        Ok(material)
+    }
+
+    fn texture_ref(&self, name: &str) -> Result<Arc<dyn Texture>, Error> {
+        match self.textures.get(name) {
+            Some(texture) => Ok(texture.clone()),
+            None => Err(self.error(&format!("unknown texture: {name}"))),
+        }
+    }
+
+    fn material_ref(&self, name: &str) -> Result<Arc<dyn Material>, Error> {
+        match self.materials.get(name) {
+            Some(material) => Ok(material.clone()),
+            None => Err(self.error(&format!("unknown material: {name}"))),
+        }
+    }
+
+    fn sphere(&mut self, params: &mut Params) -> Result<(), Error> {
+        let center = self.read(params, "center", Self::vector, Point3::zero())?;
+        let radius = self.read(params, "radius", Self::number, 1.0)?;
+        let material = self.required(params, "material", Self::material_ref)?;
+        self.object(params, Sphere::new(center, radius, material))
+    }
+
+    fn light(&mut self, params: &mut Params) -> Result<(), Error> {
+        // A spherical light, given in photometric units or as the radiance of its color.
+        let center = self.read(params, "center", Self::vector, Point3::zero())?;
+        let radius = self.read(params, "radius", Self::number, 1.0)?;
+        let color = self.read(params, "color", Self::color, Color::same(1.0))?;
+        let nits = match (params.take("luminance"), params.take("power")) {
+            (None, None) => None,
+            (Some(value), None) => Some(self.number(value)?),
+            (None, Some(value)) => {
+                // A sphere of luminance L gives off pi L lumens per unit of its area.
+                let lumens = self.number(value)? * LUMINOUS_EFFICACY;
+                Some(lumens / (PI * 4.0 * PI * radius * radius))
+            }
+            (Some(_), Some(_)) => {
+                return Err(self.error("light takes luminance or power, not both"))
+            }
+        };
+        let emit = match nits {
+            None => color,
+            Some(_) if luminance(color) <= 0.0 => {
+                return Err(self.error("light color must not be black"))
+            }
+            Some(nits) => color * (nits / luminance(color)),
+        };
+        let material = Arc::new(DiffuseLight::new(emit));
+        self.object(params, Sphere::new(center, radius, material))
+    }
+
+    fn object(
+        &mut self,
+        params: &mut Params,
+        object: impl Hittable + 'static,
+    ) -> Result<(), Error> {
+        // Adds an object, under its name if it has one so that keys can move it.
+        if let Some(name) = params.take("name") {
+            if self.names.contains_key(name) || name == "camera" {
+                return Err(self.error(&format!("duplicate object name: {name}")));
+            }
+            self.names.insert(String::from(name), self.objects.len());
+        }
+        self.objects.push((Box::new(object), Motion::new()));
+        Ok(())
+    }
+
+    fn animation(&mut self, params: &mut Params) -> Result<(), Error> {
+        let timing = self.scene.timing.unwrap_or(Timing::default());
+        let timing = Timing {
+            fps: self.read(params, "fps", Self::number, timing.fps)?,
+            shutter: self.read(params, "shutter", Self::number, timing.shutter)?,
+        };
+        if timing.fps <= 0.0 || !(0.0..=1.0).contains(&timing.shutter) {
+            return Err(self.error("animation needs fps above 0 and shutter from 0 to 1"));
+        }
+        self.scene.timing = Some(timing);
+        Ok(())
+    }
+
+    fn key(&mut self, name: &str, params: &mut Params) -> Result<(), Error> {
+        // A keyframe of the camera or of a named object.
+        let time = self.required(params, "time", Self::seconds)?;
+        let interpolation = match params.take("interpolation") {
+            None | Some("linear") => Interpolation::Linear,
+            Some("catmull_rom") => Interpolation::CatmullRom,
+            Some("bezier") => Interpolation::Bezier,
+            Some("monotone") => Interpolation::Monotone,
+            Some(interpolation) => {
+                return Err(self.error(&format!("unknown interpolation: {interpolation}")))
+            }
+        };
+        if params.values.is_empty() {
+            return Err(self.error(&format!("key {name} has no values")));
+        }
+        self.scene.timing.get_or_insert(Timing::default());
+
+        if name == "camera" {
+            let lookfrom = self.keyed(params, "lookfrom", Self::vector)?;
+            let lookat = self.keyed(params, "lookat", Self::vector)?;
+            let vfov = self.keyed(params, "vfov", Self::number)?;
+            let focus_dist = self.keyed(params, "focus_dist", Self::number)?;
+            let defocus_angle = self.keyed(params, "defocus_angle", Self::number)?;
+            let animation = &mut self.scene.view.animation;
+            for (track, value) in [
+                (&mut animation.lookfrom, lookfrom),
+                (&mut animation.lookat, lookat),
+            ] {
+                if let Some((value, handles)) = value {
+                    track.add(Key {
+                        time,
+                        value,
+                        interpolation,
+                        handles,
+                    });
+                }
+            }
+            for (track, value) in [
+                (&mut animation.vfov, vfov),
+                (&mut animation.focus_dist, focus_dist),
+                (&mut animation.defocus_angle, defocus_angle),
+            ] {
+                if let Some((value, handles)) = value {
+                    track.add(Key {
+                        time,
+                        value,
+                        interpolation,
+                        handles,
+                    });
+                }
+            }
+            return Ok(());
+        }
+
+        let index = *self
+            .names
+            .get(name)
+            .ok_or_else(|| self.error(&format!("unknown object: {name}")))?;
+        let translate = self.keyed(params, "translate", Self::vector)?;
+        let rotate = self.keyed(params, "rotate", Self::vector)?;
+        let scale = self.keyed(params, "scale", Self::number)?;
+        if scale.is_some_and(|(scale, _)| scale <= 0.0) {
+            return Err(self.error("scale must be above 0"));
+        }
+        let motion = &mut self.objects[index].1;
+        for (track, value) in [
+            (&mut motion.translate, translate),
+            (&mut motion.rotate, rotate),
+        ] {
+            if let Some((value, handles)) = value {
+                track.add(Key {
+                    time,
+                    value,
+                    interpolation,
+                    handles,
+                });
+            }
+        }
+        if let Some((value, handles)) = scale {
+            motion.scale.add(Key {
+                time,
+                value,
+                interpolation,
+                handles,
+            });
+        }
+        Ok(())
+    }
+
+    fn keyed<T>(
+        &self,
+        params: &mut Params,
+        key: &str,
+        parse: impl Fn(&Self, &str) -> Result<T, Error>,
+    ) -> Result<Option<(T, Handles<T>)>, Error> {
+        // A keyed value with its Bezier handles, given as KEY_in and KEY_out.
+        let value = self.optional(params, key, &parse)?;
+        let handles = [
+            self.optional(params, &format!("{key}_in"), &parse)?,
+            self.optional(params, &format!("{key}_out"), &parse)?,
+        ];
+        match value {
+            None if handles.iter().any(Option::is_some) => {
+                Err(self.error(&format!("handles of {key} need a value")))
+            }
+            None => Ok(None),
+            Some(value) => Ok(Some((value, handles))),
+        }
+    }
+}
@@ -0,0 +1,399 @@
+// Spectral rendering support.
+//
+// In spectral mode every camera path carries a few wavelengths, sampled by the hero
+// wavelength scheme (Wilkie et al. 2014): a first wavelength is importance sampled over
+// the visible range and the others are spread evenly from it, so that a path contributes
+// to the whole spectrum. The three components of the colors carried along such a path
+// are the values at these wavelengths rather than RGB.
+//
+// RGB colors of the scene are turned into smooth spectra with the sigmoid polynomial model
+// of Jakob and Hanika 2019 ("A Low-Dimensional Function Space for Efficient Spectral
+// Upsampling"), whose coefficients are fitted into a table on first use. Path results are
+// projected back to CIE XYZ with the color matching functions and on to RGB.
+
+use std::sync::OnceLock;
+
+use crate::color::{invert_matrix, mul_matrix, ColorSpace, Matrix3};
+use crate::Color;
+
+pub const SAMPLES: usize = 3;
+
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 780.0;
+
+fn piecewise_gaussian(lambda: f64, mu: f64, sigma_low: f64, sigma_high: f64) -> f64 {
+    let sigma = if lambda < mu { sigma_low } else { sigma_high };
+    (-0.5 * ((lambda - mu) / sigma).powi(2)).exp()
+}
+
+pub fn cie_xyz(lambda: f64) -> Color {
+    // CIE 1931 2 degree color matching functions, by the multi-lobe fit of Wyman, Sloan and
+    // Shirley 2013.
+    let g = piecewise_gaussian;
+    Color::new(
+        1.056 * g(lambda, 599.8, 37.9, 31.0) + 0.362 * g(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * g(lambda, 501.1, 20.4, 26.2),
+        0.821 * g(lambda, 568.8, 46.9, 40.5) + 0.286 * g(lambda, 530.9, 16.3, 31.1),
+        1.217 * g(lambda, 437.0, 11.8, 36.0) + 0.681 * g(lambda, 459.0, 26.0, 13.8),
+    )
+}
+
+// Relative spectral power of CIE standard illuminant D65, every 10 nm from 360 nm.
+const D65: [f64; 43] = [
+    46.6383, 52.0891, 49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008,
+    117.812, 114.861, 115.923, 108.811, 109.354, 107.802, 104.790, 107.689, 104.405, 104.046,
+    100.000, 96.3342, 95.788, 88.6856, 90.0062, 89.5991, 87.6987, 83.2886, 83.6992, 80.0268,
+    80.2146, 82.2778, 78.2842, 69.7213, 71.6091, 74.349, 61.604, 69.8856, 75.087, 63.5927, 46.4182,
+    66.8054, 63.3828,
+];
+
+fn d65_relative(lambda: f64) -> f64 {
+    let x = ((lambda - LAMBDA_MIN) / 10.0).clamp(0.0, (D65.len() - 1) as f64);
+    let i = (x as usize).min(D65.len() - 2);
+    let t = x - i as f64;
+    D65[i] * (1.0 - t) + D65[i + 1] * t
+}
+
+// Constants of the integration over the visible range, at 1 nm steps.
+struct Integrals {
+    cie_y: f64, // Integral of the y color matching function
+    d65_y: f64, // Integral of D65 times the y color matching function
+}
+
+fn integrals() -> &'static Integrals {
+    static INTEGRALS: OnceLock<Integrals> = OnceLock::new();
+    INTEGRALS.get_or_init(|| {
+        let mut cie_y = 0.0;
+        let mut d65_y = 0.0;
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            let y = cie_xyz(lambda).y();
+            cie_y += y;
+            d65_y += d65_relative(lambda) * y;
+            lambda += 1.0;
+        }
+        Integrals { cie_y, d65_y }
+    })
+}
+
+pub fn d65(lambda: f64) -> f64 {
+    // D65 scaled to a luminance Y of 1.
+    d65_relative(lambda) * integrals().cie_y / integrals().d65_y
+}
+
+// Wavelengths of a path, in nanometers, with the densities they were sampled with.
+#[derive(Debug, Copy, Clone)]
+pub struct SampledWavelengths {
+    pub lambda: [f64; SAMPLES],
+    pub pdf: [f64; SAMPLES],
+    pub secondary_terminated: bool, // Only the hero wavelength is still followed
+}
+
+// Visible wavelengths are sampled proportionally to 1 / cosh^2(B (lambda - CENTER)), which
+// follows the sum of the color matching functions (Radziszewski et al. 2009).
+const CENTER: f64 = 538.0;
+const B: f64 = 0.0072;
+
+impl SampledWavelengths {
+    pub fn sample_visible(u: f64) -> Self {
+        let t_min = (B * (LAMBDA_MIN - CENTER)).tanh();
+        let t_max = (B * (LAMBDA_MAX - CENTER)).tanh();
+        let mut lambda = [0.0; SAMPLES];
+        let mut pdf = [0.0; SAMPLES];
+        for k in 0..SAMPLES {
+            let up = (u + k as f64 / SAMPLES as f64).fract();
+            lambda[k] = CENTER + (t_min + up * (t_max - t_min)).atanh() / B;
+            pdf[k] = B / (t_max - t_min) / (B * (lambda[k] - CENTER)).cosh().powi(2);
+        }
+        Self {
+            lambda,
+            pdf,
+            secondary_terminated: false,
+        }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    pub fn terminate_secondary(&mut self, values: Color) -> Color {
+        // Past a wavelength dependent refraction the wavelengths no longer share a path and
+        // only the hero is followed. It then stands for all of them.
+        if self.secondary_terminated {
+            return values;
+        }
+        self.secondary_terminated = true;
+        Color::new(values.x() * SAMPLES as f64, 0.0, 0.0)
+    }
+
+    pub fn to_xyz(self, values: Color) -> Color {
+        // Monte Carlo estimate of the XYZ color of a spectrum known at these wavelengths.
+        let values = [values.x(), values.y(), values.z()];
+        let mut xyz = Color::zero();
+        for ((lambda, pdf), value) in self.lambda.iter().zip(self.pdf).zip(values) {
+            if pdf > 0.0 {
+                xyz += cie_xyz(*lambda) * (value / pdf);
+            }
+        }
+        xyz / (SAMPLES as f64 * integrals().cie_y)
+    }
+
+    pub fn to_rgb(self, values: Color, space: ColorSpace) -> Color {
+        mul_matrix(&space.xyz_to_rgb(), self.to_xyz(values))
+    }
+}
+
+// Spectrum sigmoid(c0 x^2 + c1 x + c2), with x the wavelength mapped from the visible range
+// to [0,1]. It stays within [0,1], which makes it a valid reflectance.
+#[derive(Debug, Copy, Clone)]
+pub struct SigmoidPolynomial {
+    c: [f64; 3],
+}
+
+fn sigmoid(x: f64) -> f64 {
+    if x.is_infinite() {
+        return if x > 0.0 { 1.0 } else { 0.0 };
+    }
+    0.5 + x / (2.0 * (1.0 + x * x).sqrt())
+}
+
+fn normalized_lambda(lambda: f64) -> f64 {
+    (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN)
+}
+
+impl SigmoidPolynomial {
+    pub fn constant(value: f64) -> Self {
+        // Inverse of the sigmoid, infinite at 0 and 1.
+        let value = value.clamp(0.0, 1.0);
+        let c2 = (value - 0.5) / (value * (1.0 - value)).sqrt();
+        Self { c: [0.0, 0.0, c2] }
+    }
+
+    pub fn evaluate(&self, lambda: f64) -> f64 {
+        let x = normalized_lambda(lambda);
+        sigmoid((self.c[0] * x + self.c[1]) * x + self.c[2])
+    }
+}
+
+const TABLE_RES: usize = 16;
+const FIT_STEP: f64 = 5.0; // Wavelength step of the integration while fitting, in nm
+
+// Coefficients of the sigmoid polynomials reproducing RGB reflectances of one color space.
+// A color is looked up by its largest component z and the two others divided by z.
+struct RgbToSpectrumTable {
+    z_nodes: [f64; TABLE_RES],
+    coefficients: Vec<[f64; 3]>, // Indexed by largest component, z, y and x
+}
+
+fn smoothstep(x: f64) -> f64 {
+    x * x * (3.0 - 2.0 * x)
+}
+
+impl RgbToSpectrumTable {
+    fn index(max_component: usize, zi: usize, yi: usize, xi: usize) -> usize {
+        ((max_component * TABLE_RES + zi) * TABLE_RES + yi) * TABLE_RES + xi
+    }
+
+    fn fit(space: ColorSpace) -> Self {
+        // Gauss-Newton fit of every table entry, starting from the solution of its neighbour
+        // in z as in the reference implementation.
+        let xyz_to_rgb = space.xyz_to_rgb();
+        let mut weights = Vec::new();
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            let xyz = cie_xyz(lambda) * (d65(lambda) * FIT_STEP / integrals().cie_y);
+            weights.push((normalized_lambda(lambda), mul_matrix(&xyz_to_rgb, xyz)));
+            lambda += FIT_STEP;
+        }
+        let rgb_of = |c: &[f64; 3]| {
+            weights.iter().fold(Color::zero(), |sum, &(x, w)| {
+                sum + w * sigmoid((c[0] * x + c[1]) * x + c[2])
+            })
+        };
+
+        let mut z_nodes = [0.0; TABLE_RES];
+        for (k, z) in z_nodes.iter_mut().enumerate() {
+            *z = smoothstep(smoothstep(k as f64 / (TABLE_RES - 1) as f64));
+        }
+        let mut coefficients = vec![[0.0; 3]; 3 * TABLE_RES * TABLE_RES * TABLE_RES];
+        let start = TABLE_RES / 5;
+        for max_component in 0..3 {
+            for yi in 0..TABLE_RES {
+                let y = yi as f64 / (TABLE_RES - 1) as f64;
+                for xi in 0..TABLE_RES {
+                    let x = xi as f64 / (TABLE_RES - 1) as f64;
+                    let mut fit_at = |zi: usize, c: [f64; 3]| {
+                        let z = z_nodes[zi];
+                        let mut rgb = [0.0; 3];
+                        rgb[max_component] = z;
+                        rgb[(max_component + 1) % 3] = x * z;
+                        rgb[(max_component + 2) % 3] = y * z;
+                        let c = gauss_newton(Color::new(rgb[0], rgb[1], rgb[2]), c, &rgb_of);
+                        coefficients[Self::index(max_component, zi, yi, xi)] = c;
+                        c
+                    };
+                    let initial = fit_at(start, [0.0; 3]);
+                    let mut c = initial;
+                    for zi in start + 1..TABLE_RES {
+                        c = fit_at(zi, c);
+                    }
+                    let mut c = initial;
+                    for zi in (0..start).rev() {
+                        c = fit_at(zi, c);
+                    }
+                }
+            }
+        }
+        Self {
+            z_nodes,
+            coefficients,
+        }
+    }
+
+    fn lookup(&self, rgb: Color) -> SigmoidPolynomial {
+        let rgb = [
+            rgb.x().clamp(0.0, 1.0),
+            rgb.y().clamp(0.0, 1.0),
+            rgb.z().clamp(0.0, 1.0),
+        ];
+        if rgb[0] == rgb[1] && rgb[1] == rgb[2] {
+            return SigmoidPolynomial::constant(rgb[0]);
+        }
+        let max_component = if rgb[0] > rgb[1] {
+            if rgb[0] > rgb[2] {
+                0
+            } else {
+                2
+            }
+        } else if rgb[1] > rgb[2] {
+            1
+        } else {
+            2
+        };
+        let z = rgb[max_component];
+        let x = rgb[(max_component + 1) % 3] / z * (TABLE_RES - 1) as f64;
+        let y = rgb[(max_component + 2) % 3] / z * (TABLE_RES - 1) as f64;
+        let zi = self
+            .z_nodes
+            .partition_point(|&node| node <= z)
+            .clamp(1, TABLE_RES - 1)
+            - 1;
+        let xi = (x as usize).min(TABLE_RES - 2);
+        let yi = (y as usize).min(TABLE_RES - 2);
+        let (dx, dy) = (x - xi as f64, y - yi as f64);
+        let dz = (z - self.z_nodes[zi]) / (self.z_nodes[zi + 1] - self.z_nodes[zi]);
+
+        // Trilinear interpolation of the coefficients
+        let mut c = [0.0; 3];
+        for (k, wz) in [(0, 1.0 - dz), (1, dz)] {
+            for (j, wy) in [(0, 1.0 - dy), (1, dy)] {
+                for (i, wx) in [(0, 1.0 - dx), (1, dx)] {
+                    let entry =
+                        self.coefficients[Self::index(max_component, zi + k, yi + j, xi + i)];
+                    for m in 0..3 {
+                        c[m] += wx * wy * wz * entry[m];
+                    }
+                }
+            }
+        }
+        SigmoidPolynomial { c }
+    }
+}
+
+fn gauss_newton(
+    target: Color,
+    initial: [f64; 3],
+    rgb_of: &impl Fn(&[f64; 3]) -> Color,
+) -> [f64; 3] {
+    // Colors outside of what reflectances can reach end at the closest reachable one: steps
+    // are shortened until they reduce the error, and the fit stops when none does.
+    let mut c = initial;
+    let mut error = (rgb_of(&c) - target).length();
+    for _ in 0..30 {
+        if error < 1e-6 {
+            break;
+        }
+        // Jacobian by central differences
+        let mut jacobian: Matrix3 = [[0.0; 3]; 3];
+        for m in 0..3 {
+            let (mut lower, mut upper) = (c, c);
+            lower[m] -= 1e-5;
+            upper[m] += 1e-5;
+            let derivative = (rgb_of(&upper) - rgb_of(&lower)) / 2e-5;
+            jacobian[0][m] = derivative.x();
+            jacobian[1][m] = derivative.y();
+            jacobian[2][m] = derivative.z();
+        }
+        let step = mul_matrix(&invert_matrix(&jacobian), rgb_of(&c) - target);
+        if !step.x().is_finite() || !step.y().is_finite() || !step.z().is_finite() {
+            break;
+        }
+
+        let mut improved = false;
+        let mut length = 1.0;
+        while length > 1e-4 {
+            let candidate = [
+                c[0] - length * step.x(),
+                c[1] - length * step.y(),
+                c[2] - length * step.z(),
+            ];
+            let candidate_error = (rgb_of(&candidate) - target).length();
+            // Keep the sigmoid out of its flat tails, where it can no longer be refined.
+            if candidate_error < error && candidate.iter().all(|v| v.abs() < 500.0) {
+                c = candidate;
+                error = candidate_error;
+                improved = true;
+                break;
+            }
+            length *= 0.5;
+        }
+        if !improved {
+            break;
+        }
+    }
+    c
+}
+
+fn table(space: ColorSpace) -> &'static RgbToSpectrumTable {
+    static TABLES: [OnceLock<RgbToSpectrumTable>; 3] =
+        [OnceLock::new(), OnceLock::new(), OnceLock::new()];
+    let index = match space {
+        ColorSpace::LinearSrgb => 0,
+        ColorSpace::AcesCg => 1,
+        ColorSpace::DisplayP3 => 2,
+    };
+    TABLES[index].get_or_init(|| RgbToSpectrumTable::fit(space))
+}
+
+pub fn reflectance(rgb: Color, space: ColorSpace) -> SigmoidPolynomial {
+    // Smooth spectrum with values in [0,1] matching an RGB reflectance or transmittance.
+    // Components outside of [0,1] are clamped.
+    table(space).lookup(rgb)
+}
+
+pub fn sample_reflectance(
+    rgb: Color,
+    space: ColorSpace,
+    wavelengths: &SampledWavelengths,
+) -> Color {
+    let spectrum = reflectance(rgb, space);
+    let [l0, l1, l2] = wavelengths.lambda;
+    Color::new(
+        spectrum.evaluate(l0),
+        spectrum.evaluate(l1),
+        spectrum.evaluate(l2),
+    )
+}
+
+pub fn sample_illuminant(rgb: Color, space: ColorSpace, wavelengths: &SampledWavelengths) -> Color {
+    // Emission whose color under the D65 white is `rgb`: a scaled reflectance spectrum
+    // times D65.
+    let scale = 2.0 * rgb.x().max(rgb.y()).max(rgb.z());
+    if scale <= 0.0 {
+        return Color::zero();
+    }
+    let spectrum = reflectance(rgb / scale, space);
+    let value = |lambda: f64| scale * spectrum.evaluate(lambda) * d65(lambda);
+    let [l0, l1, l2] = wavelengths.lambda;
+    Color::new(value(l0), value(l1), value(l2))
+}
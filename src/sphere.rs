@@ -1,8 +1,10 @@
+use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::interval::Interval;
 use crate::material::Material;
 use crate::ray::Ray;
-use crate::vec3::Point3;
+use crate::utils::PI;
+use crate::vec3::{Point3, Vec3};
 
 #[derive(Debug)]
 pub struct Sphere<M: Material> {
@@ -21,8 +23,27 @@ impl<M: Material> Sphere<M> {
     }
 }
 
+fn get_sphere_uv(p: &Point3) -> (f64, f64) {
+    // p: a given point on the sphere of radius one, centered at the origin.
+    // u: returned value [0,1] of angle around the Y axis from X=-1.
+    // v: returned value [0,1] of angle from Y=-1 to Y=+1.
+    let theta = (-p.y()).acos();
+    let phi = (-p.z()).atan2(p.x()) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
+fn get_sphere_tangents(n: &Vec3, radius: f64) -> (Vec3, Vec3) {
+    // Derivatives of the point at unit normal n with respect to the u and v of
+    // get_sphere_uv. dpdv degenerates at the poles, where only its direction is kept.
+    let (x, y, z) = (n.x(), n.y(), n.z());
+    let sin_theta = (x * x + z * z).sqrt().max(1e-9);
+    let dpdu = Vec3::new(z, 0.0, -x) * (2.0 * PI * radius);
+    let dpdv = Vec3::new(-x * y / sin_theta, sin_theta, -y * z / sin_theta) * (PI * radius);
+    (dpdu, dpdv)
+}
+
 impl<M: Material> Hittable for Sphere<M> {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
         let oc = r.origin() - self.center;
         let a = r.direction().dot_square();
         let half_b = oc.dot(&r.direction());
@@ -35,20 +56,32 @@ impl<M: Material> Hittable for Sphere<M> {
 
         let sqrtd = discriminant.sqrt();
 
-        let mut root = (-half_b - sqrtd) / a;
-        if !ray_t.contains(root) {
-            root = (-half_b + sqrtd) / a;
+        // The nearest root in range, unless the material cuts the surface out there.
+        for root in [(-half_b - sqrtd) / a, (-half_b + sqrtd) / a] {
             if !ray_t.contains(root) {
-                return None;
+                continue;
+            }
+            let mut rec = HitRecord::new();
+            rec.t = root;
+            rec.p = r.at(rec.t);
+            let outward_normal = (rec.p - self.center) / self.radius;
+            rec.set_face_normal(r, &outward_normal);
+            (rec.u, rec.v) = get_sphere_uv(&outward_normal);
+            (rec.dpdu, rec.dpdv) = get_sphere_tangents(&outward_normal, self.radius);
+            rec.mat = &self.mat;
+            rec.set_choice(r);
+            if !self.mat.cut_out(r, &rec) {
+                return Some(rec);
             }
         }
+        None
+    }
 
-        let mut rec = HitRecord::new();
-        rec.t = root;
-        rec.p = r.at(rec.t);
-        let outward_normal = (rec.p - self.center) / self.radius;
-        rec.set_face_normal(r, &outward_normal);
-        rec.mat = &self.mat;
-        Some(rec)
+    fn bounding_box(&self) -> Option<Aabb> {
+        let extent = Vec3::same(self.radius.abs());
+        Some(Aabb::from_points(
+            self.center - extent,
+            self.center + extent,
+        ))
     }
 }
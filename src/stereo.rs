@@ -0,0 +1,109 @@
+// Stereo pairs rendered into one packed image, for headset previews.
+
+use crate::projection::Projection;
+use crate::utils::INF;
+use crate::vec3::{Point3, Vec3};
+
+#[derive(Debug, Copy, Clone)]
+pub enum StereoLayout {
+    SideBySide, // Left eye in the left half
+    TopBottom,  // Left eye in the top half
+}
+
+// Two eyes looking through the same projection, each rendered into its half of the image.
+// With `omnidirectional`, the eyes turn with the direction of every ray instead of staying
+// on the camera's x axis, which is omnidirectional stereo (ODS) when the projection is a
+// 360 degree panorama.
+#[derive(Debug)]
+pub struct Stereo {
+    pub eye: Box<dyn Projection>,
+    pub interpupillary: f64, // Distance between the eyes in scene units
+    pub convergence: f64,    // Distance of zero parallax, infinite for parallel eyes
+    pub layout: StereoLayout,
+    pub omnidirectional: bool,
+}
+
+impl Stereo {
+    pub fn new(
+        eye: Box<dyn Projection>,
+        interpupillary: f64,
+        convergence: f64,
+        layout: StereoLayout,
+    ) -> Self {
+        Self {
+            eye,
+            interpupillary,
+            convergence,
+            layout,
+            omnidirectional: false,
+        }
+    }
+}
+
+impl Projection for Stereo {
+    fn ray(&self, screen: (f64, f64), aspect: f64, lens: (f64, f64)) -> Option<(Point3, Vec3)> {
+        let (origin, direction, _) = self.weighted_ray(screen, aspect, lens, None)?;
+        Some((origin, direction))
+    }
+
+    fn weighted_ray(
+        &self,
+        screen: (f64, f64),
+        aspect: f64,
+        lens: (f64, f64),
+        wavelength: Option<f64>,
+    ) -> Option<(Point3, Vec3, f64)> {
+        // Which eye the point belongs to, and where it is in that eye's image.
+        let (x, y) = screen;
+        let (left, screen, aspect) = match self.layout {
+            StereoLayout::SideBySide if x < 0.0 => (true, (2.0 * x + 1.0, y), aspect / 2.0),
+            StereoLayout::SideBySide => (false, (2.0 * x - 1.0, y), aspect / 2.0),
+            StereoLayout::TopBottom if y >= 0.0 => (true, (x, 2.0 * y - 1.0), aspect * 2.0),
+            StereoLayout::TopBottom => (false, (x, 2.0 * y + 1.0), aspect * 2.0),
+        };
+        let (origin, direction, weight) =
+            self.eye.weighted_ray(screen, aspect, lens, wavelength)?;
+
+        // The eyes sit on either side of the center, across the camera or across the ray.
+        let right = if self.omnidirectional {
+            let across = Vec3::new(-direction.z(), 0.0, direction.x());
+            if across.near_zero() {
+                // Straight up or down, where both eyes see the same.
+                Vec3::zero()
+            } else {
+                across.unit()
+            }
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let side = if left { -0.5 } else { 0.5 };
+        let eye_offset = right * (side * self.interpupillary);
+
+        // Aim both eyes at the point the center ray reaches at the convergence distance:
+        // on a plane in front of the camera, or on a sphere around it for omnidirectional
+        // stereo.
+        let t = if self.convergence >= INF {
+            INF
+        } else if self.omnidirectional {
+            self.convergence / direction.length()
+        } else if direction.z() < 0.0 {
+            self.convergence / -direction.z()
+        } else {
+            INF
+        };
+        let direction = if t >= INF {
+            direction
+        } else {
+            direction * t - eye_offset
+        };
+        Some((origin + eye_offset, direction, weight))
+    }
+
+    fn dispersive(&self) -> bool {
+        self.eye.dispersive()
+    }
+
+    fn set_lens(&mut self, vfov: f64, defocus_angle: f64, focus_dist: f64) {
+        self.eye.set_lens(vfov, defocus_angle, focus_dist);
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt::Debug;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+use crate::checkpoint::hash_bytes;
+use crate::color::{mul_matrix, srgb_decode, ColorSpace};
+use crate::interval::Interval;
+use crate::Color;
+use crate::Point3;
+
+pub trait Texture: Debug + Send + Sync {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SolidColor {
+    albedo: Color,
+}
+
+impl SolidColor {
+    pub fn new(albedo: Color) -> Self {
+        Self { albedo }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        self.albedo
+    }
+}
+
+// An 8-bit sRGB encoded image, stored decoded to linear values in the working space.
+pub struct ImageTexture {
+    path: String,
+    content_hash: u64,
+    width: i32,
+    height: i32,
+    pixels: Vec<Color>,
+    data: bool, // Values used as stored and interpolated, see `load_data`
+}
+
+impl Debug for ImageTexture {
+    // The pixels are summarized by a hash to keep scene descriptions short.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImageTexture")
+            .field("path", &self.path)
+            .field("content_hash", &self.content_hash)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("data", &self.data)
+            .finish()
+    }
+}
+
+fn invalid_data(path: &str, message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("{path}: {message}"))
+}
+
+impl ImageTexture {
+    pub fn load(path: &str, working_space: ColorSpace) -> Result<Self, Error> {
+        Self::read(path, Some(working_space))
+    }
+
+    pub fn load_data(path: &str) -> Result<Self, Error> {
+        // For images holding data rather than colors, such as normal or height maps, whose
+        // values are used as stored. They are interpolated between pixels, since bump
+        // mapping differentiates them.
+        Self::read(path, None)
+    }
+
+    fn read(path: &str, working_space: Option<ColorSpace>) -> Result<Self, Error> {
+        // Reads an ASCII (P3) or binary (P6) PPM file with at most 8 bits per channel.
+        let bytes = fs::read(path)?;
+
+        let mut pos = 0;
+        let mut header = Vec::new();
+        while header.len() < 4 {
+            // Header tokens are separated by whitespace and may be followed by comments.
+            while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'#') {
+                if bytes[pos] == b'#' {
+                    while pos < bytes.len() && bytes[pos] != b'\n' {
+                        pos += 1;
+                    }
+                } else {
+                    pos += 1;
+                }
+            }
+            let start = pos;
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid_data(path, "truncated PPM header"));
+            }
+            header.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+        }
+        let number = |s: &str| {
+            s.parse::<i32>()
+                .map_err(|_| invalid_data(path, "invalid PPM header"))
+        };
+        let (width, height, max_value) = (
+            number(&header[1])?,
+            number(&header[2])?,
+            number(&header[3])?,
+        );
+        if width <= 0 || height <= 0 || max_value <= 0 || max_value > 255 {
+            return Err(invalid_data(path, "only 8-bit PPM images are supported"));
+        }
+        let count = (width * height * 3) as usize;
+        let values: Vec<i32> = match header[0].as_str() {
+            "P6" => {
+                // A single whitespace character separates the header from the samples.
+                let data = &bytes[(pos + 1).min(bytes.len())..];
+                if data.len() < count {
+                    return Err(invalid_data(path, "truncated PPM data"));
+                }
+                data[..count].iter().map(|&b| b as i32).collect()
+            }
+            "P3" => {
+                let values: Vec<i32> = String::from_utf8_lossy(&bytes[pos..])
+                    .split_ascii_whitespace()
+                    .take(count)
+                    .map(number)
+                    .collect::<Result<_, _>>()?;
+                if values.len() < count {
+                    return Err(invalid_data(path, "truncated PPM data"));
+                }
+                values
+            }
+            _ => return Err(invalid_data(path, "not a PPM image")),
+        };
+
+        let value = |v: i32| v as f64 / max_value as f64;
+        let pixels = match working_space {
+            Some(working_space) => {
+                let to_working = ColorSpace::LinearSrgb.conversion_to(working_space);
+                let decode = |v: i32| srgb_decode(value(v));
+                values
+                    .chunks(3)
+                    .map(|c| {
+                        let linear = Color::new(decode(c[0]), decode(c[1]), decode(c[2]));
+                        mul_matrix(&to_working, linear)
+                    })
+                    .collect()
+            }
+            None => values
+                .chunks(3)
+                .map(|c| Color::new(value(c[0]), value(c[1]), value(c[2])))
+                .collect(),
+        };
+
+        Ok(Self {
+            path: String::from(path),
+            content_hash: hash_bytes(&bytes),
+            width,
+            height,
+            pixels,
+            data: working_space.is_none(),
+        })
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        // Clamp input texture coordinates to [0,1] x [1,0]; v runs up the image.
+        let u = Interval::new(0.0, 1.0).clamp(u);
+        let v = 1.0 - Interval::new(0.0, 1.0).clamp(v);
+
+        if self.data {
+            return self.bilinear(u, v);
+        }
+        let i = ((u * self.width as f64) as i32).min(self.width - 1);
+        let j = ((v * self.height as f64) as i32).min(self.height - 1);
+        self.pixels[(j * self.width + i) as usize]
+    }
+}
+
+impl ImageTexture {
+    fn bilinear(&self, u: f64, v: f64) -> Color {
+        // Interpolates between the centers of the four nearest pixels.
+        let x = u * self.width as f64 - 0.5;
+        let y = v * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let pixel = |i: f64, j: f64| {
+            let i = (i as i32).clamp(0, self.width - 1);
+            let j = (j as i32).clamp(0, self.height - 1);
+            self.pixels[(j * self.width + i) as usize]
+        };
+        let top = pixel(x0, y0) * (1.0 - fx) + pixel(x0 + 1.0, y0) * fx;
+        let bottom = pixel(x0, y0 + 1.0) * (1.0 - fx) + pixel(x0 + 1.0, y0 + 1.0) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
@@ -0,0 +1,88 @@
+// Tone mapping of linear sRGB film values into the [0,1] display range.
+
+use crate::color::{mul_matrix, ColorSpace, Matrix3};
+use crate::Color;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneMapOperator {
+    Linear,   // Clamp after exposure
+    Reinhard, // Extended Reinhard on luminance, white_point maps to 1
+    Hable,    // Uncharted 2 filmic curve, white_point maps to 1
+    Aces,     // Stephen Hill's fit of the ACES RRT and sRGB ODT
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    pub exposure: f64, // Exposure adjustment in stops (EV), applied before the operator
+    pub white_point: f64, // Smallest linear value mapped to white, for Reinhard and Hable
+}
+
+impl ToneMapping {
+    pub fn default() -> Self {
+        Self {
+            operator: ToneMapOperator::Linear,
+            exposure: 0.0,
+            white_point: 4.0,
+        }
+    }
+
+    pub fn apply(&self, c: Color) -> Color {
+        let c = c * 2f64.powf(self.exposure);
+        match self.operator {
+            ToneMapOperator::Linear => c,
+            ToneMapOperator::Reinhard => {
+                let l = ColorSpace::LinearSrgb.luminance(c);
+                if l <= 0.0 {
+                    return Color::zero();
+                }
+                let white2 = self.white_point * self.white_point;
+                let mapped = l * (1.0 + l / white2) / (1.0 + l);
+                c * (mapped / l)
+            }
+            ToneMapOperator::Hable => {
+                // The exposure bias of 2 is part of the original curve.
+                let white_scale = 1.0 / hable_partial(self.white_point);
+                Color::new(
+                    hable_partial(2.0 * c.x()) * white_scale,
+                    hable_partial(2.0 * c.y()) * white_scale,
+                    hable_partial(2.0 * c.z()) * white_scale,
+                )
+            }
+            ToneMapOperator::Aces => {
+                let v = mul_matrix(&ACES_INPUT_MATRIX, c);
+                let v = Color::new(
+                    rrt_and_odt_fit(v.x()),
+                    rrt_and_odt_fit(v.y()),
+                    rrt_and_odt_fit(v.z()),
+                );
+                mul_matrix(&ACES_OUTPUT_MATRIX, v)
+            }
+        }
+    }
+}
+
+fn hable_partial(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+// Linear sRGB to the ACES RRT input space, with the RRT saturation folded in.
+const ACES_INPUT_MATRIX: Matrix3 = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+
+// ODT output space back to linear sRGB.
+const ACES_OUTPUT_MATRIX: Matrix3 = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+fn rrt_and_odt_fit(v: f64) -> f64 {
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    a / b
+}
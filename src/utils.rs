@@ -1,3 +1,12 @@
+use std::cell::RefCell;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
 pub const INF: f64 = f64::INFINITY;
 pub const PI: f64 = std::f64::consts::PI;
 
@@ -5,12 +14,29 @@ pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
+pub fn seed_random(seed: u64) {
+    // Makes the values returned by random_double on this thread reproducible.
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
 pub fn random_double() -> f64 {
     // Returns a random real in [0,1).
-    rand::random::<f64>()
+    RNG.with(|rng| rng.borrow_mut().gen::<f64>())
 }
 
 pub fn random_double_in(min: f64, max: f64) -> f64 {
     // Returns a random real in [min,max).
     min + (max - min) * random_double()
 }
+
+pub fn hash_double(values: &[f64]) -> f64 {
+    // Returns a real in [0,1) that is a pseudo random function of the values.
+    let mut h: u64 = 0x9e3779b97f4a7c15;
+    for value in values {
+        h = (h ^ value.to_bits()).wrapping_mul(0xbf58476d1ce4e5b9);
+        h ^= h >> 31;
+    }
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^= h >> 31;
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
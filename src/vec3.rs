@@ -42,32 +42,35 @@ impl Vec3 {
         )
     }
 
-    // pub fn random_vec2() -> Self {
-    //     let a = utils::random_double_in(0.0, 2.0 * utils::PI);
-    //     let r = utils::random_double_in(0.0, 1.0);
-    //     Self::new(r * a.cos(), r * a.sin(), 0.0)
-    // }
-
-    pub fn random_vec2() -> Self {
-        loop {
-            let p = Self::new(
-                utils::random_double_in(-1.0, 1.0),
-                utils::random_double_in(-1.0, 1.0),
-                0.0,
-            );
-            if p.length() < 1.0 {
-                return p;
-            }
-        }
+    #[allow(dead_code)]
+    pub fn random_unit() -> Self {
+        Self::unit_from_2d((utils::random_double(), utils::random_double()))
     }
 
-    pub fn random_unit() -> Self {
-        let a = utils::random_double_in(0.0, 2.0 * utils::PI);
-        let z = utils::random_double_in(-1.0, 1.0);
+    pub fn unit_from_2d(u: (f64, f64)) -> Self {
+        // Maps a point of [0,1)^2 to a uniformly distributed unit vector.
+        let a = 2.0 * utils::PI * u.0;
+        let z = 1.0 - 2.0 * u.1;
         let r = (1.0 - z * z).sqrt();
         Self::new(r * a.cos(), r * a.sin(), z)
     }
 
+    pub fn disk_from_2d(u: (f64, f64)) -> Self {
+        // Maps a point of [0,1)^2 to the unit disk with Shirley's concentric mapping, which
+        // keeps strata of the square compact on the disk.
+        let ox = 2.0 * u.0 - 1.0;
+        let oy = 2.0 * u.1 - 1.0;
+        if ox == 0.0 && oy == 0.0 {
+            return Self::zero();
+        }
+        let (r, theta) = if ox.abs() > oy.abs() {
+            (ox, utils::PI / 4.0 * (oy / ox))
+        } else {
+            (oy, utils::PI / 2.0 - utils::PI / 4.0 * (ox / oy))
+        };
+        Self::new(r * theta.cos(), r * theta.sin(), 0.0)
+    }
+
     // This function is slower.
     // pub fn random_unit() -> Self {
     //     loop {
@@ -78,6 +81,7 @@ impl Vec3 {
     //     }
     // }
 
+    #[allow(dead_code)]
     pub fn random_on_hemi(normal: &Self) -> Self {
         let rand_in_unit = Self::random_unit();
         if rand_in_unit.dot(normal) > 0.0 {
@@ -140,6 +144,18 @@ impl Vec3 {
     }
 }
 
+impl std::ops::Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &f64 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            _ => &self.z,
+        }
+    }
+}
+
 impl std::ops::Add<Vec3> for Vec3 {
     type Output = Self;
 
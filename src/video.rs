@@ -0,0 +1,79 @@
+// Frame sequences encoded into one animated file, written once every frame is rendered.
+
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::gif::{self, Quantizer};
+use crate::y4m;
+
+#[derive(Debug, Clone)]
+pub struct VideoSettings {
+    pub path: String,         // The extension picks the format: .gif or .y4m
+    pub quantizer: Quantizer, // How the palette of GIF output is chosen
+    pub dither: bool,         // Dither GIF output to its palette
+}
+
+impl VideoSettings {
+    pub fn default() -> Self {
+        Self {
+            path: String::new(),
+            quantizer: Quantizer::MedianCut,
+            dither: false,
+        }
+    }
+
+    pub fn is_supported(path: &str) -> bool {
+        matches!(
+            Path::new(path).extension().and_then(|e| e.to_str()),
+            Some("gif" | "y4m")
+        )
+    }
+}
+
+pub struct Video {
+    pub settings: VideoSettings,
+    pub width: i32,
+    pub height: i32,
+    pub frames: Vec<Vec<[u8; 3]>>, // 8-bit display values of each frame
+}
+
+impl Video {
+    pub fn new(settings: VideoSettings, width: i32, height: i32) -> Self {
+        Self {
+            settings,
+            width,
+            height,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn add_frame(&mut self, pixels: Vec<[u8; 3]>) {
+        self.frames.push(pixels);
+    }
+
+    pub fn write(&self, fps: f64) -> Result<(), Error> {
+        let path = &self.settings.path;
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("gif") => gif::write_gif(
+                path,
+                self.width,
+                self.height,
+                &self.frames,
+                fps,
+                self.settings.quantizer,
+                self.settings.dither,
+            )?,
+            Some("y4m") => y4m::write_y4m(path, self.width, self.height, &self.frames, fps)?,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unsupported video format: {path}"),
+                ))
+            }
+        }
+
+        // Log
+        println!("Video saved");
+        Ok(())
+    }
+}
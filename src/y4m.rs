@@ -0,0 +1,115 @@
+// YUV4MPEG2 (Y4M) writer: uncompressed video that ffmpeg, mpv and most encoders read.
+//
+// Frames are stored as BT.709 Y'CbCr in limited range with the chroma averaged over 2x2
+// pixels (4:2:0), converted from the 8-bit display values.
+
+use std::fs;
+
+fn frame_rate(fps: f64) -> (u64, u64) {
+    // A ratio of integers, exact to a thousandth of a frame per second.
+    let (a, b) = ((fps * 1000.0).round() as u64, 1000);
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    (a / x, b / x)
+}
+
+fn luma(rgb: [f64; 3]) -> f64 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+pub fn write_y4m(
+    path: &str,
+    width: i32,
+    height: i32,
+    frames: &[Vec<[u8; 3]>],
+    fps: f64,
+) -> Result<(), std::io::Error> {
+    let (width, height) = (width as usize, height as usize);
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    let (numerator, denominator) = frame_rate(fps);
+    let mut bytes = format!(
+        "YUV4MPEG2 W{width} H{height} F{numerator}:{denominator} Ip A1:1 C420jpeg XCOLORRANGE=LIMITED\n"
+    )
+    .into_bytes();
+    let to_byte = |value: f64| value.round().clamp(0.0, 255.0) as u8;
+    for pixels in frames {
+        let rgb = |i: usize, j: usize| pixels[j * width + i].map(|c| c as f64 / 255.0);
+        bytes.extend_from_slice(b"FRAME\n");
+        for j in 0..height {
+            for i in 0..width {
+                bytes.push(to_byte(16.0 + 219.0 * luma(rgb(i, j))));
+            }
+        }
+
+        // Mean color of each 2x2 block, which is a single pixel or two at odd edges.
+        let mut cb = Vec::with_capacity(chroma_width * chroma_height);
+        let mut cr = Vec::with_capacity(chroma_width * chroma_height);
+        for j in 0..chroma_height {
+            for i in 0..chroma_width {
+                let mut sum = [0.0; 3];
+                let mut count = 0.0;
+                for y in 2 * j..(2 * j + 2).min(height) {
+                    for x in 2 * i..(2 * i + 2).min(width) {
+                        let c = rgb(x, y);
+                        for k in 0..3 {
+                            sum[k] += c[k];
+                        }
+                        count += 1.0;
+                    }
+                }
+                let c = sum.map(|s| s / count);
+                let y = luma(c);
+                cb.push(to_byte(128.0 + 224.0 * (c[2] - y) / 1.8556));
+                cr.push(to_byte(128.0 + 224.0 * (c[0] - y) / 1.5748));
+            }
+        }
+        bytes.extend_from_slice(&cb);
+        bytes.extend_from_slice(&cr);
+    }
+    fs::write(path, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_rates() {
+        assert_eq!(frame_rate(24.0), (24, 1));
+        assert_eq!(frame_rate(25.5), (51, 2));
+        assert_eq!(frame_rate(29.97), (2997, 100));
+        assert_eq!(frame_rate(23.976), (2997, 125));
+    }
+
+    #[test]
+    fn frames_are_limited_range_420() {
+        // A 3x3 white frame with a red corner, whose chroma blocks at the odd edges cover
+        // fewer pixels, then a black frame.
+        let mut white = vec![[255, 255, 255]; 9];
+        white[8] = [255, 0, 0];
+        let frames = [white, vec![[0, 0, 0]; 9]];
+        let path = std::env::temp_dir().join("ray_tracing_y4m_frames.y4m");
+        let path = path.to_str().unwrap();
+        write_y4m(path, 3, 3, &frames, 30.0).unwrap();
+        let bytes = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let header = b"YUV4MPEG2 W3 H3 F30:1 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED\n";
+        assert_eq!(bytes[..header.len()], header[..]);
+        let frame_size = 6 + 9 + 2 * 4;
+        assert_eq!(bytes.len(), header.len() + 2 * frame_size);
+
+        let first = &bytes[header.len()..header.len() + frame_size];
+        assert_eq!(first[..6], *b"FRAME\n");
+        assert_eq!(first[6..15], [235, 235, 235, 235, 235, 235, 235, 235, 63]);
+        assert_eq!(first[15..19], [128, 128, 128, 102]); // Cb
+        assert_eq!(first[19..23], [128, 128, 128, 240]); // Cr
+
+        let second = &bytes[header.len() + frame_size..];
+        assert_eq!(second[..6], *b"FRAME\n");
+        assert!(second[6..15].iter().all(|&y| y == 16));
+        assert!(second[15..].iter().all(|&c| c == 128));
+    }
+}